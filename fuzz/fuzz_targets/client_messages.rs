@@ -0,0 +1,29 @@
+//! decoding the messages a client sends, in both encodings
+#![no_main]
+
+use std::thread;
+
+use libfuzzer_sys::fuzz_target;
+use roosty_clockd::{ClientMessage, encoding::Encoding};
+
+fuzz_target!(|data: &[u8]| {
+    // on a stack as big as the daemons client threads, the fuzzers own is much bigger and would
+    // hide messages that overflow it
+    thread::scope(|scope| {
+        thread::Builder::new()
+            .stack_size(roosty_clockd::CLIENT_STACK_SIZE)
+            .spawn_scoped(scope, || {
+                for encoding in [Encoding::Bitcode, Encoding::Json] {
+                    // a single message
+                    let _ = encoding.decode::<ClientMessage>(data);
+                    // and a stream of them, like the daemon reads
+                    let mut r = data;
+                    let mut buf = Vec::new();
+                    while encoding.recieve::<ClientMessage>(&mut r, &mut buf).is_ok() {}
+                }
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+    });
+});
@@ -0,0 +1,13 @@
+//! reading length prefixed frames from whatever a client sends
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+fuzz_target!(|data: &[u8]| {
+    let mut r = data;
+    let mut buf = Vec::new();
+    while roosty_clockd::read(&mut r, &mut buf).is_ok() {
+        assert!(buf.len() <= roosty_clockd::MAX_MESSAGE_SIZE);
+        buf.clear();
+    }
+});
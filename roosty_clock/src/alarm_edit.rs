@@ -1,13 +1,17 @@
 use std::{collections::HashMap, ffi::OsStr, iter, path::Path};
 
-use chrono::NaiveTime;
-use eframe::egui::{self, DragValue, Id, ScrollArea, Widget, Window};
-use interprocess::local_socket::SendHalf;
-use roosty_clockd::config;
+use chrono::{Local, NaiveTime};
+use eframe::egui::{self, Color32, DragValue, Id, ScrollArea, Widget, Window};
+use roosty_clockd::{
+    config::{self, SoundMode},
+    cron,
+};
 
 use crate::{
-    AlarmBuilder, TimeOfDay, send_to_server,
-    widgets::{Knob, Value},
+    AlarmBuilder, TimeOfDay,
+    connection::Connection,
+    preview::Preview,
+    widgets::{DatePicker, Knob, Value},
 };
 
 impl AlarmBuilder {
@@ -34,8 +38,16 @@ impl AlarmBuilder {
             )
             .unwrap(),
             sound: self.sound,
+            playlist: self.playlist,
+            sound_mode: self.sound_mode,
             volume: self.volume,
-            enabled: true,
+            enabled: self.enabled,
+            start: self.start,
+            end: self.end,
+            date: self.date,
+            skip: self.skip,
+            exceptions: self.exceptions,
+            cron: Some(self.cron.trim().to_string()).filter(|cron| !cron.is_empty()),
             id: self.id,
         }
     }
@@ -44,18 +56,27 @@ impl AlarmBuilder {
         &mut self,
         ui: &mut egui::Ui,
         sounds: &HashMap<String, roosty_clockd::config::Sound>,
-        sender: &mut SendHalf,
+        connection: &mut Connection,
         max_volume: f32,
+        preview: &mut Preview,
+        holidays: &[config::Exception],
     ) {
         ui.horizontal(|ui| {
             ui.label("Alarm Name");
             ui.text_edit_singleline(&mut self.name);
         });
         ui.horizontal(|ui| {
-            self.render_time_editor(ui);
+            ui.vertical(|ui| {
+                // a cron expression replaces the time
+                ui.add_enabled_ui(self.cron.trim().is_empty(), |ui| {
+                    self.render_time_editor(ui);
+                });
+                self.render_date_editor(ui);
+                self.render_cron_editor(ui, holidays);
+            });
             // // sound editor
             // // ui.separator();
-            self.render_sound_editor(ui, sounds, sender, max_volume);
+            self.render_sound_editor(ui, sounds, connection, max_volume, preview);
         });
     }
 
@@ -73,6 +94,47 @@ impl AlarmBuilder {
             self.render_am_pm_selector(ui);
         });
     }
+    /// on which days the alarm rings, a one-shot date hides the start and end
+    pub(crate) fn render_date_editor(&mut self, ui: &mut egui::Ui) {
+        ui.vertical(|ui| {
+            ui.add(DatePicker::new(&mut self.date, "only on"));
+            if self.date.is_some() {
+                // a one-off alarm has no range of days
+                self.start = None;
+                self.end = None;
+            } else {
+                ui.add(DatePicker::new(&mut self.start, "from"));
+                ui.add(DatePicker::new(&mut self.end, "until"));
+            }
+        });
+    }
+
+    /// a cron expression for power users, with the next times it matches or why it doesn't parse
+    pub(crate) fn render_cron_editor(&mut self, ui: &mut egui::Ui, holidays: &[config::Exception]) {
+        ui.horizontal(|ui| {
+            ui.label("cron");
+            ui.text_edit_singleline(&mut self.cron)
+                .on_hover_text("like 0 */2 * * 1-5 or 0 7 * * 5L (the last friday of the month)");
+        });
+        if self.cron.trim().is_empty() {
+            return;
+        }
+        if let Err(e) = self.cron.parse::<cron::Schedule>() {
+            ui.colored_label(Color32::LIGHT_RED, e.to_string());
+            return;
+        }
+        let upcoming = self
+            .clone()
+            .build()
+            .upcoming(Local::now().naive_local(), holidays, 5);
+        if upcoming.is_empty() {
+            ui.label("won't ring again");
+        }
+        for time in upcoming {
+            ui.label(time.format("%a %Y-%m-%d %H:%M").to_string());
+        }
+    }
+
     pub(crate) fn render_am_pm_selector(&mut self, ui: &mut egui::Ui) {
         ui.horizontal(|ui| {
             ui.add_space(15.0);
@@ -124,31 +186,62 @@ impl AlarmBuilder {
         &mut self,
         ui: &mut egui::Ui,
         sounds: &HashMap<String, roosty_clockd::config::Sound>,
-        sender: &mut SendHalf,
+        connection: &mut Connection,
         max_volume: f32,
+        preview: &mut Preview,
     ) {
-        Self::render_sound_selector_editor(&mut self.sound, ui, sounds, sender);
+        ui.vertical(|ui| {
+            self.render_sound_mode_selector(ui);
+            let playlist = (self.sound_mode != SoundMode::Single).then_some(&mut self.playlist);
+            Self::render_sound_selector_editor(
+                &mut self.sound,
+                playlist,
+                ui,
+                sounds,
+                connection,
+                preview,
+                self.volume,
+            );
+        });
         self.render_volume_slider(ui, max_volume);
+        preview.set_volume(self.volume);
     }
 
+    pub(crate) fn render_sound_mode_selector(&mut self, ui: &mut egui::Ui) {
+        ui.horizontal(|ui| {
+            for (mode, text) in [
+                (SoundMode::Single, "single"),
+                (SoundMode::Playlist, "playlist"),
+                (SoundMode::Shuffle, "shuffle"),
+                (SoundMode::Daily, "random daily"),
+            ] {
+                ui.selectable_value(&mut self.sound_mode, mode, text);
+            }
+        });
+    }
+
+    /// if a playlist is given multiple sounds can be selected, otherwise only one
     pub(crate) fn render_sound_selector_editor(
         sound: &mut String,
+        playlist: Option<&mut Vec<String>>,
         ui: &mut egui::Ui,
         sounds: &HashMap<String, roosty_clockd::config::Sound>,
-        sender: &mut SendHalf,
+        connection: &mut Connection,
+        preview: &mut Preview,
+        volume: f32,
     ) {
         ui.vertical(|ui| {
             // alarm sound
-            Self::render_alarm_sound_selector(sound, ui, sounds);
+            Self::render_alarm_sound_selector(sound, playlist, ui, sounds, preview, volume);
             // set custom alarm sound stuff
-            Self::render_custom_alarm_sound_editor(sounds, ui, sender);
+            Self::render_custom_alarm_sound_editor(sounds, ui, connection);
         });
     }
 
     fn render_custom_alarm_sound_editor(
         _sounds: &HashMap<String, roosty_clockd::config::Sound>,
         ui: &mut egui::Ui,
-        sender: &mut SendHalf,
+        connection: &mut Connection,
     ) {
         if ui.button("Custom").clicked() {
             // TODO: rfd with gnome opens Recents not audio folder https://github.com/PolyMeilex/rfd/issues/237
@@ -164,40 +257,58 @@ impl AlarmBuilder {
 
             // when done in alarm editor which one do we pick if we have multiple alarms
             if let Some(paths) = { file_dialog }.pick_files() {
-                send_to_server(
-                    sender,
-                    roosty_clockd::ClientMessage::AddedSounds(
-                        paths
-                            .iter()
-                            .filter_map(|path_name| {
-                                path_name
-                                    .file_prefix()
-                                    .and_then(OsStr::to_str)
-                                    .map(|path| (path_name, path))
-                            })
-                            .map(|(path_name, name)| roosty_clockd::config::Sound {
-                                name: name.to_string(),
-                                path: path_name.clone(),
-                            })
-                            .collect(),
-                    ),
-                );
+                connection.send(roosty_clockd::ClientMessage::AddedSounds(
+                    paths
+                        .iter()
+                        .filter_map(|path_name| {
+                            path_name
+                                .file_prefix()
+                                .and_then(OsStr::to_str)
+                                .map(|path| (path_name, path))
+                        })
+                        .map(|(path_name, name)| {
+                            roosty_clockd::config::Sound::new(name.to_string(), path_name.clone())
+                        })
+                        .collect(),
+                ));
             }
         }
     }
 
     pub(crate) fn render_alarm_sound_selector(
         sound: &mut String,
+        mut playlist: Option<&mut Vec<String>>,
         ui: &mut egui::Ui,
         sounds: &HashMap<String, roosty_clockd::config::Sound>,
+        preview: &mut Preview,
+        volume: f32,
     ) {
         ui.vertical(|ui| {
             // set size of alarm selector so it doesnt make alarm creation to big when using cutom alarm
             // pick an alarm sound
             // TODO: make something that automates this
             ScrollArea::vertical().id_salt("alarm").show(ui, |ui| {
-                for name in sounds.keys() {
-                    ui.selectable_value(sound, name.clone(), name);
+                for (name, sound_config) in sounds {
+                    ui.horizontal(|ui| {
+                        preview.render_button(ui, sound_config, volume);
+                        if let Some(playlist) = playlist.as_deref_mut() {
+                            // sounds are played in the order they were selected in
+                            let position = playlist.iter().position(|s| s == name);
+                            let text = position.map_or_else(
+                                || name.clone(),
+                                |position| format!("{}. {name}", position + 1),
+                            );
+                            if ui.selectable_label(position.is_some(), text).clicked() {
+                                if let Some(position) = position {
+                                    playlist.remove(position);
+                                } else {
+                                    playlist.push(name.clone());
+                                }
+                            }
+                        } else {
+                            ui.selectable_value(sound, name.clone(), name);
+                        }
+                    });
                 }
             });
         });
@@ -217,8 +328,10 @@ impl AlarmBuilder {
         &mut self,
         ctx: &egui::Context,
         sounds: &HashMap<String, roosty_clockd::config::Sound>,
-        sender: &mut SendHalf,
+        connection: &mut Connection,
         max_volume: f32,
+        preview: &mut Preview,
+        holidays: &[config::Exception],
     ) -> EditingState {
         let mut ret = EditingState::Editing;
         // if no alarm name set we need way to differentiate between different alarms
@@ -226,7 +339,7 @@ impl AlarmBuilder {
             .id(Id::new(self.id))
             .collapsible(false)
             .show(ctx, |ui| {
-                self.edit_alarm(ui, sounds, sender, max_volume);
+                self.edit_alarm(ui, sounds, connection, max_volume, preview, holidays);
                 ui.horizontal(|ui| {
                     if ui.button("done").clicked() {
                         ret = EditingState::Done(self.clone().build());
@@ -236,6 +349,9 @@ impl AlarmBuilder {
                         ret = EditingState::Editing;
                     }
                 });
+                if !matches!(ret, EditingState::Editing) {
+                    preview.stop();
+                }
             });
         ret
     }
@@ -0,0 +1,131 @@
+use std::{fs, path::PathBuf, time::Duration};
+
+use eframe::egui::{self, Button, ScrollArea, Window};
+use roosty_clockd::{
+    ImportResult,
+    client::{Client, Pending},
+    ics,
+};
+
+use crate::connection::Connection;
+
+/// The calendar menu, for importing alarms from and exporting them to `.ics` files (the daemon
+/// does the converting).
+#[derive(Default)]
+pub struct Calendar {
+    // the file the calendar the daemon is making goes in
+    exporting: Option<(PathBuf, Pending<String>)>,
+    importing: Option<Pending<ImportResult>>,
+    // how the last import or export went, and what was left out, until its window is closed
+    report: Option<(String, Vec<ics::Warning>)>,
+}
+
+impl Calendar {
+    pub fn menu(&mut self, ui: &mut egui::Ui, connection: &mut Connection) {
+        let idle =
+            connection.is_connected() && self.exporting.is_none() && self.importing.is_none();
+        if ui
+            .add_enabled(idle, Button::new("import calendar…"))
+            .on_hover_text("add an alarm for each reminder in an .ics file")
+            .clicked()
+        {
+            ui.close();
+            self.import(connection);
+        }
+        if ui
+            .add_enabled(idle, Button::new("export calendar…"))
+            .on_hover_text("save the alarms as an .ics file for calendar apps")
+            .clicked()
+        {
+            ui.close();
+            self.export(connection);
+        }
+    }
+
+    fn dialog() -> rfd::FileDialog {
+        rfd::FileDialog::new().add_filter("iCalendar", &["ics"])
+    }
+
+    fn import(&mut self, connection: &mut Connection) {
+        let Some(path) = Self::dialog().set_title("Import alarms").pick_file() else {
+            return;
+        };
+        match fs::read_to_string(&path) {
+            Ok(calendar) => {
+                self.importing = connection.request(|client| client.import_calendar(calendar));
+            }
+            Err(e) => {
+                self.report = Some((format!("couldn't read {}: {e}", path.display()), Vec::new()));
+            }
+        }
+    }
+
+    fn export(&mut self, connection: &mut Connection) {
+        let Some(path) = Self::dialog()
+            .set_title("Export alarms")
+            .set_file_name("alarms.ics")
+            .save_file()
+        else {
+            return;
+        };
+        self.exporting = connection
+            .request(Client::export_calendar)
+            .map(|pending| (path, pending));
+    }
+
+    /// Takes the daemons answers if they came.
+    fn poll(&mut self, ctx: &egui::Context) {
+        if let Some((path, pending)) = &mut self.exporting
+            && let Some(calendar) = pending.try_answer()
+        {
+            let status = match calendar.map(|calendar| fs::write(&*path, calendar)) {
+                Ok(Ok(())) => format!("exported the alarms to {}", path.display()),
+                Ok(Err(e)) => format!("couldn't write {}: {e}", path.display()),
+                Err(e) => format!("couldn't export the alarms: {e}"),
+            };
+            self.report = Some((status, Vec::new()));
+            self.exporting = None;
+        }
+        if let Some(pending) = &mut self.importing
+            && let Some(imported) = pending.try_answer()
+        {
+            self.report = Some(match imported {
+                Ok(Ok((changes, warnings))) => {
+                    (format!("imported {}", ics::summary(&changes)), warnings)
+                }
+                Ok(Err(e)) => (format!("couldn't import the calendar: {e}"), Vec::new()),
+                Err(e) => (format!("couldn't import the calendar: {e}"), Vec::new()),
+            });
+            self.importing = None;
+        }
+        if self.exporting.is_some() || self.importing.is_some() {
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+    }
+
+    pub fn render(&mut self, ctx: &egui::Context) {
+        self.poll(ctx);
+        let Some((status, warnings)) = &self.report else {
+            return;
+        };
+        let mut open = true;
+        Window::new("calendar 📅")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.label(status);
+                if !warnings.is_empty() {
+                    ui.separator();
+                    ui.label("left out:");
+                    ScrollArea::vertical().id_salt("calendar").show(ui, |ui| {
+                        for warning in warnings {
+                            ui.label(warning.to_string());
+                        }
+                    });
+                }
+            });
+        if !open {
+            self.report = None;
+        }
+    }
+}
@@ -1,11 +1,16 @@
-use std::{collections::HashMap, fmt, ops::Not, path::PathBuf};
+use std::{
+    collections::HashMap,
+    fmt,
+    ops::Not,
+    path::{Path, PathBuf},
+};
 
 use chrono::Timelike;
 use eframe::egui;
-use roosty_clockd::config::{self, Alarm};
+use roosty_clockd::config::{self, Alarm, SoundMode};
 use serde::{Deserialize, Serialize};
 
-use crate::{AlarmBuilder, Clock, TimeOfDay, send_to_server};
+use crate::{AlarmBuilder, Clock, TimeOfDay};
 
 #[derive(Debug, Serialize, Deserialize, Default, Clone, Copy, PartialEq, Eq)]
 pub enum Theme {
@@ -43,6 +48,9 @@ pub struct Config {
     pub(crate) default_sound: String,
 
     pub(crate) max_volume: f32,
+    /// where to find `roosty_clockd` when starting it
+    #[serde(default)]
+    pub(crate) daemon_path: Option<PathBuf>,
 }
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Sounds {
@@ -60,6 +68,7 @@ impl Default for Config {
             // Rain,
             default_sound: "beep beep".to_string(),
             max_volume: 150.,
+            daemon_path: None,
         }
     }
 }
@@ -106,6 +115,11 @@ impl Config {
     pub fn is_config_present() -> bool {
         Self::config_path().exists()
     }
+
+    #[must_use]
+    pub fn daemon_path(&self) -> Option<&Path> {
+        self.daemon_path.as_deref()
+    }
 }
 
 #[inline]
@@ -125,7 +139,16 @@ impl From<config::Alarm> for AlarmBuilder {
             minute: minute as u8,
             time_of_day: if ampm { TimeOfDay::PM } else { TimeOfDay::AM },
             sound: alarm.sound,
+            playlist: alarm.playlist,
+            sound_mode: alarm.sound_mode,
             volume: alarm.volume,
+            enabled: alarm.enabled,
+            start: alarm.start,
+            end: alarm.end,
+            date: alarm.date,
+            skip: alarm.skip,
+            exceptions: alarm.exceptions,
+            cron: alarm.cron.unwrap_or_default(),
             id: alarm.id,
         }
     }
@@ -151,17 +174,36 @@ impl Clock {
                 ui.label(alarm.name.as_ref().unwrap_or(&"alarm".to_string()));
                 // on off button
                 if ui.checkbox(&mut alarm.enabled, "enabled").clicked() {
-                    send_to_server(
-                        &mut self.send,
-                        roosty_clockd::ClientMessage::SetAlarm(
-                            alarm.id,
-                            roosty_clockd::AlarmEdit::Enable(alarm.enabled),
-                        ),
-                    );
+                    self.connection.send(roosty_clockd::ClientMessage::SetAlarm(
+                        alarm.id,
+                        roosty_clockd::AlarmEdit::Enable(alarm.enabled),
+                    ));
                 }
             });
-            ui.label(alarm.time.format(&self.config.time_format).to_string());
-            ui.label(format!("alarm sound: {}", alarm.sound));
+            if let Some(cron) = &alarm.cron {
+                ui.label(format!("cron: {cron}"));
+            } else {
+                ui.label(alarm.time.format(&self.config.time_format).to_string());
+            }
+            match (alarm.date, alarm.start, alarm.end) {
+                (Some(date), ..) => ui.label(format!("only on {date}")),
+                (None, Some(start), Some(end)) => ui.label(format!("from {start} until {end}")),
+                (None, Some(start), None) => ui.label(format!("from {start}")),
+                (None, None, Some(end)) => ui.label(format!("until {end}")),
+                (None, None, None) => ui.label("every day"),
+            };
+            if let Some(skip) = alarm.skip {
+                ui.label(format!("skipping {skip}"));
+            }
+            if alarm.sound_mode == SoundMode::Single || alarm.playlist.is_empty() {
+                ui.label(format!("alarm sound: {}", alarm.sound));
+            } else {
+                ui.label(format!(
+                    "alarm sounds ({:?}): {}",
+                    alarm.sound_mode,
+                    alarm.playlist.join(", ")
+                ));
+            }
             if ui
                 .add(
                     egui::Slider::new(&mut alarm.volume, 0.0..=self.config.max_volume)
@@ -171,13 +213,10 @@ impl Clock {
                 )
                 .changed()
             {
-                send_to_server(
-                    &mut self.send,
-                    roosty_clockd::ClientMessage::SetAlarm(
-                        alarm.id,
-                        roosty_clockd::AlarmEdit::Volume(alarm.volume),
-                    ),
-                );
+                self.connection.send(roosty_clockd::ClientMessage::SetAlarm(
+                    alarm.id,
+                    roosty_clockd::AlarmEdit::Volume(alarm.volume),
+                ));
                 ret = true;
             }
 
@@ -202,6 +241,14 @@ impl Clock {
                     self.alarm_edits
                         .insert(alarm.id, AlarmBuilder::from(alarm.clone()));
                 }
+                if ui
+                    .add_enabled(alarm.enabled, egui::Button::new("skip next"))
+                    .on_hover_text("don't ring the next time, but keep ringing after that")
+                    .clicked()
+                {
+                    self.connection
+                        .send(roosty_clockd::ClientMessage::SkipNext(alarm.id));
+                }
             });
         });
         ret
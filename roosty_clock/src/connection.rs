@@ -0,0 +1,247 @@
+use std::{
+    collections::VecDeque,
+    error::Error,
+    io,
+    time::{Duration, Instant},
+};
+
+use roosty_clockd::{
+    ClientMessage, ServerMessage,
+    client::{self, Client, Pending, Subscription, Target},
+};
+
+/// The connection to `roosty_clockd`.
+///
+/// If the daemon goes away it keeps trying to reconnect (backing off exponentially), and once
+/// it's back it asks for the current state of the daemon with [`ClientMessage::Init`].
+/// Anything sent while disconnected is queued up and sent after that. The same happens when the
+/// daemon says we fell behind ([`ServerMessage::ResyncRequired`]).
+///
+/// Nothing here blocks (except [`Connection::connect`]), answers to requests are polled with
+/// [`Pending::try_answer`] and everything else with [`Connection::recieve`].
+pub struct Connection {
+    target: Target,
+    state: State,
+    // messages sent while disconnected
+    queue: VecDeque<ClientMessage>,
+    backoff: Duration,
+}
+
+enum State {
+    Connected {
+        client: Client,
+        events: Subscription,
+    },
+    /// (re)connected but still waiting for the response to `Init`
+    Syncing {
+        client: Client,
+        events: Subscription,
+        init: Pending<client::State>,
+    },
+    Disconnected {
+        retry_at: Instant,
+    },
+}
+
+impl Connection {
+    const MIN_BACKOFF: Duration = Duration::from_millis(500);
+    const MAX_BACKOFF: Duration = Duration::from_secs(30);
+
+    /// Connects to the daemon and waits for its current state.
+    ///
+    /// # Errors
+    /// If the daemon is not running or didn't answer.
+    pub fn connect(target: Target) -> Result<(Self, client::State), Box<dyn Error>> {
+        let client = Client::connect(&target)?;
+        let events = client.subscribe();
+        let state = client.init()?.wait()?;
+        let connection = Self {
+            target,
+            state: State::Connected { client, events },
+            queue: VecDeque::new(),
+            backoff: Self::MIN_BACKOFF,
+        };
+        Ok((connection, state))
+    }
+
+    #[must_use]
+    pub const fn is_connected(&self) -> bool {
+        matches!(self.state, State::Connected { .. })
+    }
+
+    /// what to show the user when not connected
+    #[must_use]
+    pub fn status(&self) -> Option<String> {
+        match &self.state {
+            State::Connected { .. } => None,
+            State::Syncing { .. } => Some("reconnected to roosty_clockd, syncing".to_string()),
+            State::Disconnected { retry_at } => Some(format!(
+                "disconnected from roosty_clockd, reconnecting in {}s ({} changes waiting)",
+                retry_at.saturating_duration_since(Instant::now()).as_secs(),
+                self.queue.len()
+            )),
+        }
+    }
+
+    /// Sends a message to the daemon, if we are not connected it is sent after reconnecting.
+    pub fn send(&mut self, message: ClientMessage) {
+        if let State::Connected { client, .. } = &self.state {
+            if client.send(&message).is_ok() {
+                return;
+            }
+            self.disconnect();
+        }
+        self.queue.push_back(message);
+    }
+
+    /// Sends a request (like [`Client::new_uid`]), `None` if we are not connected.
+    pub fn request<T>(
+        &mut self,
+        request: impl FnOnce(&Client) -> io::Result<Pending<T>>,
+    ) -> Option<Pending<T>> {
+        let State::Connected { client, .. } = &self.state else {
+            return None;
+        };
+        match request(client) {
+            Ok(pending) => Some(pending),
+            Err(e) => {
+                log::warn!("lost connection to roosty_clockd: {e}");
+                self.disconnect();
+                None
+            }
+        }
+    }
+
+    /// Receives a message from the daemon if there is one, and reconnects if its time to try
+    /// again.
+    pub fn recieve(&mut self) -> Option<ServerMessage> {
+        match &mut self.state {
+            State::Connected { events, .. } => match events.try_next() {
+                Ok(Some(ServerMessage::ResyncRequired)) => {
+                    log::warn!("fell behind roosty_clockd, syncing again");
+                    self.resync();
+                    None
+                }
+                Ok(message) => {
+                    // so we start trying to reconnect right away
+                    if matches!(message, Some(ServerMessage::ShuttingDown)) {
+                        log::info!("roosty_clockd is shutting down");
+                        self.disconnect();
+                    }
+                    message
+                }
+                Err(e) => {
+                    log::warn!("lost connection to roosty_clockd: {e}");
+                    self.disconnect();
+                    None
+                }
+            },
+            State::Syncing { init, .. } => match init.try_answer()? {
+                Ok(client::State {
+                    alarms,
+                    sounds,
+                    ringing_alarms,
+                }) => {
+                    self.synced();
+                    Some(ServerMessage::Init {
+                        alarms,
+                        sounds,
+                        ringing_alarms,
+                    })
+                }
+                Err(e) => {
+                    log::warn!("roosty_clockd didn't send its state: {e}");
+                    self.disconnect();
+                    None
+                }
+            },
+            State::Disconnected { retry_at } if *retry_at <= Instant::now() => {
+                match Client::connect(&self.target) {
+                    Ok(client) => {
+                        log::info!("reconnected to roosty_clockd");
+                        self.sync(client);
+                    }
+                    Err(e) => {
+                        log::warn!("couldn't reconnect to roosty_clockd: {e}");
+                        self.backoff = (self.backoff * 2).min(Self::MAX_BACKOFF);
+                        self.disconnect();
+                    }
+                }
+                None
+            }
+            State::Disconnected { .. } => None,
+        }
+    }
+
+    /// how long until the connection needs to be polled again (for retrying)
+    #[must_use]
+    pub fn poll_after(&self) -> Option<Duration> {
+        match &self.state {
+            State::Connected { .. } => None,
+            State::Syncing { .. } => Some(Self::MIN_BACKOFF),
+            State::Disconnected { retry_at } => {
+                Some(retry_at.saturating_duration_since(Instant::now()))
+            }
+        }
+    }
+
+    fn disconnect(&mut self) {
+        self.state = State::Disconnected {
+            retry_at: Instant::now() + self.backoff,
+        };
+    }
+
+    fn sync(&mut self, client: Client) {
+        // subscribe first so nothing after the state is missed
+        let events = client.subscribe();
+        match client.init() {
+            Ok(init) => {
+                self.state = State::Syncing {
+                    client,
+                    events,
+                    init,
+                };
+            }
+            Err(_) => self.disconnect(),
+        }
+    }
+
+    /// gets the current state again, without reconnecting
+    fn resync(&mut self) {
+        let state = std::mem::replace(
+            &mut self.state,
+            State::Disconnected {
+                retry_at: Instant::now(),
+            },
+        );
+        if let State::Connected { client, events } = state {
+            match client.init() {
+                Ok(init) => {
+                    self.state = State::Syncing {
+                        client,
+                        events,
+                        init,
+                    };
+                }
+                Err(_) => self.disconnect(),
+            }
+        }
+    }
+
+    fn synced(&mut self) {
+        let state = std::mem::replace(
+            &mut self.state,
+            State::Disconnected {
+                retry_at: Instant::now(),
+            },
+        );
+        if let State::Syncing { client, events, .. } = state {
+            self.state = State::Connected { client, events };
+            self.backoff = Self::MIN_BACKOFF;
+            let queue = std::mem::take(&mut self.queue);
+            for message in queue {
+                self.send(message);
+            }
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use std::{
+    env,
+    error::Error,
+    io,
+    path::{Path, PathBuf},
+    process::{Command, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+use roosty_clockd::{
+    client::{self, Target},
+    socket::Address,
+};
+
+use crate::connection::Connection;
+
+/// how long to wait for a daemon we started to start listening
+pub const SPAWN_TIMEOUT: Duration = Duration::from_secs(5);
+
+const DAEMON_NAME: &str = "roosty_clockd";
+
+/// Connects to `roosty_clockd` (see [`Connection::connect`]), starting it first if it isn't
+/// running and `spawn` is set (only a local one can be started).
+///
+/// # Errors
+/// If the daemon is not running and could not be started (or `spawn` is not set).
+pub fn connect(
+    target: &Target,
+    spawn: bool,
+    configured: Option<&Path>,
+) -> Result<(Connection, client::State), Box<dyn Error>> {
+    let e = match Connection::connect(target.clone()) {
+        Ok(connection) => return Ok(connection),
+        Err(e) => e,
+    };
+    let Target::Local(address) = target else {
+        return Err(format!("couldn't connect to {DAEMON_NAME} on {target}: {e}").into());
+    };
+    if !spawn {
+        return Err(e);
+    }
+    log::info!("couldn't connect to {DAEMON_NAME} ({e}), starting it");
+    let path = find(configured).ok_or_else(|| {
+        format!("{DAEMON_NAME} is not running and couldn't find it to start it: {e}")
+    })?;
+    start(&path, address).map_err(|e| format!("couldn't start {}: {e}", path.display()))?;
+
+    let started = Instant::now();
+    loop {
+        match Connection::connect(target.clone()) {
+            Ok(connection) => return Ok(connection),
+            Err(e) if started.elapsed() > SPAWN_TIMEOUT => {
+                return Err(
+                    format!("started {} but couldn't connect to it: {e}", path.display()).into(),
+                );
+            }
+            Err(_) => thread::sleep(Duration::from_millis(100)),
+        }
+    }
+}
+
+/// Finds the daemon binary, checking (in order) the configured path, next to this executable,
+/// and `PATH`.
+#[must_use]
+pub fn find(configured: Option<&Path>) -> Option<PathBuf> {
+    let name = format!("{DAEMON_NAME}{}", env::consts::EXE_SUFFIX);
+    let next_to_us = env::current_exe()
+        .ok()
+        .and_then(|exe| exe.parent().map(|dir| dir.join(&name)));
+    let on_path = env::var_os("PATH")
+        .into_iter()
+        .flat_map(|path| env::split_paths(&path).collect::<Vec<_>>())
+        .map(|dir| dir.join(&name));
+    configured
+        .map(Path::to_path_buf)
+        .into_iter()
+        .chain(next_to_us)
+        .chain(on_path)
+        .find(|path| path.is_file())
+}
+
+/// Starts the daemon in the background listening on `address`, so it keeps running after we exit.
+///
+/// # Errors
+/// If the daemon could not be started.
+pub fn start(path: &Path, address: &Address) -> io::Result<()> {
+    let mut command = Command::new(path);
+    command
+        .arg("--socket")
+        .arg(address.to_string())
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    #[cfg(unix)]
+    {
+        use std::os::unix::process::CommandExt;
+        // so it doesn't get our signals (like ctrl-c in a terminal)
+        command.process_group(0);
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        const DETACHED_PROCESS: u32 = 0x0000_0008;
+        command.creation_flags(DETACHED_PROCESS);
+    }
+    command.spawn().map(drop)
+}
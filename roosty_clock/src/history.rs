@@ -0,0 +1,157 @@
+use std::{collections::HashMap, time::Duration};
+
+use chrono::{Local, TimeDelta};
+use eframe::egui::{self, ComboBox, Grid, ScrollArea, Window};
+use roosty_clockd::{
+    client::Pending,
+    config::Alarm,
+    history::{Entry, Stats},
+};
+
+use crate::connection::Connection;
+
+/// The window showing when alarms rang and how long it took to stop them.
+pub struct History {
+    pub open: bool,
+    /// how far back to look, everything if `None`
+    days: Option<i64>,
+    // `None` until the daemon answers
+    entries: Option<Vec<Entry>>,
+    pending: Option<Pending<Vec<Entry>>>,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        Self {
+            open: false,
+            days: Some(30),
+            entries: None,
+            pending: None,
+        }
+    }
+}
+
+impl History {
+    /// the most recent entries that are listed
+    const RECENT: usize = 100;
+
+    /// Opens the window and asks the daemon for the history.
+    pub fn show(&mut self, connection: &mut Connection) {
+        self.open = true;
+        self.refresh(connection);
+    }
+
+    pub fn refresh(&mut self, connection: &mut Connection) {
+        self.entries = None;
+        let since = self.days.map(|days| Local::now() - TimeDelta::days(days));
+        self.pending = connection.request(|client| client.history(since, None, None));
+    }
+
+    /// Takes the daemons answer if it came.
+    fn poll(&mut self, ctx: &egui::Context) {
+        let Some(pending) = &mut self.pending else {
+            return;
+        };
+        match pending.try_answer() {
+            Some(Ok(entries)) => self.entries = Some(entries),
+            Some(Err(e)) => log::warn!("couldn't get the history: {e}"),
+            None => {
+                ctx.request_repaint_after(Duration::from_millis(50));
+                return;
+            }
+        }
+        self.pending = None;
+    }
+
+    pub fn render(
+        &mut self,
+        ctx: &egui::Context,
+        alarms: &HashMap<u64, Alarm>,
+        connection: &mut Connection,
+    ) {
+        self.poll(ctx);
+        let mut open = self.open;
+        Window::new("history 📜")
+            .open(&mut open)
+            .collapsible(false)
+            .show(ctx, |ui| {
+                ui.horizontal(|ui| {
+                    let days = self.days;
+                    ComboBox::from_id_salt("history_range")
+                        .selected_text(Self::range_name(self.days))
+                        .show_ui(ui, |ui| {
+                            for range in [Some(7), Some(30), Some(365), None] {
+                                ui.selectable_value(&mut self.days, range, Self::range_name(range));
+                            }
+                        });
+                    if ui.button("⟳").on_hover_text("refresh").clicked() || days != self.days {
+                        self.refresh(connection);
+                    }
+                });
+                let Some(entries) = &self.entries else {
+                    ui.spinner();
+                    return;
+                };
+                let name = |id: &u64| {
+                    alarms.get(id).map_or_else(
+                        || format!("removed alarm {id}"),
+                        |alarm| alarm.name.clone().unwrap_or_else(|| "alarm".to_string()),
+                    )
+                };
+
+                ui.heading("alarms");
+                let mut stats: Vec<_> = Stats::of(entries).into_iter().collect();
+                stats.sort_by_key(|(id, _)| *id);
+                Grid::new("history_stats").striped(true).show(ui, |ui| {
+                    ui.label("alarm");
+                    ui.label("rang");
+                    ui.label("stopped");
+                    ui.label("ignored");
+                    ui.label("average time to stop");
+                    ui.end_row();
+                    for (id, stats) in stats.iter().filter(|(_, stats)| stats.rang > 0) {
+                        ui.label(name(id));
+                        ui.label(stats.rang.to_string());
+                        ui.label(stats.dismissed.to_string());
+                        ui.label(stats.ignored.to_string());
+                        ui.label(
+                            stats
+                                .average_dismiss
+                                .map_or_else(|| "-".to_string(), format_duration),
+                        );
+                        ui.end_row();
+                    }
+                });
+
+                ui.separator();
+                ui.heading("recent");
+                ScrollArea::vertical().id_salt("history").show(ui, |ui| {
+                    Grid::new("history_entries").striped(true).show(ui, |ui| {
+                        for entry in entries.iter().rev().take(Self::RECENT) {
+                            ui.label(entry.time.format("%Y-%m-%d %H:%M:%S").to_string());
+                            ui.label(name(&entry.alarm));
+                            ui.label(entry.event.to_string());
+                            ui.end_row();
+                        }
+                    });
+                });
+            });
+        self.open = open;
+    }
+
+    fn range_name(days: Option<i64>) -> String {
+        days.map_or_else(
+            || "all time".to_string(),
+            |days| format!("last {days} days"),
+        )
+    }
+}
+
+fn format_duration(duration: TimeDelta) -> String {
+    let seconds = duration.num_seconds();
+    if seconds < 60 {
+        format!("{seconds}s")
+    } else {
+        format!("{}m {}s", seconds / 60, seconds % 60)
+    }
+}
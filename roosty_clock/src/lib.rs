@@ -3,29 +3,43 @@
 #![allow(clippy::multiple_crate_versions, clippy::module_name_repetitions)]
 
 use std::{
-    collections::HashMap,
-    io::{BufReader, ErrorKind},
+    collections::{HashMap, HashSet},
     mem,
+    time::Duration,
 };
 
 use alarm_edit::EditingState;
-use chrono::Timelike;
+use chrono::{NaiveDate, Timelike};
 use config::{Config, Sound, Theme};
+use connection::Connection;
 use eframe::{
     Frame,
-    egui::{self, Button, CentralPanel, Grid, Id, Layout, ScrollArea, TopBottomPanel, Window},
+    egui::{
+        self, Button, CentralPanel, Color32, ComboBox, Grid, Id, Layout, ScrollArea,
+        TopBottomPanel, Window,
+    },
 };
-use interprocess::local_socket::{RecvHalf, SendHalf};
 
 pub mod config;
 use roosty_clockd::{
-    ServerMessage,
+    AlarmError, BatchResult, Change, Op, ServerMessage, SoundRemoval, SoundRemovalError,
+    client::Pending,
     config::{self as roosty_clockd_config, Alarm},
 };
 
 /// implementation of alarm editing for egui
 pub mod alarm_edit;
+/// importing alarms from and exporting them to calendar files
+pub mod calendar;
 pub mod communication;
+/// the connection to `roosty_clockd`
+pub mod connection;
+/// starting `roosty_clockd` if its not running
+pub mod daemon;
+/// when alarms rang and how long they took to stop
+pub mod history;
+/// playing sounds in the alarm editor
+pub mod preview;
 pub mod widgets;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
@@ -40,36 +54,34 @@ pub struct Clock {
     adding_alarm: Option<AlarmBuilder>,
     alarms: HashMap<u64, roosty_clockd_config::Alarm>,
     sounds: HashMap<String, roosty_clockd_config::Sound>,
-    recv: BufReader<RecvHalf>,
     alarm_edits: HashMap<u64, AlarmBuilder>,
-    send: SendHalf,
+    connection: Connection,
     ringing: HashMap<u64, String>,
+    sound_removal: SoundRemoval,
+    // result of the last sound removal, shown in the settings
+    sound_removal_status: Option<String>,
+    preview: preview::Preview,
+    history: history::History,
+    calendar: calendar::Calendar,
+    // an alarm the daemon is creating, kept around to reopen the editor if it fails
+    creating_alarm: Option<(AlarmBuilder, Pending<Result<Alarm, AlarmError>>)>,
+    // why the last alarm couldn't be created
+    creation_error: Option<String>,
+    // edited alarms the daemon hasn't saved yet
+    saving_alarms: HashMap<u64, SavingAlarm>,
+    // why the last edit couldn't be saved
+    edit_error: Option<String>,
+    // the days no alarm rings on, for showing when alarms ring next
+    holidays: Vec<roosty_clockd_config::Exception>,
 }
 
-pub fn send_to_server(w: &mut SendHalf, message: roosty_clockd::ClientMessage) -> Result<(), ()> {
-    let bytes = bitcode::serialize(&message).map_err(|_| ())?;
-
-    roosty_clockd::write(w, &bytes).map_err(|_| ()).map(|_| ())
+/// Edits to an alarm that are shown already, kept around to undo them if the daemon refuses them.
+struct SavingAlarm {
+    old: Alarm,
+    editing: AlarmBuilder,
+    pending: Pending<BatchResult>,
 }
-pub fn recieve_from_server(
-    conn: &mut BufReader<RecvHalf>,
-    block: bool,
-) -> Result<roosty_clockd::ServerMessage, ()> {
-    let mut bytes = Vec::new();
-    match roosty_clockd::read(conn, &mut bytes) {
-        Ok(()) => {}
-        Err(e) if e.kind() == ErrorKind::WouldBlock && block => {
-            return recieve_from_server(conn, block);
-        }
-        Err(_) => {
-            return Err(());
-        }
-    }
 
-    bitcode::deserialize(&bytes).map_err(|_e| {
-        ();
-    })
-}
 #[derive(Debug, Clone, PartialEq)]
 pub struct AlarmBuilder {
     name: String,
@@ -77,7 +89,18 @@ pub struct AlarmBuilder {
     minute: u8,
     time_of_day: TimeOfDay,
     sound: String,
+    playlist: Vec<String>,
+    sound_mode: roosty_clockd_config::SoundMode,
     volume: f32,
+    enabled: bool,
+    start: Option<NaiveDate>,
+    end: Option<NaiveDate>,
+    date: Option<NaiveDate>,
+    // rings when this matches instead of at the time, unless it's empty
+    cron: String,
+    // not edited here, but kept so saving the alarm doesn't clear them
+    skip: Option<NaiveDate>,
+    exceptions: Vec<roosty_clockd_config::Exception>,
     id: u64,
 }
 
@@ -92,8 +115,18 @@ impl Default for AlarmBuilder {
             minute: minute as u8,
             time_of_day: if ampm { TimeOfDay::PM } else { TimeOfDay::AM },
             sound: Sound::get_default_name(),
+            playlist: Vec::new(),
+            sound_mode: roosty_clockd_config::SoundMode::default(),
             volume: 100.0,
-            id: 0,
+            enabled: true,
+            start: None,
+            end: None,
+            date: None,
+            cron: String::new(),
+            skip: None,
+            exceptions: Vec::new(),
+            // new alarms get their id from the daemon
+            id: u64::MAX,
         }
     }
 }
@@ -101,22 +134,32 @@ impl Default for AlarmBuilder {
 impl Clock {
     #[must_use]
     pub fn new(
-        send: SendHalf,
-        recv: BufReader<RecvHalf>,
+        mut connection: Connection,
         sounds: HashMap<String, roosty_clockd_config::Sound>,
         alarms: HashMap<u64, roosty_clockd_config::Alarm>,
         ringing: HashMap<u64, String>,
     ) -> Self {
+        // the answer comes in like any other message
+        connection.send(roosty_clockd::ClientMessage::GetExceptions);
         Self {
             alarm_edits: HashMap::new(),
             config: Config::load(Config::config_path()),
             sounds,
             alarms,
-            send,
-            recv,
+            connection,
             in_config: false,
             adding_alarm: None,
             ringing,
+            sound_removal: SoundRemoval::default(),
+            sound_removal_status: None,
+            preview: preview::Preview::default(),
+            history: history::History::default(),
+            calendar: calendar::Calendar::default(),
+            creating_alarm: None,
+            creation_error: None,
+            saving_alarms: HashMap::new(),
+            edit_error: None,
+            holidays: Vec::new(),
         }
     }
 
@@ -128,14 +171,142 @@ impl Clock {
                 ui.label("Default Sound");
                 AlarmBuilder::render_sound_selector_editor(
                     &mut self.config.default_sound,
+                    None,
                     ui,
                     &self.sounds,
-                    &mut self.send,
+                    &mut self.connection,
+                    &mut self.preview,
+                    100.,
                 );
+                ui.separator();
+                ui.label("Sounds");
+                ComboBox::from_label("alarms using a removed sound")
+                    .selected_text(format!("{:?}", self.sound_removal))
+                    .show_ui(ui, |ui| {
+                        for removal in [
+                            SoundRemoval::Refuse,
+                            SoundRemoval::Reassign,
+                            SoundRemoval::Disable,
+                        ] {
+                            ui.selectable_value(
+                                &mut self.sound_removal,
+                                removal,
+                                format!("{removal:?}"),
+                            );
+                        }
+                    });
+                ScrollArea::vertical().id_salt("sounds").show(ui, |ui| {
+                    for name in self.sounds.keys() {
+                        ui.horizontal(|ui| {
+                            if ui.button("x").on_hover_text("remove sound").clicked() {
+                                self.connection
+                                    .send(roosty_clockd::ClientMessage::RemoveSound(
+                                        name.clone(),
+                                        self.sound_removal,
+                                    ));
+                            }
+                            ui.label(name);
+                        });
+                    }
+                });
+                if let Some(status) = &self.sound_removal_status {
+                    ui.label(status);
+                }
                 self.config.save(Config::config_path());
             });
     }
 
+    /// the names of the alarms that are ringing
+    #[must_use]
+    pub fn ringing_names(
+        alarms: &HashMap<u64, roosty_clockd_config::Alarm>,
+        ringing_alarms: &HashSet<u64>,
+    ) -> HashMap<u64, String> {
+        alarms
+            .iter()
+            .filter(|(id, _)| ringing_alarms.contains(*id))
+            .map(|(id, Alarm { name, .. })| {
+                (*id, name.clone().unwrap_or_else(|| "alarm".to_string()))
+            })
+            .collect()
+    }
+
+    fn render_connection_banner(&self, ui: &mut egui::Ui) {
+        if let Some(status) = self.connection.status() {
+            egui::Panel::top("connection").show_inside(ui, |ui| {
+                ui.colored_label(Color32::LIGHT_RED, status);
+            });
+        }
+        if let Some(after) = self.connection.poll_after() {
+            ui.ctx().request_repaint_after(after);
+        }
+    }
+
+    /// Checks if the daemon created the alarm from the editor, and reopens it if not.
+    fn poll_creating_alarm(&mut self, ctx: &egui::Context) {
+        let Some((_, pending)) = &mut self.creating_alarm else {
+            return;
+        };
+        let Some(answer) = pending.try_answer() else {
+            ctx.request_repaint_after(Duration::from_millis(50));
+            return;
+        };
+        let Some((editing, _)) = self.creating_alarm.take() else {
+            return;
+        };
+        let error = match answer {
+            Ok(Ok(alarm)) => {
+                self.alarms.insert(alarm.id, alarm);
+                return;
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(e) => format!("roosty_clockd didn't answer: {e}"),
+        };
+        log::warn!("couldn't add alarm: {error}");
+        self.creation_error = Some(error);
+        self.adding_alarm = Some(editing);
+    }
+
+    /// Checks if the daemon saved the edited alarms, and undoes the edits and reopens the editor if
+    /// not.
+    fn poll_saving_alarms(&mut self, ctx: &egui::Context) {
+        let answers: Vec<_> = self
+            .saving_alarms
+            .iter_mut()
+            .filter_map(|(id, saving)| Some((*id, saving.pending.try_answer()?)))
+            .collect();
+        if answers.len() < self.saving_alarms.len() {
+            ctx.request_repaint_after(Duration::from_millis(50));
+        }
+        for (id, answer) in answers {
+            let Some(saving) = self.saving_alarms.remove(&id) else {
+                continue;
+            };
+            let error = match answer {
+                Ok(Ok(_)) => continue,
+                Ok(Err((_, e))) => e.to_string(),
+                // reconnecting gets the alarms from the daemon again, whatever it did
+                Err(e) => format!("roosty_clockd didn't answer: {e}"),
+            };
+            log::warn!("couldn't save alarm {id}: {error}");
+            self.edit_error = Some(error);
+            self.alarms.insert(id, saving.old);
+            self.alarm_edits.insert(id, saving.editing);
+        }
+    }
+
+    fn alarm_names(&self, ids: &[u64]) -> String {
+        ids.iter()
+            .map(|id| {
+                self.alarms
+                    .get(id)
+                    .and_then(|alarm| alarm.name.clone())
+                    .unwrap_or_else(|| "alarm".to_string())
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
     fn render_header(&mut self, ctx: &egui::Context) {
         TopBottomPanel::top("time_and_ctrl").show(ctx, |ui| {
             ui.horizontal(|ui| {
@@ -162,6 +333,12 @@ impl Clock {
                     if ui.button("⚙").on_hover_text("settings").clicked() {
                         self.in_config = true;
                     }
+                    if ui.button("📜").on_hover_text("history").clicked() {
+                        self.history.show(&mut self.connection);
+                    }
+                    ui.menu_button("📅", |ui| self.calendar.menu(ui, &mut self.connection))
+                        .response
+                        .on_hover_text("calendar");
                 });
             });
         });
@@ -178,10 +355,8 @@ impl Clock {
         for (_i, id) in collect {
             if ui.button("x").on_hover_text("delete alarm").clicked() {
                 // handle if alarm is currently active
-                send_to_server(
-                    &mut self.send,
-                    roosty_clockd::ClientMessage::RemoveAlarm(id),
-                );
+                self.connection
+                    .send(roosty_clockd::ClientMessage::RemoveAlarm(id));
 
                 self.alarms.remove(&id);
                 // self.list_alarms(ui, 0, ctx);
@@ -209,27 +384,33 @@ impl eframe::App for Clock {
         if self.in_config {
             self.render_settings(ui.ctx());
         }
+        if self.history.open {
+            self.history
+                .render(ui.ctx(), &self.alarms, &mut self.connection);
+        }
+        self.calendar.render(ui.ctx());
         // alarm creation
         if let Some(editing) = &mut self.adding_alarm {
             match editing.render_alarm_editor(
                 ui.ctx(),
                 &self.sounds,
-                &mut self.send,
+                &mut self.connection,
                 self.config.max_volume,
+                &mut self.preview,
+                &self.holidays,
             ) {
                 EditingState::Done(new_alarm) => {
-                    self.adding_alarm = None;
-                    self.alarms.insert(new_alarm.id, new_alarm.clone());
-                    send_to_server(
-                        &mut self.send,
-                        roosty_clockd::ClientMessage::AddAlarm(roosty_clockd::Alarm {
-                            name: new_alarm.name,
-                            time: new_alarm.time,
-                            volume: new_alarm.volume,
-                            sound: new_alarm.sound,
-                            id: new_alarm.id,
-                        }),
-                    );
+                    let editing = self.adding_alarm.take();
+                    self.creation_error = None;
+                    if let Some(pending) = self
+                        .connection
+                        .request(|client| client.create_alarm(new_alarm.into()))
+                    {
+                        self.creating_alarm = editing.map(|editing| (editing, pending));
+                    } else {
+                        self.creation_error = Some("not connected to roosty_clockd".to_string());
+                        self.adding_alarm = editing;
+                    }
                 }
                 EditingState::Cancelled => {
                     self.adding_alarm = None;
@@ -237,30 +418,33 @@ impl eframe::App for Clock {
                 _ => {}
             }
         }
-        if let Ok(message) = recieve_from_server(&mut self.recv, false) {
+        if let Some(message) = self.connection.recieve() {
             match message {
                 ServerMessage::AlarmSet(id, alarm_edit) => {
-                    let alarm = self.alarms.get_mut(&id).unwrap();
-                    match alarm_edit {
-                        roosty_clockd::AlarmEdit::Time(new_time) => alarm.time = new_time,
-                        roosty_clockd::AlarmEdit::Name(new_name) => alarm.name = new_name,
-                        roosty_clockd::AlarmEdit::Sound(new_sound) => alarm.sound = new_sound,
-                        roosty_clockd::AlarmEdit::Volume(new_volume) => alarm.volume = new_volume,
-                        roosty_clockd::AlarmEdit::Enable(new_enable) => alarm.enabled = new_enable,
+                    self.alarms.get_mut(&id).unwrap().apply(alarm_edit);
+                }
+                ServerMessage::BatchApplied(changes) => {
+                    for change in changes {
+                        match change {
+                            Change::Created(alarm) => {
+                                self.alarms.insert(alarm.id, alarm);
+                            }
+                            Change::Set(id, alarm_edit) => {
+                                if let Some(alarm) = self.alarms.get_mut(&id) {
+                                    alarm.apply(alarm_edit);
+                                }
+                            }
+                            Change::Removed(id) => {
+                                self.alarms.remove(&id);
+                            }
+                        }
                     }
                 }
                 ServerMessage::AlaramAdded(alarm) => {
-                    self.alarms.insert(
-                        alarm.id,
-                        Alarm {
-                            name: alarm.name,
-                            time: alarm.time,
-                            volume: alarm.volume,
-                            sound: alarm.sound,
-                            enabled: true,
-                            id: alarm.id,
-                        },
-                    );
+                    self.alarms.insert(alarm.id, alarm.into());
+                }
+                ServerMessage::AlarmCreated(alarm) => {
+                    self.alarms.insert(alarm.id, alarm);
                 }
                 ServerMessage::AlarmRemoved(id) => {
                     self.alarms.remove(&id);
@@ -269,8 +453,27 @@ impl eframe::App for Clock {
                     self.sounds
                         .extend(sounds.into_iter().map(|sound| (sound.name.clone(), sound)));
                 }
-                ServerMessage::SoundRemoved(sounds) => {
-                    self.sounds.remove(&sounds);
+                ServerMessage::SoundRemoved(sound, affected) => {
+                    self.sounds.remove(&sound);
+                    if self.config.default_sound == sound {
+                        self.config.default_sound = Sound::get_default_name();
+                    }
+                    self.sound_removal_status = Some(if affected.is_empty() {
+                        format!("removed {sound}")
+                    } else {
+                        format!("removed {sound}, affected: {}", self.alarm_names(&affected))
+                    });
+                }
+                ServerMessage::SoundNotRemoved(sound, error) => {
+                    self.sound_removal_status = Some(match error {
+                        SoundRemovalError::InUse(alarms) => {
+                            format!("{sound} is used by: {}", self.alarm_names(&alarms))
+                        }
+                        SoundRemovalError::IsDefault => {
+                            format!("{sound} is the default sound")
+                        }
+                        SoundRemovalError::NotFound => format!("{sound} does not exist"),
+                    });
                 }
                 ServerMessage::AlarmRinging(id) => {
                     println!("ringing");
@@ -287,8 +490,36 @@ impl eframe::App for Clock {
                 ServerMessage::AlarmStopped(id) => {
                     self.ringing.remove(&id);
                 }
-                ServerMessage::UID(_) => unreachable!(),
-                ServerMessage::Init { .. } => unreachable!(),
+                // what we asked for after connecting, or someone else changed them
+                ServerMessage::Exceptions(exceptions) => {
+                    self.holidays = exceptions;
+                }
+                // answers to requests go to whoever sent them, so these only show up if someone
+                // else didn't use a request
+                ServerMessage::UID(_)
+                | ServerMessage::History(_)
+                | ServerMessage::AlarmNotCreated(_)
+                | ServerMessage::BatchRejected(..)
+                | ServerMessage::Subscribed(_)
+                | ServerMessage::Calendar(_)
+                | ServerMessage::CalendarImported(..)
+                | ServerMessage::CalendarNotImported(_)
+                | ServerMessage::ResyncRequired
+                | ServerMessage::Response(..)
+                | ServerMessage::Pong(_)
+                | ServerMessage::ShuttingDown => {}
+                // after reconnecting
+                ServerMessage::Init {
+                    alarms,
+                    sounds,
+                    ringing_alarms,
+                } => {
+                    self.ringing = Self::ringing_names(&alarms, &ringing_alarms);
+                    self.alarms = alarms;
+                    self.sounds = sounds;
+                    self.connection
+                        .send(roosty_clockd::ClientMessage::GetExceptions);
+                }
             }
         }
         let mut old = HashMap::new();
@@ -303,10 +534,8 @@ impl eframe::App for Clock {
                     .show(ui.ctx(), |ui| {
                         close = ui.button("stop").clicked();
                         if close {
-                            send_to_server(
-                                &mut self.send,
-                                roosty_clockd::ClientMessage::StopAlarm(*id),
-                            );
+                            self.connection
+                                .send(roosty_clockd::ClientMessage::StopAlarm(*id));
                         }
                     });
 
@@ -314,18 +543,34 @@ impl eframe::App for Clock {
             })
             .collect();
         // header
+        self.render_connection_banner(ui);
         self.render_header(ui.ctx());
         // // show all alarms
         CentralPanel::default().show_inside(ui, |ui| {
-            if ui.button("+").on_hover_text("add alarm").clicked() {
-                send_to_server(&mut self.send, roosty_clockd::ClientMessage::GetNewUID);
-                if let Ok(ServerMessage::UID(id)) = recieve_from_server(&mut self.recv, true) {
-                    self.adding_alarm = Some(AlarmBuilder {
-                        sound: self.config.default_sound.clone(),
-                        id,
-                        ..Default::default()
-                    });
-                }
+            // new alarms are created by the daemon
+            if ui
+                .add_enabled(
+                    self.connection.is_connected()
+                        && self.adding_alarm.is_none()
+                        && self.creating_alarm.is_none(),
+                    Button::new("+"),
+                )
+                .on_hover_text("add alarm")
+                .clicked()
+            {
+                self.creation_error = None;
+                self.adding_alarm = Some(AlarmBuilder {
+                    sound: self.config.default_sound.clone(),
+                    ..Default::default()
+                });
+            }
+            self.poll_creating_alarm(ui.ctx());
+            if let Some(error) = &self.creation_error {
+                ui.colored_label(Color32::LIGHT_RED, format!("couldn't add alarm: {error}"));
+            }
+            self.poll_saving_alarms(ui.ctx());
+            if let Some(error) = &self.edit_error {
+                ui.colored_label(Color32::LIGHT_RED, format!("couldn't save alarm: {error}"));
             }
 
             ScrollArea::vertical().show(ui, |ui| {
@@ -336,33 +581,48 @@ impl eframe::App for Clock {
 
             let mut old_alarm_edits = HashMap::new();
             mem::swap(&mut old_alarm_edits, &mut self.alarm_edits);
-            self.alarm_edits =
-                (old_alarm_edits.into_iter().filter_map(|(id, mut alarm)| {
-                    match alarm.render_alarm_editor(
-                        ui.ctx(),
-                        &self.sounds,
-                        &mut self.send,
-                        self.config.max_volume,
-                    ) {
-                        EditingState::Cancelled => None,
-                        EditingState::Editing => Some((id, alarm)),
-                        EditingState::Done(alarm) => {
-                            self.alarms.insert(id, alarm.clone());
-                            send_to_server(
-                                &mut self.send,
-                                roosty_clockd::ClientMessage::AddAlarm(roosty_clockd::Alarm {
-                                    name: alarm.name,
-                                    time: alarm.time,
-                                    volume: alarm.volume,
-                                    sound: alarm.sound,
-                                    id,
-                                }),
-                            );
-                            None
+            self.alarm_edits = (old_alarm_edits.into_iter().filter_map(|(id, mut alarm)| {
+                match alarm.render_alarm_editor(
+                    ui.ctx(),
+                    &self.sounds,
+                    &mut self.connection,
+                    self.config.max_volume,
+                    &mut self.preview,
+                    &self.holidays,
+                ) {
+                    EditingState::Cancelled => None,
+                    EditingState::Editing => Some((id, alarm)),
+                    EditingState::Done(new_alarm) => {
+                        let old = self.alarms.get(&id).cloned()?;
+                        // only send what changed (all at once), the alarm already exists
+                        let ops: Vec<_> = old
+                            .edits(&new_alarm)
+                            .into_iter()
+                            .map(|edit| Op::Set(id, edit))
+                            .collect();
+                        if ops.is_empty() {
+                            return None;
                         }
+                        self.edit_error = None;
+                        let Some(pending) = self.connection.request(|client| client.batch(ops))
+                        else {
+                            self.edit_error = Some("not connected to roosty_clockd".to_string());
+                            return Some((id, alarm));
+                        };
+                        self.alarms.insert(id, new_alarm);
+                        self.saving_alarms.insert(
+                            id,
+                            SavingAlarm {
+                                old,
+                                editing: alarm,
+                                pending,
+                            },
+                        );
+                        None
                     }
-                }))
-                .collect();
+                }
+            }))
+            .collect();
         });
     }
 }
@@ -6,20 +6,52 @@
     clippy::missing_panics_doc
 )]
 
-use std::{collections::HashMap, error::Error, io::BufReader, path::PathBuf};
+use std::{
+    env,
+    error::Error,
+    fs,
+    path::{Path, PathBuf},
+};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
 use eframe::{egui::ViewportBuilder, run_native};
-use interprocess::local_socket::{
-    GenericFilePath, GenericNamespaced, RecvHalf, SendHalf, Stream, prelude::*,
+use roosty_clock::{Clock, config::Config, daemon};
+use roosty_clockd::{
+    AlarmEdit,
+    client::{Client, Target},
+    config::Exception,
+    ics,
+    remote::{self, Remote},
+    socket::Address,
 };
-use roosty_clock::{Clock, config::Config};
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Args {
     #[clap(subcommand)]
     command: Option<Command>,
+    /// don't start roosty_clockd if it isn't running
+    #[clap(long)]
+    no_spawn: bool,
+    /// the socket roosty_clockd listens on (see also `ROOSTY_CLOCKD_SOCKET`)
+    #[clap(long, conflicts_with = "host")]
+    socket: Option<String>,
+    /// control roosty_clockd on another machine (`host[:port]`), with the token from
+    /// `ROOSTY_CLOCKD_TOKEN`
+    #[clap(long)]
+    host: Option<String>,
+}
+
+impl Args {
+    /// where to find roosty_clockd
+    fn target(&self) -> Result<Target, Box<dyn Error>> {
+        let Some(host) = &self.host else {
+            return Ok(Target::Local(Address::resolve(self.socket.as_deref())));
+        };
+        let token = env::var(remote::TOKEN_ENV)
+            .map_err(|_| format!("set {} to connect to {host}", remote::TOKEN_ENV))?;
+        Ok(Target::Remote(Remote::new(host, token)))
+    }
 }
 #[derive(Subcommand)]
 enum Command {
@@ -36,6 +68,95 @@ enum Command {
         time: String,
         sound: String,
     },
+    /// add the days in an iCalendar (.ics) file, like public holidays, to the days alarms don't
+    /// ring on
+    Holidays {
+        path: PathBuf,
+        /// only for this alarm instead of every alarm
+        #[clap(long)]
+        alarm: Option<u64>,
+    },
+    /// write every alarm to stdout (or a file)
+    Export {
+        #[clap(long, value_enum, default_value_t = Format::Ics)]
+        format: Format,
+        #[clap(long, short)]
+        output: Option<PathBuf>,
+    },
+    /// add an alarm for each reminder in an iCalendar (.ics) file
+    Import {
+        path: PathBuf,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+enum Format {
+    /// iCalendar, for calendar apps
+    Ics,
+}
+
+/// Writes the alarms to `output` (or stdout).
+fn export(target: &Target, format: Format, output: Option<&Path>) -> Result<(), Box<dyn Error>> {
+    let client = Client::connect(target)?;
+    let exported = match format {
+        Format::Ics => client.export_calendar()?.wait()?,
+    };
+    match output {
+        Some(path) => fs::write(path, exported)?,
+        None => print!("{exported}"),
+    }
+    Ok(())
+}
+
+/// Adds the alarms in a calendar file, and says what was left out.
+fn import(target: &Target, path: &Path) -> Result<(), Box<dyn Error>> {
+    let calendar = fs::read_to_string(path)?;
+    let client = Client::connect(target)?;
+    let (changes, warnings) = client.import_calendar(calendar)?.wait()??;
+    for warning in warnings {
+        eprintln!("{}: {warning}", path.display());
+    }
+    println!("imported {}: {}", path.display(), ics::summary(&changes));
+    Ok(())
+}
+
+/// Adds the holidays in `path` to the exceptions of the daemon (or of one alarm).
+fn import_holidays(target: &Target, path: &Path, alarm: Option<u64>) -> Result<(), Box<dyn Error>> {
+    let text = fs::read_to_string(path)?;
+    let (holidays, warnings) = ics::holidays(&text)?;
+    for warning in warnings {
+        eprintln!("{}: {warning}", path.display());
+    }
+    let client = Client::connect(target)?;
+    let add = |exceptions: &mut Vec<Exception>| {
+        for holiday in &holidays {
+            if !exceptions.contains(holiday) {
+                exceptions.push(holiday.clone());
+            }
+        }
+    };
+    if let Some(id) = alarm {
+        let state = client.init()?.wait()?;
+        let alarm = state
+            .alarms
+            .get(&id)
+            .ok_or_else(|| format!("there is no alarm {id}"))?;
+        let mut exceptions = alarm.exceptions.clone();
+        add(&mut exceptions);
+        client
+            .set_alarm(id, AlarmEdit::Exceptions(exceptions))?
+            .wait()??;
+    } else {
+        let mut exceptions = client.exceptions()?.wait()?;
+        add(&mut exceptions);
+        client.set_exceptions(exceptions)?.wait()?;
+    }
+    println!(
+        "imported {} holidays from {}",
+        holidays.len(),
+        path.display()
+    );
+    Ok(())
 }
 fn main() -> Result<(), Box<dyn Error>> {
     // initilize the logger
@@ -50,9 +171,9 @@ fn main() -> Result<(), Box<dyn Error>> {
     };
 
     let args = Args::parse();
-    match args.command {
+    match &args.command {
         Some(Command::Init { force }) => {
-            if force && Config::is_config_present() || !Config::is_config_present() {
+            if *force && Config::is_config_present() || !Config::is_config_present() {
                 Config::new().save(Config::config_path());
             }
         }
@@ -62,19 +183,23 @@ fn main() -> Result<(), Box<dyn Error>> {
             time: _,
             sound: _,
         }) => {}
+        Some(Command::Holidays { path, alarm }) => {
+            return import_holidays(&args.target()?, path, *alarm);
+        }
+        Some(Command::Export { format, output }) => {
+            return export(&args.target()?, *format, output.as_deref());
+        }
+        Some(Command::Import { path }) => return import(&args.target()?, path),
         None => {}
     }
 
-    let conn = get_socket()?;
-    let (recv, mut send) = conn.split();
-    let mut recv = BufReader::new(recv);
-    // let mut send = BufWriter::new(send);
-    let (alarms, sounds, ringing_alarms) = get_alarms(&mut recv, &mut send);
-    println!("done");
-
-    // Print out the result, getting the newline for free!
-    // print!("Server answered: {buffer}");
-    //{
+    let config = Config::is_config_present().then(|| Config::load(Config::config_path()));
+    let (connection, state) = daemon::connect(
+        &args.target()?,
+        !args.no_spawn,
+        config.as_ref().and_then(Config::daemon_path),
+    )?;
+    let ringing_alarms = Clock::ringing_names(&state.alarms, &state.ringing_alarms);
 
     // run the gui
     run_native(
@@ -82,55 +207,12 @@ fn main() -> Result<(), Box<dyn Error>> {
         native_options,
         Box::new(|_| {
             Ok(Box::new(Clock::new(
-                send,
-                recv,
-                sounds,
-                alarms,
+                connection,
+                state.sounds,
+                state.alarms,
                 ringing_alarms,
             )))
         }),
     )
     .map_err(std::convert::Into::into)
 }
-
-fn get_alarms(
-    recv: &mut BufReader<RecvHalf>,
-    send: &mut SendHalf,
-) -> (
-    HashMap<u64, roosty_clockd::config::Alarm>,
-    HashMap<String, roosty_clockd::config::Sound>,
-    HashMap<u64, String>,
-) {
-    roosty_clock::send_to_server(send, roosty_clockd::ClientMessage::Init).unwrap();
-
-    println!("alarms");
-    if let Ok(roosty_clockd::ServerMessage::Init {
-        alarms,
-        ringing_alarms,
-        sounds,
-    }) = roosty_clock::recieve_from_server(recv, true)
-    {
-        let collect = alarms
-            .iter()
-            .filter(|(id, _)| ringing_alarms.contains(*id))
-            .map(|(id, roosty_clockd::config::Alarm { name, .. })| {
-                (*id, name.clone().unwrap_or("alarm".to_string()))
-            })
-            .collect();
-        (alarms, sounds, collect)
-    } else {
-        panic!()
-    }
-}
-
-fn get_socket() -> Result<LocalSocketStream, Box<dyn Error + 'static>> {
-    let name = if GenericNamespaced::is_supported() {
-        "roosty-clockd.sock".to_ns_name::<GenericNamespaced>()?
-    } else {
-        "/tmp/roosty-clockd.sock".to_fs_name::<GenericFilePath>()?
-    };
-    let conn = Stream::connect(name)?;
-    conn.set_nonblocking(true);
-
-    Ok(conn)
-}
@@ -0,0 +1,74 @@
+use std::time::Duration;
+
+use rodio::{DeviceSinkBuilder, MixerDeviceSink, Player, Source};
+use roosty_clockd::config::Sound;
+
+/// Plays sounds locally so they can be heard before picking them for an alarm.
+#[derive(Default)]
+pub struct Preview {
+    // opened the first time something is previewed
+    sink: Option<MixerDeviceSink>,
+    playing: Option<(String, Player)>,
+}
+
+impl Preview {
+    /// how long a preview plays for before stopping on its own
+    pub const LENGTH: Duration = Duration::from_secs(5);
+
+    /// Plays `sound` at `volume` (in percent), stopping any other preview.
+    pub fn play(&mut self, sound: &Sound, volume: f32) {
+        self.stop();
+        if self.sink.is_none() {
+            match DeviceSinkBuilder::open_default_sink() {
+                Ok(mut sink) => {
+                    sink.log_on_drop(false);
+                    self.sink = Some(sink);
+                }
+                Err(e) => {
+                    log::warn!("couldn't open audio output to preview {}: {e}", sound.name);
+                    return;
+                }
+            }
+        }
+        if let Some(sink) = &self.sink {
+            let player = Player::connect_new(sink.mixer());
+            player.set_volume(volume / 100.);
+            player.append(roosty_clockd::sound::open(sound).take_duration(Self::LENGTH));
+            self.playing = Some((sound.name.clone(), player));
+        }
+    }
+
+    pub fn stop(&mut self) {
+        if let Some((_, player)) = self.playing.take() {
+            player.stop();
+        }
+    }
+
+    pub fn set_volume(&self, volume: f32) {
+        if let Some((_, player)) = &self.playing {
+            player.set_volume(volume / 100.);
+        }
+    }
+
+    /// the name of the sound being previewed, if its still playing
+    #[must_use]
+    pub fn playing(&self) -> Option<&str> {
+        self.playing
+            .as_ref()
+            .filter(|(_, player)| !player.empty())
+            .map(|(name, _)| name.as_str())
+    }
+
+    /// A button to start or stop previewing `sound`.
+    pub fn render_button(&mut self, ui: &mut eframe::egui::Ui, sound: &Sound, volume: f32) {
+        if self.playing() == Some(sound.name()) {
+            // so the button goes back once the preview is done
+            ui.ctx().request_repaint_after(Duration::from_millis(250));
+            if ui.small_button("■").on_hover_text("stop preview").clicked() {
+                self.stop();
+            }
+        } else if ui.small_button("▶").on_hover_text("preview").clicked() {
+            self.play(sound, volume);
+        }
+    }
+}
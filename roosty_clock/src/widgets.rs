@@ -1,4 +1,7 @@
-use eframe::egui::{Align2, Color32, Sense, Stroke, TextStyle, Vec2, Widget};
+use chrono::{Datelike, Local, NaiveDate};
+use eframe::egui::{
+    Align2, Color32, DragValue, Response, Sense, Stroke, TextStyle, Ui, Vec2, Widget,
+};
 
 pub struct Value<N> {
     pub value: N,
@@ -138,3 +141,42 @@ where
         responce
     }
 }
+
+/// A date that can be turned off with a checkbox (it starts at today when turned on).
+pub struct DatePicker<'a> {
+    date: &'a mut Option<NaiveDate>,
+    label: &'a str,
+}
+
+impl<'a> DatePicker<'a> {
+    pub const fn new(date: &'a mut Option<NaiveDate>, label: &'a str) -> Self {
+        Self { date, label }
+    }
+}
+
+impl Widget for DatePicker<'_> {
+    fn ui(self, ui: &mut Ui) -> Response {
+        ui.horizontal(|ui| {
+            let mut on = self.date.is_some();
+            let mut response = ui.checkbox(&mut on, self.label);
+            if on != self.date.is_some() {
+                *self.date = on.then(|| Local::now().date_naive());
+            }
+            if let Some(date) = self.date {
+                let (mut year, mut month, mut day) = (date.year(), date.month(), date.day());
+                response |= DragValue::new(&mut year).range(2000..=9999).ui(ui);
+                response |= DragValue::new(&mut month).range(1..=12).ui(ui);
+                // the day is kept in the month when the month changes
+                let days = NaiveDate::from_ymd_opt(year, month, 1)
+                    .map_or(31, |first| u32::from(first.num_days_in_month()));
+                day = day.min(days);
+                response |= DragValue::new(&mut day).range(1..=days).ui(ui);
+                if let Some(new) = NaiveDate::from_ymd_opt(year, month, day) {
+                    *date = new;
+                }
+            }
+            response
+        })
+        .inner
+    }
+}
@@ -0,0 +1,923 @@
+//! a typed client for talking to `roosty_clockd`, for the gui and any other tool
+//!
+//! ```no_run
+//! use roosty_clockd::{client::Client, socket::Address};
+//!
+//! let client = Client::connect(&Address::default())?;
+//! let state = client.init()?.wait()?;
+//! for id in state.ringing_alarms {
+//!     client.stop_alarm(id)?.wait()?;
+//! }
+//! // or from async code (with any runtime)
+//! # async_io::block_on(async {
+//! let pid = client.ping()?.await?;
+//! # Ok::<_, std::io::Error>(())
+//! # })?;
+//! # Ok::<_, std::io::Error>(())
+//! ```
+//!
+//! A thread reads everything the daemon sends and hands it to whoever is waiting for it: answers
+//! ([`ServerMessage::Response`]) go to the request with the same id, and everything else to the
+//! [`Subscription`]s. It stops once the connection closes or the client and all its subscriptions
+//! are dropped.
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    future::{Future, IntoFuture},
+    io::{self, BufReader, Read, Write},
+    pin::Pin,
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    task::{Context, Poll},
+    thread,
+    time::{Duration, Instant},
+};
+
+use async_broadcast::{InactiveReceiver, Receiver, RecvError, Sender, TryRecvError};
+use async_io::Timer;
+use chrono::{DateTime, Local};
+use futures_lite::{Stream, future};
+use interprocess::local_socket::prelude::*;
+
+use crate::{
+    Alarm, AlarmEdit, AlarmError, AlarmSpec, BatchResult, ClientMessage, ImportResult, Op,
+    ServerMessage, SoundRemoval, SoundRemovalError, config, encoding::Encoding, filter::Filter,
+    history, remote::Remote, socket::Address,
+};
+
+/// how long requests wait for an answer by default
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Where the daemon is.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    /// on this machine
+    Local(Address),
+    /// on another machine, over TCP (see [`crate::remote`])
+    Remote(Remote),
+}
+
+impl Target {
+    #[must_use]
+    pub const fn is_local(&self) -> bool {
+        matches!(self, Self::Local(_))
+    }
+}
+
+impl From<Address> for Target {
+    fn from(address: Address) -> Self {
+        Self::Local(address)
+    }
+}
+
+impl From<&Address> for Target {
+    fn from(address: &Address) -> Self {
+        Self::Local(address.clone())
+    }
+}
+
+impl From<&Self> for Target {
+    fn from(target: &Self) -> Self {
+        target.clone()
+    }
+}
+
+impl fmt::Display for Target {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Local(address) => write!(f, "{address}"),
+            Self::Remote(remote) => write!(f, "{remote}"),
+        }
+    }
+}
+
+/// The state of the daemon, the answer to [`Client::init`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct State {
+    pub alarms: HashMap<u64, config::Alarm>,
+    pub sounds: HashMap<String, config::Sound>,
+    pub ringing_alarms: HashSet<u64>,
+}
+
+/// A connection to the daemon.
+///
+/// Requests are sent right away and return a [`Pending`] answer, which can be waited for with
+/// [`Pending::wait`] or awaited.
+pub struct Client {
+    send: Mutex<Box<dyn Write + Send>>,
+    // kept around so requests and subscriptions can start listening
+    events: InactiveReceiver<ServerMessage>,
+    timeout: Duration,
+    next_request: AtomicU64,
+}
+
+impl fmt::Debug for Client {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Client")
+            .field("timeout", &self.timeout)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Client {
+    /// how many messages a subscription can fall behind before it misses some
+    const CAPACITY: usize = 64;
+    /// how often the reader checks if anyone is still listening
+    const POLL: Duration = Duration::from_millis(100);
+
+    /// Connects to the daemon at `target` (an [`Address`] for a local one).
+    ///
+    /// # Errors
+    /// If the daemon is not running (or can't be reached).
+    pub fn connect(target: impl Into<Target>) -> io::Result<Self> {
+        let (recv, mut send): (Box<dyn Read + Send>, Box<dyn Write + Send>) = match target.into() {
+            Target::Local(address) => {
+                let conn = address.connect()?;
+                conn.set_recv_timeout(Some(Self::POLL))?;
+                let (recv, send) = conn.split();
+                (Box::new(recv), Box::new(send))
+            }
+            Target::Remote(remote) => {
+                let conn = remote.connect()?;
+                conn.set_read_timeout(Some(Self::POLL))?;
+                (Box::new(conn.try_clone()?), Box::new(conn))
+            }
+        };
+        Encoding::Bitcode.handshake(&mut send)?;
+
+        let (mut events, receiver) = async_broadcast::broadcast(Self::CAPACITY);
+        events.set_overflow(true);
+        // nobody might be listening right now, but thats fine
+        events.set_await_active(false);
+        let reader = Reader { recv, events };
+        thread::spawn(move || read_events(reader));
+        Ok(Self {
+            send: Mutex::new(send),
+            events: receiver.deactivate(),
+            timeout: DEFAULT_TIMEOUT,
+            next_request: AtomicU64::new(0),
+        })
+    }
+
+    /// Sets how long requests wait for an answer.
+    pub const fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    /// Every message from the daemon from now on, except answers to requests.
+    #[must_use]
+    pub fn subscribe(&self) -> Subscription {
+        Subscription(self.events.activate_cloned())
+    }
+
+    /// Only get the events that get through `filter` from now on, see
+    /// [`ClientMessage::Subscribe`].
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn set_filter(&self, filter: Filter) -> io::Result<Pending<Filter>> {
+        self.call(ClientMessage::Subscribe(filter), |message| match message {
+            ServerMessage::Subscribed(filter) => Some(filter),
+            _ => None,
+        })
+    }
+
+    /// Sends a message without waiting for an answer.
+    ///
+    /// # Errors
+    /// If the message couldn't be sent.
+    pub fn send(&self, message: &ClientMessage) -> io::Result<()> {
+        let mut send = self
+            .send
+            .lock()
+            .map_err(|_| io::Error::other("the connection was poisoned"))?;
+        Encoding::Bitcode.send(&mut *send, message)
+    }
+
+    /// Sends `message` and waits for the first message from the daemon that `answer` accepts.
+    ///
+    /// This is for messages that aren't answered directly, but change something every client is
+    /// told about (like [`ClientMessage::AddAlarm`]), see [`Client::call`] for the rest.
+    ///
+    /// # Errors
+    /// If the message couldn't be sent.
+    pub fn request<T>(
+        &self,
+        message: &ClientMessage,
+        answer: impl FnMut(ServerMessage) -> Option<T> + Send + 'static,
+    ) -> io::Result<Pending<T>> {
+        // listen before sending, so the answer can't be missed
+        let events = self.events.activate_cloned();
+        self.send(message)?;
+        Ok(Pending {
+            events,
+            deadline: Instant::now() + self.timeout,
+            answer: Box::new(answer),
+        })
+    }
+
+    /// Sends `message` as a [`ClientMessage::Request`], and waits for the answer to it.
+    ///
+    /// # Errors
+    /// If the message couldn't be sent.
+    pub fn call<T>(
+        &self,
+        message: ClientMessage,
+        mut answer: impl FnMut(ServerMessage) -> Option<T> + Send + 'static,
+    ) -> io::Result<Pending<T>> {
+        let id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        self.request(
+            &ClientMessage::Request(id, Box::new(message)),
+            move |message| match message {
+                ServerMessage::Response(answered, message) if answered == id => answer(*message),
+                _ => None,
+            },
+        )
+    }
+
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn init(&self) -> io::Result<Pending<State>> {
+        self.call(ClientMessage::Init, |message| match message {
+            ServerMessage::Init {
+                alarms,
+                sounds,
+                ringing_alarms,
+            } => Some(State {
+                alarms,
+                sounds,
+                ringing_alarms,
+            }),
+            _ => None,
+        })
+    }
+
+    /// An unused id for a new alarm.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn new_uid(&self) -> io::Result<Pending<u64>> {
+        self.call(ClientMessage::GetNewUID, |message| match message {
+            ServerMessage::UID(id) => Some(id),
+            _ => None,
+        })
+    }
+
+    /// Creates an alarm, answered with the alarm (and the id the daemon gave it) or why it
+    /// wasn't created.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn create_alarm(
+        &self,
+        spec: AlarmSpec,
+    ) -> io::Result<Pending<Result<config::Alarm, AlarmError>>> {
+        self.call(ClientMessage::CreateAlarm(spec), |message| match message {
+            ServerMessage::AlarmCreated(alarm) => Some(Ok(alarm)),
+            ServerMessage::AlarmNotCreated(error) => Some(Err(error)),
+            _ => None,
+        })
+    }
+
+    /// Applies all of `ops` or none of them, answered with what changed or the index of the op
+    /// that couldn't be applied.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn batch(&self, ops: Vec<Op>) -> io::Result<Pending<BatchResult>> {
+        self.call(ClientMessage::Batch(ops), |message| match message {
+            ServerMessage::BatchApplied(changes) => Some(Ok(changes)),
+            ServerMessage::BatchRejected(op, error) => Some(Err((op, error))),
+            _ => None,
+        })
+    }
+
+    /// Skips the next time an alarm would ring, answered with the edit or why it couldn't be
+    /// skipped.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn skip_next(&self, id: u64) -> io::Result<Pending<BatchResult>> {
+        self.call(ClientMessage::SkipNext(id), |message| match message {
+            ServerMessage::BatchApplied(changes) => Some(Ok(changes)),
+            ServerMessage::BatchRejected(op, error) => Some(Err((op, error))),
+            _ => None,
+        })
+    }
+
+    /// The days no alarm rings on.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn exceptions(&self) -> io::Result<Pending<Vec<config::Exception>>> {
+        self.call(ClientMessage::GetExceptions, |message| match message {
+            ServerMessage::Exceptions(exceptions) => Some(exceptions),
+            _ => None,
+        })
+    }
+
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn set_exceptions(&self, exceptions: Vec<config::Exception>) -> io::Result<Pending<()>> {
+        self.request(
+            &ClientMessage::SetExceptions(exceptions.clone()),
+            move |message| {
+                matches!(message, ServerMessage::Exceptions(set) if set == exceptions).then_some(())
+            },
+        )
+    }
+
+    /// Every alarm as an iCalendar file.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn export_calendar(&self) -> io::Result<Pending<String>> {
+        self.call(ClientMessage::ExportCalendar, |message| match message {
+            ServerMessage::Calendar(calendar) => Some(calendar),
+            _ => None,
+        })
+    }
+
+    /// Creates alarms for the reminders in an iCalendar file, answered with what was created and
+    /// what couldn't be imported, or why the file couldn't be read.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn import_calendar(&self, calendar: String) -> io::Result<Pending<ImportResult>> {
+        self.call(
+            ClientMessage::ImportCalendar(calendar),
+            |message| match message {
+                ServerMessage::CalendarImported(changes, warnings) => Some(Ok((changes, warnings))),
+                ServerMessage::CalendarNotImported(error) => Some(Err(error)),
+                _ => None,
+            },
+        )
+    }
+
+    /// Answered with the alarm once every client is told about it, or why the daemon refused it.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn add_alarm(&self, alarm: Alarm) -> io::Result<Pending<Result<Alarm, AlarmError>>> {
+        let id = alarm.id;
+        self.request_or_refusal(
+            ClientMessage::AddAlarm(alarm),
+            move |message| match message {
+                ServerMessage::AlaramAdded(alarm) if alarm.id == id => Some(Ok(alarm)),
+                ServerMessage::AlarmNotCreated(error) => Some(Err(error)),
+                _ => None,
+            },
+        )
+    }
+
+    /// Answered once every client is told about the edit, or with why the daemon refused it.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn set_alarm(
+        &self,
+        id: u64,
+        edit: AlarmEdit,
+    ) -> io::Result<Pending<Result<(), AlarmError>>> {
+        self.request_or_refusal(
+            ClientMessage::SetAlarm(id, edit),
+            move |message| match message {
+                ServerMessage::AlarmSet(set, _) if set == id => Some(Ok(())),
+                ServerMessage::BatchRejected(_, error) => Some(Err(error)),
+                _ => None,
+            },
+        )
+    }
+
+    /// Like [`Client::request`], but the daemon answers `message` directly if it refuses it, so
+    /// `answer` sees both what every client is told and the answer to this request.
+    fn request_or_refusal<T>(
+        &self,
+        message: ClientMessage,
+        mut answer: impl FnMut(ServerMessage) -> Option<T> + Send + 'static,
+    ) -> io::Result<Pending<T>> {
+        let id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        self.request(
+            &ClientMessage::Request(id, Box::new(message)),
+            move |message| match message {
+                ServerMessage::Response(answered, message) if answered == id => answer(*message),
+                // answers to other requests aren't for us
+                ServerMessage::Response(..) => None,
+                message => answer(message),
+            },
+        )
+    }
+
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn remove_alarm(&self, id: u64) -> io::Result<Pending<()>> {
+        self.request(&ClientMessage::RemoveAlarm(id), move |message| {
+            matches!(message, ServerMessage::AlarmRemoved(removed) if removed == id).then_some(())
+        })
+    }
+
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn stop_alarm(&self, id: u64) -> io::Result<Pending<()>> {
+        self.request(&ClientMessage::StopAlarm(id), move |message| {
+            matches!(message, ServerMessage::AlarmStopped(stopped) if stopped == id).then_some(())
+        })
+    }
+
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn add_sounds(&self, sounds: Vec<config::Sound>) -> io::Result<Pending<()>> {
+        let names: Vec<_> = sounds.iter().map(|sound| sound.name.clone()).collect();
+        self.request(&ClientMessage::AddedSounds(sounds), move |message| {
+            matches!(message, ServerMessage::SoundsAdded(added)
+                if added.iter().map(|sound| &sound.name).eq(&names))
+            .then_some(())
+        })
+    }
+
+    /// Removes a sound, answered with the alarms that were changed because of it.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn remove_sound(
+        &self,
+        name: String,
+        removal: SoundRemoval,
+    ) -> io::Result<Pending<Result<Vec<u64>, SoundRemovalError>>> {
+        // only refusals are answered, every client is told when a sound is removed
+        let id = self.next_request.fetch_add(1, Ordering::Relaxed);
+        let message = ClientMessage::RemoveSound(name.clone(), removal);
+        self.request(
+            &ClientMessage::Request(id, Box::new(message)),
+            move |message| match message {
+                ServerMessage::SoundRemoved(sound, affected) if sound == name => Some(Ok(affected)),
+                ServerMessage::Response(answered, message) if answered == id => match *message {
+                    ServerMessage::SoundNotRemoved(_, error) => Some(Err(error)),
+                    _ => None,
+                },
+                _ => None,
+            },
+        )
+    }
+
+    /// See [`ClientMessage::QueryHistory`].
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn history(
+        &self,
+        since: Option<DateTime<Local>>,
+        until: Option<DateTime<Local>>,
+        alarm: Option<u64>,
+    ) -> io::Result<Pending<Vec<history::Entry>>> {
+        self.call(
+            ClientMessage::QueryHistory {
+                since,
+                until,
+                alarm,
+            },
+            |message| match message {
+                ServerMessage::History(entries) => Some(entries),
+                _ => None,
+            },
+        )
+    }
+
+    /// Answered with the PID of the daemon.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn ping(&self) -> io::Result<Pending<u32>> {
+        self.call(ClientMessage::Ping, |message| match message {
+            ServerMessage::Pong(pid) => Some(pid),
+            _ => None,
+        })
+    }
+
+    /// Stops the daemon.
+    ///
+    /// # Errors
+    /// If the request couldn't be sent.
+    pub fn shutdown(&self) -> io::Result<()> {
+        self.send(&ClientMessage::Shutdown)
+    }
+}
+
+/// The answer to a request that was sent.
+#[must_use = "the request was sent either way, this is only needed to wait for the answer"]
+pub struct Pending<T> {
+    events: Receiver<ServerMessage>,
+    deadline: Instant,
+    answer: Box<dyn FnMut(ServerMessage) -> Option<T> + Send>,
+}
+
+impl<T> Pending<T> {
+    /// Blocks until the answer comes.
+    ///
+    /// # Errors
+    /// If it took longer than the timeout ([`io::ErrorKind::TimedOut`]) or the connection closed
+    /// ([`io::ErrorKind::UnexpectedEof`]).
+    pub fn wait(self) -> io::Result<T> {
+        async_io::block_on(self.answer())
+    }
+
+    /// # Errors
+    /// Same as [`Pending::wait`].
+    pub async fn answer(mut self) -> io::Result<T> {
+        let deadline = self.deadline;
+        let answer = async move {
+            loop {
+                match self.events.recv().await {
+                    Ok(message) => {
+                        if let Some(answer) = (self.answer)(message) {
+                            return Ok(answer);
+                        }
+                    }
+                    // if the answer was missed this times out
+                    Err(RecvError::Overflowed(_)) => {}
+                    Err(RecvError::Closed) => return Err(closed()),
+                }
+            }
+        };
+        let timed_out = async move {
+            Timer::at(deadline).await;
+            Err(timed_out())
+        };
+        future::or(answer, timed_out).await
+    }
+
+    /// The answer if it came already, without blocking (for polling from a render loop).
+    ///
+    /// Once this returned something it shouldn't be called again.
+    pub fn try_answer(&mut self) -> Option<io::Result<T>> {
+        loop {
+            match self.events.try_recv() {
+                Ok(message) => {
+                    if let Some(answer) = (self.answer)(message) {
+                        return Some(Ok(answer));
+                    }
+                }
+                Err(TryRecvError::Overflowed(_)) => {}
+                Err(TryRecvError::Empty) => {
+                    return (Instant::now() >= self.deadline).then(|| Err(timed_out()));
+                }
+                Err(TryRecvError::Closed) => return Some(Err(closed())),
+            }
+        }
+    }
+}
+
+impl<T> fmt::Debug for Pending<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Pending")
+            .field("deadline", &self.deadline)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T: Send + 'static> IntoFuture for Pending<T> {
+    type Output = io::Result<T>;
+    type IntoFuture = Pin<Box<dyn Future<Output = Self::Output> + Send>>;
+
+    fn into_future(self) -> Self::IntoFuture {
+        Box::pin(self.answer())
+    }
+}
+
+/// Every message the daemon sends, as a blocking [`Iterator`] or a [`Stream`].
+///
+/// Both end when the connection closes. If the subscription falls too far behind the oldest
+/// messages are skipped, [`Subscription::try_next`] says so with a
+/// [`ServerMessage::ResyncRequired`] (like the daemon does when the client falls behind).
+#[derive(Debug)]
+pub struct Subscription(Receiver<ServerMessage>);
+
+impl Subscription {
+    /// The next message if there is one already, without blocking.
+    ///
+    /// # Errors
+    /// If the connection closed ([`io::ErrorKind::UnexpectedEof`]).
+    pub fn try_next(&mut self) -> io::Result<Option<ServerMessage>> {
+        loop {
+            match self.0.try_recv() {
+                Ok(ServerMessage::Response(..)) => {}
+                Ok(message) => return Ok(Some(message)),
+                Err(TryRecvError::Overflowed(missed)) => {
+                    log::warn!("missed {missed} messages from roosty_clockd");
+                    return Ok(Some(ServerMessage::ResyncRequired));
+                }
+                Err(TryRecvError::Empty) => return Ok(None),
+                Err(TryRecvError::Closed) => return Err(closed()),
+            }
+        }
+    }
+}
+
+impl Iterator for Subscription {
+    type Item = ServerMessage;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        async_io::block_on(futures_lite::StreamExt::next(self))
+    }
+}
+
+impl Stream for Subscription {
+    type Item = ServerMessage;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            match Pin::new(&mut self.0).poll_next(cx) {
+                // those are for whoever sent the request
+                Poll::Ready(Some(ServerMessage::Response(..))) => {}
+                poll => return poll,
+            }
+        }
+    }
+}
+
+fn timed_out() -> io::Error {
+    io::Error::new(io::ErrorKind::TimedOut, "roosty_clockd didn't answer")
+}
+
+fn closed() -> io::Error {
+    io::Error::new(
+        io::ErrorKind::UnexpectedEof,
+        "the connection to roosty_clockd closed",
+    )
+}
+
+/// The receiving half of the connection, it waits for the next message for as long as anyone is
+/// listening for it.
+struct Reader {
+    recv: Box<dyn Read + Send>,
+    events: Sender<ServerMessage>,
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            match self.recv.read(buf) {
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    if self.events.is_closed() {
+                        return Err(closed());
+                    }
+                }
+                result => return result,
+            }
+        }
+    }
+}
+
+fn read_events(reader: Reader) {
+    let mut reader = BufReader::new(reader);
+    let mut buffer = Vec::new();
+    loop {
+        match Encoding::Bitcode.recieve(&mut reader, &mut buffer) {
+            Ok(message) => {
+                let _ = reader.get_ref().events.try_broadcast(message);
+            }
+            // there is no telling where the next message starts, so this ends the subscriptions
+            // like a lost connection would
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {
+                log::warn!("roosty_clockd sent an invalid message, disconnecting: {e}");
+                return;
+            }
+            Err(_) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use chrono::NaiveTime;
+
+    use super::*;
+    use crate::config::SoundMode;
+
+    /// a daemon that echoes alarms back and answers some requests, but only after sending an
+    /// alert and an answer for another client first
+    fn fake_daemon(name: &str) -> Address {
+        let address = Address::Path(PathBuf::from(format!(
+            "{}/roosty-clockd-client-{}-{name}.sock",
+            std::env::temp_dir().display(),
+            std::process::id()
+        )));
+        let _ = std::fs::remove_file(address.to_string());
+        let listener = address.listen().unwrap();
+        thread::spawn(move || {
+            let (recv, mut send) = listener.accept().unwrap().split();
+            let mut recv = BufReader::new(recv);
+            let encoding = Encoding::accept(&mut recv).unwrap();
+            let mut bytes = Vec::new();
+            while let Ok(message) = encoding.recieve(&mut recv, &mut bytes) {
+                let (request, message) = match message {
+                    ClientMessage::Request(id, message) => (Some(id), *message),
+                    message => (None, message),
+                };
+                for unrelated in [
+                    ServerMessage::AlarmRinging(7),
+                    ServerMessage::UID(1).respond_to(Some(u64::MAX)),
+                ] {
+                    encoding.send(&mut send, &unrelated).unwrap();
+                }
+                let answer = match message {
+                    ClientMessage::AddAlarm(alarm) if alarm.volume > 100. => {
+                        ServerMessage::AlarmNotCreated(AlarmError::VolumeOutOfRange(alarm.volume))
+                            .respond_to(request)
+                    }
+                    ClientMessage::AddAlarm(alarm) => ServerMessage::AlaramAdded(alarm),
+                    ClientMessage::SetAlarm(id, _) if id != 3 => {
+                        ServerMessage::BatchRejected(0, AlarmError::NoSuchAlarm(id))
+                            .respond_to(request)
+                    }
+                    ClientMessage::SetAlarm(id, edit) => ServerMessage::AlarmSet(id, edit),
+                    ClientMessage::Ping => ServerMessage::Pong(42).respond_to(request),
+                    // a broken message, and then a fine one that can't be found anymore
+                    ClientMessage::GetExceptions => {
+                        let mut garbage = 4_usize.to_ne_bytes().to_vec();
+                        garbage.extend_from_slice(&[0xff; 4]);
+                        send.write_all(&garbage).unwrap();
+                        ServerMessage::AlarmRinging(8)
+                    }
+                    ClientMessage::GetNewUID => ServerMessage::UID(5).respond_to(request),
+                    ClientMessage::CreateAlarm(spec) if spec.volume > 100. => {
+                        ServerMessage::AlarmNotCreated(AlarmError::VolumeOutOfRange(spec.volume))
+                            .respond_to(request)
+                    }
+                    ClientMessage::CreateAlarm(spec) => {
+                        ServerMessage::AlarmCreated(spec.with_id(6)).respond_to(request)
+                    }
+                    // never answered
+                    _ => continue,
+                };
+                encoding.send(&mut send, &answer).unwrap();
+            }
+        });
+        address
+    }
+
+    #[test]
+    fn requests_get_their_answers() {
+        let client = Client::connect(fake_daemon("answers")).unwrap();
+        let mut events = client.subscribe();
+        let alarm = Alarm {
+            name: None,
+            time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            volume: 100.,
+            sound: "Bell".to_string(),
+            playlist: Vec::new(),
+            sound_mode: SoundMode::Single,
+            id: 3,
+        };
+        assert_eq!(
+            client.add_alarm(alarm.clone()).unwrap().wait().unwrap(),
+            Ok(alarm.clone())
+        );
+        assert_eq!(
+            async_io::block_on(client.ping().unwrap().into_future()).unwrap(),
+            42
+        );
+        let mut uid = client.new_uid().unwrap();
+        let uid = loop {
+            if let Some(uid) = uid.try_answer() {
+                break uid.unwrap();
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(uid, 5);
+        let mut spec = AlarmSpec {
+            name: None,
+            time: alarm.time,
+            volume: 100.,
+            sound: "Bell".to_string(),
+            playlist: Vec::new(),
+            sound_mode: SoundMode::Single,
+            enabled: true,
+            start: None,
+            end: None,
+            date: None,
+            exceptions: Vec::new(),
+            cron: None,
+        };
+        let created = client.create_alarm(spec.clone()).unwrap().wait().unwrap();
+        assert_eq!(created, Ok(spec.clone().with_id(6)));
+        spec.volume = 300.;
+        let created = client.create_alarm(spec).unwrap().wait().unwrap();
+        assert_eq!(created, Err(AlarmError::VolumeOutOfRange(300.)));
+        // refusals are answered, but only to the client that asked
+        let loud = Alarm {
+            volume: 300.,
+            ..alarm.clone()
+        };
+        let added = client.add_alarm(loud).unwrap().wait().unwrap();
+        assert_eq!(added, Err(AlarmError::VolumeOutOfRange(300.)));
+        let set = client
+            .set_alarm(3, AlarmEdit::Volume(50.))
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(set, Ok(()));
+        let set = client
+            .set_alarm(4, AlarmEdit::Volume(50.))
+            .unwrap()
+            .wait()
+            .unwrap();
+        assert_eq!(set, Err(AlarmError::NoSuchAlarm(4)));
+        // subscriptions see everything but the answers
+        assert_eq!(events.next(), Some(ServerMessage::AlarmRinging(7)));
+        assert_eq!(events.next(), Some(ServerMessage::AlaramAdded(alarm)));
+        for _ in 0..6 {
+            assert_eq!(events.next(), Some(ServerMessage::AlarmRinging(7)));
+        }
+        assert_eq!(
+            events.next(),
+            Some(ServerMessage::AlarmSet(3, AlarmEdit::Volume(50.)))
+        );
+        assert_eq!(events.next(), Some(ServerMessage::AlarmRinging(7)));
+    }
+
+    /// a remote daemon on loopback that answers pings from clients with the right token
+    fn fake_remote_daemon() -> Remote {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        thread::spawn(move || {
+            for conn in listener.incoming() {
+                let mut recv = BufReader::new(conn.unwrap());
+                thread::spawn(move || {
+                    // closes the connection if its wrong
+                    let peer = recv.get_ref().peer_addr()?.ip();
+                    crate::remote::accept_token(
+                        &mut recv,
+                        "secret",
+                        peer,
+                        &crate::remote::Backoff::default(),
+                    )?;
+                    let mut send = recv.get_ref().try_clone()?;
+                    let encoding = Encoding::accept(&mut recv)?;
+                    let mut bytes = Vec::new();
+                    while let Ok(ClientMessage::Request(id, message)) =
+                        encoding.recieve(&mut recv, &mut bytes)
+                    {
+                        if *message == ClientMessage::Ping {
+                            let answer = ServerMessage::Pong(42).respond_to(Some(id));
+                            encoding.send(&mut send, &answer)?;
+                        }
+                    }
+                    io::Result::Ok(())
+                });
+            }
+        });
+        Remote::new(&address.to_string(), "secret".to_string())
+    }
+
+    #[test]
+    fn remote_clients_need_the_token() {
+        let remote = fake_remote_daemon();
+        let client = Client::connect(Target::Remote(remote.clone())).unwrap();
+        assert_eq!(client.ping().unwrap().wait().unwrap(), 42);
+        let wrong = Remote::new(&remote.host, "guess".to_string());
+        // the daemon might hang up before the handshake or the ping is even sent
+        let error = Client::connect(Target::Remote(wrong))
+            .and_then(|client| client.ping()?.wait())
+            .unwrap_err();
+        assert!(
+            matches!(
+                error.kind(),
+                io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionReset
+            ),
+            "{error}"
+        );
+    }
+
+    #[test]
+    fn invalid_messages_end_subscriptions() {
+        let client = Client::connect(fake_daemon("invalid")).unwrap();
+        let events = client.subscribe();
+        client.send(&ClientMessage::GetExceptions).unwrap();
+        let events: Vec<_> = events.collect();
+        assert_eq!(events, [ServerMessage::AlarmRinging(7)]);
+    }
+
+    #[test]
+    fn requests_time_out() {
+        let mut client = Client::connect(fake_daemon("timeout")).unwrap();
+        client.set_timeout(Duration::from_millis(200));
+        let error = client.remove_alarm(3).unwrap().wait().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+        let mut pending = client.remove_alarm(3).unwrap();
+        thread::sleep(Duration::from_millis(300));
+        let error = pending.try_answer().unwrap().unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::TimedOut);
+    }
+}
@@ -1,19 +1,89 @@
 use core::fmt;
-use std::{collections::HashMap, hash::Hash, path::PathBuf};
+use std::{
+    collections::{HashMap, HashSet},
+    hash::Hash,
+    path::PathBuf,
+    sync::atomic::{AtomicU64, Ordering},
+};
 
-use chrono::NaiveTime;
-use serde::{Deserialize, Serialize};
+use chrono::{Datelike, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Deserializer, Serialize, de};
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+use crate::{AlarmEdit, Change, SoundRemoval, SoundRemovalError, cron};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 // idea is to have ids be non persistant so users do not have to worry about manually enteriing
 // ids, but maybe better idea is:
 // alarms use ids
 // sounds are by name. (as sounds are referenced so they need to have a presistant way to
 // refrence them)
 pub struct Config {
+    #[serde(deserialize_with = "load_alarms")]
     pub alarms: Collection<u64, Alarm>,
     #[serde(flatten)]
     pub sounds: Sounds,
+    #[serde(default)]
+    pub log: Logging,
+    /// controlling the daemon over TCP, off by default
+    #[serde(default)]
+    pub remote: crate::remote::Config,
+    /// what happens to alarms that won't ring again
+    #[serde(default)]
+    pub expired: Expiry,
+    /// days no alarm rings on, like public holidays
+    #[serde(default)]
+    pub exceptions: Vec<Exception>,
+}
+
+/// Days an alarm doesn't ring on, like a holiday or a vacation.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Exception {
+    #[serde(default)]
+    pub name: Option<String>,
+    pub start: NaiveDate,
+    /// the last day, the same as `start` for a single day
+    pub end: NaiveDate,
+}
+
+impl Exception {
+    /// just `day`
+    #[must_use]
+    pub const fn day(day: NaiveDate) -> Self {
+        Self {
+            name: None,
+            start: day,
+            end: day,
+        }
+    }
+
+    #[must_use]
+    pub fn contains(&self, day: NaiveDate) -> bool {
+        (self.start..=self.end).contains(&day)
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Some(name) = &self.name {
+            write!(f, "{name} ")?;
+        }
+        if self.start == self.end {
+            write!(f, "({})", self.start)
+        } else {
+            write!(f, "({} to {})", self.start, self.end)
+        }
+    }
+}
+
+/// what happens to alarms that won't ring again (past their [`Alarm::end`] or [`Alarm::date`])
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Expiry {
+    /// disable them, so they can be given new dates
+    #[default]
+    Disable,
+    /// remove them
+    Delete,
 }
 // https://stackoverflow.com/questions/79314434/rust-serde-serialization-to-from-vec-into-hashmap
 pub trait GetId<T> {
@@ -21,8 +91,14 @@ pub trait GetId<T> {
 }
 
 /// Serializable collection
-#[derive(Serialize, Deserialize, Clone, Debug)]
-#[serde(from = "Vec<V>", into = "Vec<V>")]
+///
+/// It's (de)serialized as a list, which is refused if two items in it have the same id.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(
+    try_from = "Vec<V>",
+    into = "Vec<V>",
+    bound(deserialize = "K: fmt::Display, V: Deserialize<'de>")
+)]
 pub struct Collection<K, V>
 where
     K: Eq + Hash + Clone,
@@ -61,17 +137,35 @@ where
     }
 }
 
-impl<K, V> From<Vec<V>> for Collection<K, V>
+/// two items of a [`Collection`] with the same id
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DuplicateId<K>(pub K);
+
+impl<K: fmt::Display> fmt::Display for DuplicateId<K> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "more than one item has the id {}", self.0)
+    }
+}
+
+impl<K: fmt::Display + fmt::Debug> std::error::Error for DuplicateId<K> {}
+
+impl<K, V> TryFrom<Vec<V>> for Collection<K, V>
 where
     K: Eq + Hash + Clone,
     V: GetId<K> + Clone,
 {
-    fn from(value: Vec<V>) -> Self {
+    type Error = DuplicateId<K>;
+
+    // one of them would be lost otherwise
+    fn try_from(value: Vec<V>) -> Result<Self, Self::Error> {
         let mut obj: Self = Self::new();
-        value.into_iter().for_each(|v| {
-            obj.insert(v);
-        });
-        obj
+        for v in value {
+            let id = v.get_id().clone();
+            if obj.insert(v).is_some() {
+                return Err(DuplicateId(id));
+            }
+        }
+        Ok(obj)
     }
 }
 
@@ -84,7 +178,29 @@ where
         Self::from_iter(val.data.into_values())
     }
 }
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// how the daemon logs (see [`crate::logging`])
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+#[serde(default)]
+pub struct Logging {
+    /// the log level, or a filter like `info,roosty_clockd::sound=debug`
+    pub level: String,
+    /// how big (in bytes) the log file gets before starting a new one
+    pub max_file_size: u64,
+    /// how many old log files to keep around
+    pub keep_files: usize,
+}
+
+impl Default for Logging {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            max_file_size: 1024 * 1024,
+            keep_files: 5,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Sounds {
     pub sounds: HashMap<String, Sound>,
     pub default_sound: String,
@@ -105,11 +221,17 @@ impl Default for Config {
                     ("tick tock".to_string(), Sound::tick_tock()),
                     ("beep beep".to_string(), Sound::beep_beep()),
                     ("raing".to_string(), Sound::rain()),
+                    ("tone".to_string(), Sound::tone()),
+                    ("chime".to_string(), Sound::chime()),
                 ]
                 .into_iter()
                 .collect(),
                 default_sound: "beep beep".to_string(),
             },
+            log: Logging::default(),
+            remote: crate::remote::Config::default(),
+            expired: Expiry::default(),
+            exceptions: Vec::new(),
         }
     }
 }
@@ -126,10 +248,87 @@ impl Config {
         toml::from_str(&config).expect("couldn't parse config file")
     }
 
+    /// The changes that deal with alarms that expired by `now` (see [`Config::expired`]). Ringing
+    /// alarms expire once they are stopped.
+    #[must_use]
+    pub fn expire(&self, ringing_alarms: &HashSet<u64>, now: NaiveDateTime) -> Vec<Change> {
+        self.alarms
+            .data
+            .values()
+            .filter(|alarm| {
+                !ringing_alarms.contains(&alarm.id) && alarm.expired(now, &self.exceptions)
+            })
+            .filter_map(|alarm| match self.expired {
+                Expiry::Disable => alarm
+                    .enabled
+                    .then_some(Change::Set(alarm.id, AlarmEdit::Enable(false))),
+                Expiry::Delete => Some(Change::Removed(alarm.id)),
+            })
+            .collect()
+    }
+
+    /// The edits that move alarms off `sound` so it can be removed, grouped by alarm. The sound
+    /// is replaced by the default one and taken out of playlists.
+    ///
+    /// # Errors
+    /// If the sound doesn't exist, is the default or is still used and `removal` refuses that.
+    pub fn sound_removal(
+        &self,
+        sound: &str,
+        removal: SoundRemoval,
+    ) -> Result<Vec<(u64, AlarmEdit)>, SoundRemovalError> {
+        let affected: Vec<_> = self
+            .alarms
+            .data
+            .values()
+            .filter(|alarm| alarm.uses_sound(sound))
+            .collect();
+        if !self.sounds.sounds.contains_key(sound) {
+            return Err(SoundRemovalError::NotFound);
+        }
+        if sound == self.sounds.default_sound {
+            return Err(SoundRemovalError::IsDefault);
+        }
+        if removal == SoundRemoval::Refuse && !affected.is_empty() {
+            return Err(SoundRemovalError::InUse(
+                affected.iter().map(|alarm| alarm.id).collect(),
+            ));
+        }
+        let mut edits = Vec::new();
+        for alarm in affected {
+            if alarm.sound == sound {
+                edits.push((
+                    alarm.id,
+                    AlarmEdit::Sound(self.sounds.default_sound.clone()),
+                ));
+            }
+            if alarm.playlist.iter().any(|s| s == sound) {
+                let playlist = alarm.playlist.iter().filter(|s| *s != sound).cloned();
+                edits.push((alarm.id, AlarmEdit::Playlist(playlist.collect())));
+            }
+            if removal == SoundRemoval::Disable {
+                edits.push((alarm.id, AlarmEdit::Enable(false)));
+            }
+        }
+        Ok(edits)
+    }
+
+    /// Like [`Config::load`] but doesn't panic if the config is broken.
+    ///
+    /// # Errors
+    /// If the file couldn't be read or parsed.
+    pub fn try_load(path: PathBuf) -> Result<Self, Box<dyn std::error::Error>> {
+        let config = std::fs::read_to_string(path)?;
+        Ok(toml::from_str(&config)?)
+    }
+
     pub fn save(&self, path: PathBuf) {
         let config = toml::to_string(self).expect("couldn't serialize config");
         std::fs::create_dir_all(path.parent().unwrap()).expect("couldn't create config dir");
-        std::fs::write(path, config).expect("couldn't write config file");
+        // written next to it and then moved over it, so the config is never half written
+        let temp = path.with_extension("toml.tmp");
+        std::fs::write(&temp, config).expect("couldn't write config file");
+        std::fs::rename(temp, path).expect("couldn't write config file");
     }
 
     #[must_use]
@@ -152,6 +351,28 @@ impl Config {
         path
     }
 
+    /// where the daemon keeps the alarm history (see [`crate::history`])
+    #[must_use]
+    pub fn history_path() -> PathBuf {
+        let mut path = directories::ProjectDirs::from("", "", "roosty_clockd")
+            .expect("couldn't get history path")
+            .data_dir()
+            .to_path_buf();
+        path.push("history.jsonl");
+        path
+    }
+
+    /// where the daemon writes its logs
+    #[must_use]
+    pub fn log_path() -> PathBuf {
+        let mut path = directories::ProjectDirs::from("", "", "roosty_clockd")
+            .expect("couldn't get log directory path")
+            .data_local_dir()
+            .to_path_buf();
+        path.push("log");
+        path
+    }
+
     #[must_use]
     pub fn is_config_present() -> bool {
         Self::config_path().exists()
@@ -164,13 +385,35 @@ pub const fn always_true() -> bool {
     true
 }
 
-static mut UID: u64 = 0;
+/// the last id that was handed out or loaded
+static UID: AtomicU64 = AtomicU64::new(UNASSIGNED);
+
+/// the id of an alarm in the config file that doesn't have one yet, [`get_uid`] never hands it out
+const UNASSIGNED: u64 = 0;
+
+const fn unassigned() -> u64 {
+    UNASSIGNED
+}
+
+/// A new id, after every one that was handed out or loaded.
 pub fn get_uid() -> u64 {
-    // SAFETY: this is only called from main server thread
-    unsafe {
-        UID += 1;
-        UID
+    UID.fetch_add(1, Ordering::SeqCst) + 1
+}
+
+/// Loads the alarms in the config file. Ids are saved so that the history stays attached to the
+/// right alarm, so alarms without one get an id after all of the saved ones (an alarm further down
+/// could have the next id otherwise).
+fn load_alarms<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Collection<u64, Alarm>, D::Error> {
+    let mut alarms = Vec::<Alarm>::deserialize(deserializer)?;
+    for alarm in &alarms {
+        UID.fetch_max(alarm.id, Ordering::SeqCst);
     }
+    for alarm in alarms.iter_mut().filter(|alarm| alarm.id == UNASSIGNED) {
+        alarm.id = get_uid();
+    }
+    Collection::try_from(alarms).map_err(de::Error::custom)
 }
 
 impl GetId<u64> for Alarm {
@@ -178,32 +421,343 @@ impl GetId<u64> for Alarm {
         &self.id
     }
 }
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Alarm {
     pub name: Option<String>,
     pub time: NaiveTime,
     pub volume: f32,
     #[serde(default = "Sound::get_default_name")]
     pub sound: String,
+    /// sounds to pick from when the [`SoundMode`] is not [`SoundMode::Single`]
+    #[serde(default)]
+    pub playlist: Vec<String>,
+    #[serde(default)]
+    pub sound_mode: SoundMode,
     #[serde(default = "always_true")]
     pub enabled: bool,
-    #[serde(skip, default = "get_uid")]
+    /// don't ring before this day
+    #[serde(default)]
+    pub start: Option<NaiveDate>,
+    /// don't ring after this day
+    #[serde(default)]
+    pub end: Option<NaiveDate>,
+    /// only ring on this day
+    #[serde(default)]
+    pub date: Option<NaiveDate>,
+    /// a day it skips (but still rings on the days after it)
+    #[serde(default)]
+    pub skip: Option<NaiveDate>,
+    /// days it doesn't ring on, as well as the ones in [`Config::exceptions`]
+    #[serde(default)]
+    pub exceptions: Vec<Exception>,
+    /// a cron expression (see [`cron`](crate::cron)) for when it rings, instead of at `time`
+    /// every day
+    #[serde(default)]
+    pub cron: Option<String>,
+    /// see [`get_uid`]
+    #[serde(default = "unassigned")]
     pub id: u64,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+/// how an alarm picks what to play
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundMode {
+    /// only play [`Alarm::sound`]
+    #[default]
+    Single,
+    /// play the playlist in order
+    Playlist,
+    /// play the playlist in a random order
+    Shuffle,
+    /// play one sound from the playlist, a different one every day
+    Daily,
+}
+
+impl Alarm {
+    /// the loudest an alarm can be (in percent)
+    pub const MAX_VOLUME: f32 = 200.;
+
+    pub fn apply(&mut self, edit: AlarmEdit) {
+        match edit {
+            AlarmEdit::Time(time) => self.time = time,
+            AlarmEdit::Name(name) => self.name = name,
+            AlarmEdit::Sound(sound) => self.sound = sound,
+            AlarmEdit::Playlist(playlist) => self.playlist = playlist,
+            AlarmEdit::SoundMode(sound_mode) => self.sound_mode = sound_mode,
+            AlarmEdit::Volume(volume) => self.volume = volume,
+            AlarmEdit::Enable(enabled) => self.enabled = enabled,
+            AlarmEdit::Start(start) => self.start = start,
+            AlarmEdit::End(end) => self.end = end,
+            AlarmEdit::Date(date) => {
+                // a one-off alarm has no range of days
+                if date.is_some() {
+                    self.start = None;
+                    self.end = None;
+                }
+                self.date = date;
+            }
+            AlarmEdit::Skip(skip) => self.skip = skip,
+            AlarmEdit::Exceptions(exceptions) => self.exceptions = exceptions,
+            AlarmEdit::Cron(cron) => self.cron = cron,
+        }
+    }
+
+    /// if the alarm rings on `day` going by its dates (whether it's enabled isn't checked)
+    #[must_use]
+    pub fn rings_on(&self, day: NaiveDate) -> bool {
+        self.date.is_none_or(|date| date == day)
+            && self.start.is_none_or(|start| start <= day)
+            && self.end.is_none_or(|end| day <= end)
+    }
+
+    /// The next time the alarm rings at or after `from`, `None` if it never does again (or its
+    /// cron expression is invalid). It doesn't ring on its skipped day, its exceptions or
+    /// `holidays`.
+    #[must_use]
+    pub fn next_ring(&self, from: NaiveDateTime, holidays: &[Exception]) -> Option<NaiveDateTime> {
+        let schedule = self
+            .cron
+            .as_deref()
+            .map(str::parse::<cron::Schedule>)
+            .transpose()
+            .ok()?;
+        // skip ahead to the first day it could ring on
+        let first_day = self
+            .start
+            .max(self.date)
+            .map(|day| day.and_time(NaiveTime::MIN));
+        let mut from = from.max(first_day.unwrap_or(from));
+        let skip = self.skip.map(Exception::day);
+        loop {
+            let next = match &schedule {
+                Some(schedule) => schedule.next(from)?,
+                None if from.time() <= self.time => from.date().and_time(self.time),
+                None => from.date().succ_opt()?.and_time(self.time),
+            };
+            let day = next.date();
+            // exceptions can overlap, so until none of them has the day
+            let Some(exception) = self
+                .exceptions
+                .iter()
+                .chain(holidays)
+                .chain(&skip)
+                .find(|exception| exception.contains(day))
+            else {
+                return self.rings_on(day).then_some(next);
+            };
+            from = exception.end.succ_opt()?.and_time(NaiveTime::MIN);
+        }
+    }
+
+    /// The next `count` times it rings at or after `from`, like
+    /// [`cron::Schedule::upcoming`](crate::cron::Schedule::upcoming).
+    #[must_use]
+    pub fn upcoming(
+        &self,
+        from: NaiveDateTime,
+        holidays: &[Exception],
+        count: usize,
+    ) -> Vec<NaiveDateTime> {
+        std::iter::successors(self.next_ring(from, holidays), |last| {
+            self.next_ring(*last + chrono::Duration::minutes(1), holidays)
+        })
+        .take(count)
+        .collect()
+    }
+
+    /// if the alarm has a last day and it rang for the last time before `now`
+    #[must_use]
+    pub fn expired(&self, now: NaiveDateTime, holidays: &[Exception]) -> bool {
+        (self.date.is_some() || self.end.is_some()) && self.next_ring(now, holidays).is_none()
+    }
+
+    /// The edits that turn this alarm into `new` (the id is not compared).
+    #[must_use]
+    pub fn edits(&self, new: &Self) -> Vec<AlarmEdit> {
+        let mut edits = Vec::new();
+        if self.name != new.name {
+            edits.push(AlarmEdit::Name(new.name.clone()));
+        }
+        if self.time != new.time {
+            edits.push(AlarmEdit::Time(new.time));
+        }
+        if self.sound != new.sound {
+            edits.push(AlarmEdit::Sound(new.sound.clone()));
+        }
+        if self.playlist != new.playlist {
+            edits.push(AlarmEdit::Playlist(new.playlist.clone()));
+        }
+        if self.sound_mode != new.sound_mode {
+            edits.push(AlarmEdit::SoundMode(new.sound_mode));
+        }
+        #[allow(clippy::float_cmp)]
+        if self.volume != new.volume {
+            edits.push(AlarmEdit::Volume(new.volume));
+        }
+        if self.enabled != new.enabled {
+            edits.push(AlarmEdit::Enable(new.enabled));
+        }
+        // first, so that the old date doesn't get in the way of the new start and end
+        if self.date != new.date {
+            edits.push(AlarmEdit::Date(new.date));
+        }
+        if self.start != new.start {
+            edits.push(AlarmEdit::Start(new.start));
+        }
+        if self.end != new.end {
+            edits.push(AlarmEdit::End(new.end));
+        }
+        if self.skip != new.skip {
+            edits.push(AlarmEdit::Skip(new.skip));
+        }
+        if self.exceptions != new.exceptions {
+            edits.push(AlarmEdit::Exceptions(new.exceptions.clone()));
+        }
+        if self.cron != new.cron {
+            edits.push(AlarmEdit::Cron(new.cron.clone()));
+        }
+        edits
+    }
+
+    /// The sounds to play (in order) when the alarm rings on `date`.
+    ///
+    /// Falls back to [`Alarm::sound`] if the playlist is empty.
+    #[must_use]
+    pub fn sounds_for(&self, date: NaiveDate) -> Vec<&str> {
+        let mut sounds: Vec<_> = self.playlist.iter().map(String::as_str).collect();
+        if sounds.is_empty() {
+            return vec![&self.sound];
+        }
+        match self.sound_mode {
+            SoundMode::Single => vec![&self.sound],
+            SoundMode::Playlist => sounds,
+            SoundMode::Shuffle => {
+                fastrand::shuffle(&mut sounds);
+                sounds
+            }
+            SoundMode::Daily => {
+                // seeded by the day so the pick stays the same if the alarm rings more than once
+                // a day
+                let pick = |day: i32, below: usize| {
+                    fastrand::Rng::with_seed(u64::from(day.unsigned_abs())).usize(..below)
+                };
+                let day = date.num_days_from_ce();
+                let i = match sounds.len() {
+                    1 => 0,
+                    // there is no other way to never play the same one twice in a row
+                    2 => day.rem_euclid(2).unsigned_abs() as usize,
+                    // even days pick any sound, the days between them one neither of them did
+                    len if day % 2 == 0 => pick(day, len),
+                    len => {
+                        let taken = [pick(day - 1, len), pick(day + 1, len)];
+                        let free: Vec<_> = (0..len).filter(|i| !taken.contains(i)).collect();
+                        free[pick(day, free.len())]
+                    }
+                };
+                vec![sounds[i]]
+            }
+        }
+    }
+
+    /// if the alarm plays `sound` in any way
+    #[must_use]
+    pub fn uses_sound(&self, sound: &str) -> bool {
+        self.sound == sound || self.playlist.iter().any(|s| s == sound)
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Sound {
     pub name: String,
+    #[serde(default)]
+    pub kind: SoundKind,
+    // only used for file sounds
+    #[serde(default)]
     pub path: PathBuf,
+    // only used for synthesized sounds, if not set the defaults are used
+    #[serde(default)]
+    pub synth: Option<Synth>,
+}
+
+/// where the audio of a sound comes from
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SoundKind {
+    /// an audio file at [`Sound::path`]
+    #[default]
+    File,
+    /// a beep with a [`Waveform`] at [`Synth::pitch`]
+    Tone,
+    /// alternates between [`Synth::pitch`] and [`Synth::second_pitch`]
+    Chime,
+    /// [`Synth::noise`] colored noise
+    Noise,
 }
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Waveform {
+    #[default]
+    Sine,
+    Square,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum NoiseColor {
+    #[default]
+    White,
+    Pink,
+    Brown,
+}
+
+/// parameters for synthesized sounds (see [`crate::sound::Synth`])
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct Synth {
+    pub waveform: Waveform,
+    /// in hz
+    pub pitch: f32,
+    /// in hz, only used for chimes
+    pub second_pitch: f32,
+    /// how long each beep is in milliseconds, can't be 0
+    #[serde(deserialize_with = "beep_length")]
+    pub on_ms: u32,
+    /// how long the silence between beeps is in milliseconds, 0 for a continuous sound
+    pub off_ms: u32,
+    pub noise: NoiseColor,
+}
+
+/// Refuses beeps that are 0 ms long, they would make an alarm silent.
+fn beep_length<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u32, D::Error> {
+    match u32::deserialize(deserializer)? {
+        0 => Err(de::Error::custom("on_ms can't be 0")),
+        ms => Ok(ms),
+    }
+}
+
+impl Default for Synth {
+    fn default() -> Self {
+        Self {
+            waveform: Waveform::Sine,
+            pitch: 880.,
+            second_pitch: 660.,
+            on_ms: 250,
+            off_ms: 250,
+            noise: NoiseColor::White,
+        }
+    }
+}
+
 impl fmt::Display for Sound {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(
-            f,
-            "{}:{}",
-            self.name,
-            self.path.file_name().unwrap().to_string_lossy()
-        )
+        match self.path.file_name() {
+            Some(file_name) if self.kind == SoundKind::File => {
+                write!(f, "{}:{}", self.name, file_name.to_string_lossy())
+            }
+            _ => write!(f, "{}:{:?}", self.name, self.kind),
+        }
     }
 }
 
@@ -221,47 +775,81 @@ impl Sound {
 
     #[must_use]
     pub const fn new(name: String, path: PathBuf) -> Self {
-        Self { name, path }
+        Self {
+            name,
+            kind: SoundKind::File,
+            path,
+            synth: None,
+        }
     }
 
     #[must_use]
-    pub fn ring() -> Self {
+    pub const fn synthesized(name: String, kind: SoundKind, synth: Synth) -> Self {
         Self {
-            name: "ring".to_string(),
-            path: Config::sounds_path().join("ring.mp3"),
+            name,
+            kind,
+            path: PathBuf::new(),
+            synth: Some(synth),
         }
     }
 
+    #[must_use]
+    pub fn tone() -> Self {
+        Self::synthesized(
+            "tone".to_string(),
+            SoundKind::Tone,
+            Synth {
+                waveform: Waveform::Square,
+                ..Synth::default()
+            },
+        )
+    }
+
+    #[must_use]
+    pub fn chime() -> Self {
+        Self::synthesized(
+            "chime".to_string(),
+            SoundKind::Chime,
+            Synth {
+                on_ms: 800,
+                off_ms: 400,
+                ..Synth::default()
+            },
+        )
+    }
+
+    #[must_use]
+    pub fn ring() -> Self {
+        Self::new("ring".to_string(), Config::sounds_path().join("ring.mp3"))
+    }
+
     #[must_use]
     pub fn bing_bong() -> Self {
-        Self {
-            name: "bing bong".to_string(),
-            path: Config::sounds_path().join("bing_bong.mp3"),
-        }
+        Self::new(
+            "bing bong".to_string(),
+            Config::sounds_path().join("bing_bong.mp3"),
+        )
     }
 
     #[must_use]
     pub fn tick_tock() -> Self {
-        Self {
-            name: "tick tock".to_string(),
-            path: Config::sounds_path().join("tick_tock.mp3"),
-        }
+        Self::new(
+            "tick tock".to_string(),
+            Config::sounds_path().join("tick_tock.mp3"),
+        )
     }
 
     #[must_use]
     pub fn beep_beep() -> Self {
-        Self {
-            name: "beep beep".to_string(),
-            path: Config::sounds_path().join("beep_beep.mp3"),
-        }
+        Self::new(
+            "beep beep".to_string(),
+            Config::sounds_path().join("beep_beep.mp3"),
+        )
     }
 
     #[must_use]
     pub fn rain() -> Self {
-        Self {
-            name: "rain".to_string(),
-            path: Config::sounds_path().join("rain.mp3"),
-        }
+        Self::new("rain".to_string(), Config::sounds_path().join("rain.mp3"))
     }
 
     #[must_use]
@@ -269,3 +857,525 @@ impl Sound {
         self.name.as_ref()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use proptest::prelude::*;
+
+    use super::*;
+
+    fn date() -> impl Strategy<Value = NaiveDate> {
+        (2000..2100, 1..=365_u32).prop_map(|(year, day)| NaiveDate::from_yo_opt(year, day).unwrap())
+    }
+
+    fn exception() -> impl Strategy<Value = Exception> {
+        (any::<Option<String>>(), date(), 0..30_u64).prop_map(|(name, start, days)| Exception {
+            name,
+            start,
+            end: start + chrono::Days::new(days),
+        })
+    }
+
+    /// alarms without skipped days or exceptions, so [`Alarm::rings_on`] says when they ring
+    fn alarm() -> impl Strategy<Value = Alarm> {
+        (
+            (
+                any::<Option<String>>(),
+                0..24_u32,
+                0..60_u32,
+                0..60_u32,
+                0.0..=Alarm::MAX_VOLUME,
+            ),
+            (
+                any::<String>(),
+                prop::collection::vec(any::<String>(), 0..4),
+                prop_oneof![
+                    Just(SoundMode::Single),
+                    Just(SoundMode::Playlist),
+                    Just(SoundMode::Shuffle),
+                    Just(SoundMode::Daily),
+                ],
+                any::<bool>(),
+            ),
+            [
+                prop::option::of(date()),
+                prop::option::of(date()),
+                prop::option::of(date()),
+            ],
+        )
+            .prop_map(
+                |(
+                    (name, hour, min, sec, volume),
+                    (sound, playlist, sound_mode, enabled),
+                    [start, end, date],
+                )| Alarm {
+                    name,
+                    time: NaiveTime::from_hms_opt(hour, min, sec).unwrap(),
+                    volume,
+                    sound,
+                    playlist,
+                    sound_mode,
+                    enabled,
+                    start,
+                    end,
+                    date,
+                    skip: None,
+                    exceptions: Vec::new(),
+                    cron: None,
+                    id: 0,
+                },
+            )
+    }
+
+    /// alarms with different ids (small enough for toml, which only has signed integers, and not
+    /// [`UNASSIGNED`])
+    fn alarms() -> impl Strategy<Value = Vec<Alarm>> {
+        prop::collection::btree_map(1..1_u64 << 32, alarm(), 0..8).prop_map(|alarms| {
+            alarms
+                .into_iter()
+                .map(|(id, alarm)| Alarm { id, ..alarm })
+                .collect()
+        })
+    }
+
+    fn sound() -> impl Strategy<Value = Sound> {
+        (
+            any::<String>(),
+            prop_oneof![
+                Just(SoundKind::File),
+                Just(SoundKind::Tone),
+                Just(SoundKind::Chime),
+                Just(SoundKind::Noise),
+            ],
+            any::<String>(),
+            prop::option::of((
+                20.0..20_000_f32,
+                20.0..20_000_f32,
+                1..=u32::MAX,
+                any::<u32>(),
+            )),
+        )
+            .prop_map(|(name, kind, path, synth)| Sound {
+                name,
+                kind,
+                path: path.into(),
+                synth: synth.map(|(pitch, second_pitch, on_ms, off_ms)| Synth {
+                    pitch,
+                    second_pitch,
+                    on_ms,
+                    off_ms,
+                    ..Synth::default()
+                }),
+            })
+    }
+
+    fn config() -> impl Strategy<Value = Config> {
+        (
+            alarms(),
+            prop::collection::hash_map(any::<String>(), sound(), 0..4),
+            any::<String>(),
+            any::<Option<String>>(),
+            prop_oneof![Just(Expiry::Disable), Just(Expiry::Delete)],
+            prop::collection::vec(exception(), 0..4),
+        )
+            .prop_map(
+                |(alarms, sounds, default_sound, token, expired, exceptions)| Config {
+                    alarms: alarms.try_into().unwrap(),
+                    sounds: Sounds {
+                        sounds,
+                        default_sound,
+                    },
+                    log: Logging::default(),
+                    remote: crate::remote::Config {
+                        listen: Vec::new(),
+                        token,
+                    },
+                    expired,
+                    exceptions,
+                },
+            )
+    }
+
+    proptest! {
+        #[test]
+        fn configs_round_trip(config in config()) {
+            let saved = toml::to_string(&config).unwrap();
+            prop_assert_eq!(toml::from_str::<Config>(&saved).unwrap(), config);
+        }
+
+        #[test]
+        fn alarms_ring_when_their_dates_say(alarm in alarm(), from in date(), hour in 0..24_u32) {
+            let from = from.and_hms_opt(hour, 0, 0).unwrap();
+            if let Some(next) = alarm.next_ring(from, &[]) {
+                prop_assert!(next >= from);
+                prop_assert_eq!(next.time(), alarm.time);
+                prop_assert!(alarm.rings_on(next.date()));
+                // and not on any day before it
+                let mut day = from.date();
+                while day < next.date() {
+                    prop_assert!(!alarm.rings_on(day) || day.and_time(alarm.time) < from);
+                    day = day.succ_opt().unwrap();
+                }
+            } else {
+                prop_assert!(alarm.date.is_some() || alarm.end.is_some());
+            }
+        }
+
+        #[test]
+        fn daily_sounds_change_every_day(sounds in 1..6_usize, from in date()) {
+            let alarm = Alarm {
+                playlist: (0..sounds).map(|i| i.to_string()).collect(),
+                sound_mode: SoundMode::Daily,
+                ..seven_am(1)
+            };
+            let mut yesterday = alarm.sounds_for(from.pred_opt().unwrap());
+            for day in from.iter_days().take(60) {
+                let today = alarm.sounds_for(day);
+                prop_assert_eq!(today.len(), 1);
+                prop_assert!(alarm.playlist.iter().any(|sound| sound == today[0]));
+                // it doesn't change if the alarm rings again that day
+                prop_assert_eq!(&alarm.sounds_for(day), &today);
+                if sounds > 1 {
+                    prop_assert_ne!(&today, &yesterday);
+                }
+                yesterday = today;
+            }
+        }
+
+        #[test]
+        fn collections_keep_every_alarm(alarms in alarms()) {
+            let collection = Collection::try_from(alarms.clone()).unwrap();
+            let mut kept: Vec<Alarm> = collection.into();
+            kept.sort_by_key(|alarm| alarm.id);
+            prop_assert_eq!(kept, alarms);
+        }
+
+        #[test]
+        fn duplicate_ids_are_refused(alarms in alarms(), copy in any::<prop::sample::Index>()) {
+            prop_assume!(!alarms.is_empty());
+            let copy = alarms[copy.index(alarms.len())].clone();
+            let id = copy.id;
+            let mut alarms = alarms;
+            alarms.push(Alarm { name: Some("copy".to_string()), ..copy });
+            prop_assert_eq!(
+                Collection::<u64, Alarm>::try_from(alarms).unwrap_err(),
+                DuplicateId(id)
+            );
+        }
+    }
+
+    fn day(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, 11, day).unwrap()
+    }
+
+    fn at(day: u32, hour: u32) -> NaiveDateTime {
+        self::day(day).and_hms_opt(hour, 0, 0).unwrap()
+    }
+
+    fn seven_am(id: u64) -> Alarm {
+        Alarm {
+            name: None,
+            time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            volume: 100.,
+            sound: Sound::get_default_name(),
+            playlist: Vec::new(),
+            sound_mode: SoundMode::Single,
+            enabled: true,
+            start: None,
+            end: None,
+            date: None,
+            skip: None,
+            exceptions: Vec::new(),
+            cron: None,
+            id,
+        }
+    }
+
+    #[test]
+    fn playlists_play_in_their_mode() {
+        let playlist = ["a", "b", "c", "d", "e", "f"].map(str::to_string).to_vec();
+        let alarm = |sound_mode| Alarm {
+            playlist: playlist.clone(),
+            sound_mode,
+            ..seven_am(1)
+        };
+        let default = Sound::get_default_name();
+        assert_eq!(alarm(SoundMode::Single).sounds_for(day(1)), [&default]);
+        assert_eq!(alarm(SoundMode::Playlist).sounds_for(day(1)), playlist);
+        // every sound once, just not always in order
+        let shuffled: Vec<_> = (0..20)
+            .map(|_| alarm(SoundMode::Shuffle).sounds_for(day(1)).join(","))
+            .collect();
+        for order in &shuffled {
+            let mut sorted: Vec<_> = order.split(',').collect();
+            sorted.sort_unstable();
+            assert_eq!(sorted, playlist);
+        }
+        assert!(shuffled.iter().any(|order| *order != playlist.join(",")));
+        // without a playlist there is only the sound
+        let alarm = Alarm {
+            sound_mode: SoundMode::Shuffle,
+            ..seven_am(1)
+        };
+        assert_eq!(alarm.sounds_for(day(1)), [&default]);
+    }
+
+    #[test]
+    fn one_shot_alarms_ring_once() {
+        let alarm = Alarm {
+            date: Some(day(3)),
+            ..seven_am(1)
+        };
+        assert_eq!(alarm.next_ring(at(1, 12), &[]), Some(at(3, 7)));
+        assert_eq!(alarm.next_ring(at(3, 7), &[]), Some(at(3, 7)));
+        assert_eq!(alarm.next_ring(at(3, 8), &[]), None);
+        assert!(!alarm.expired(at(3, 7), &[]));
+        assert!(alarm.expired(at(3, 8), &[]));
+    }
+
+    #[test]
+    fn alarms_ring_between_start_and_end() {
+        let alarm = Alarm {
+            start: Some(day(10)),
+            end: Some(day(30)),
+            ..seven_am(1)
+        };
+        assert_eq!(alarm.next_ring(at(1, 12), &[]), Some(at(10, 7)));
+        assert_eq!(alarm.next_ring(at(12, 12), &[]), Some(at(13, 7)));
+        assert_eq!(alarm.next_ring(at(30, 12), &[]), None);
+        assert!(alarm.expired(at(30, 12), &[]));
+        // alarms without an end never expire
+        let alarm = Alarm {
+            start: Some(day(10)),
+            ..seven_am(1)
+        };
+        assert_eq!(
+            alarm.next_ring(at(30, 12), &[]),
+            Some(at(30, 12) + chrono::Duration::hours(19))
+        );
+        assert!(!alarm.expired(at(30, 12), &[]));
+    }
+
+    #[test]
+    fn alarms_dont_ring_on_skipped_days_or_exceptions() {
+        let holidays = [Exception {
+            name: Some("Christmas".to_string()),
+            start: day(24),
+            end: day(26),
+        }];
+        let alarm = Alarm {
+            skip: Some(day(2)),
+            exceptions: vec![Exception::day(day(3)), Exception::day(day(5))],
+            ..seven_am(1)
+        };
+        assert_eq!(alarm.next_ring(at(1, 12), &holidays), Some(at(4, 7)));
+        assert_eq!(alarm.next_ring(at(4, 12), &holidays), Some(at(6, 7)));
+        assert_eq!(alarm.next_ring(at(23, 12), &holidays), Some(at(27, 7)));
+        // a one-shot alarm on a holiday never rings
+        let alarm = Alarm {
+            date: Some(day(25)),
+            ..seven_am(2)
+        };
+        assert_eq!(alarm.next_ring(at(1, 12), &holidays), None);
+        assert!(alarm.expired(at(1, 12), &holidays));
+    }
+
+    #[test]
+    fn cron_alarms_ring_when_the_expression_matches() {
+        // every two hours from 8 to 12 on weekdays, 2026-11-06 is a friday
+        let alarm = Alarm {
+            cron: Some("0 8-12/2 * * MON-FRI".to_string()),
+            skip: Some(day(9)),
+            end: Some(day(10)),
+            ..seven_am(1)
+        };
+        assert_eq!(
+            alarm.upcoming(at(6, 9), &[], 5),
+            [at(6, 10), at(6, 12), at(10, 8), at(10, 10), at(10, 12)]
+        );
+        assert!(alarm.expired(at(10, 13), &[]));
+        // the last friday of the month, but not on a holiday
+        let alarm = Alarm {
+            cron: Some("0 7 * * 5L".to_string()),
+            ..seven_am(2)
+        };
+        let holidays = [Exception::day(day(27))];
+        assert_eq!(
+            alarm.next_ring(at(1, 0), &holidays),
+            Some(
+                NaiveDate::from_ymd_opt(2026, 12, 25)
+                    .unwrap()
+                    .and_hms_opt(7, 0, 0)
+                    .unwrap()
+            )
+        );
+        // an invalid expression never rings
+        let alarm = Alarm {
+            cron: Some("0 7 * *".to_string()),
+            ..seven_am(3)
+        };
+        assert_eq!(alarm.next_ring(at(1, 0), &[]), None);
+    }
+
+    #[test]
+    fn expired_alarms_are_disabled_or_deleted() {
+        let mut config = Config::new();
+        for alarm in [
+            Alarm {
+                end: Some(day(2)),
+                ..seven_am(1)
+            },
+            Alarm {
+                date: Some(day(2)),
+                ..seven_am(2)
+            },
+            Alarm {
+                date: Some(day(2)),
+                enabled: false,
+                ..seven_am(3)
+            },
+            seven_am(4),
+        ] {
+            config.alarms.insert(alarm);
+        }
+        // 2 is still ringing
+        let ringing = HashSet::from([2]);
+        let mut changes = config.expire(&ringing, at(3, 12));
+        changes.sort_by_key(Change::alarm);
+        assert_eq!(changes, [Change::Set(1, AlarmEdit::Enable(false))]);
+        config.expired = Expiry::Delete;
+        let mut changes = config.expire(&HashSet::new(), at(3, 12));
+        changes.sort_by_key(Change::alarm);
+        assert_eq!(
+            changes,
+            [Change::Removed(1), Change::Removed(2), Change::Removed(3)]
+        );
+    }
+
+    #[test]
+    fn removed_sounds_are_replaced_by_the_default() {
+        let mut config = Config::new();
+        config.alarms.insert(Alarm {
+            sound: "ring".to_string(),
+            ..seven_am(1)
+        });
+        assert_eq!(
+            config.sound_removal("ring", SoundRemoval::Refuse),
+            Err(SoundRemovalError::InUse(vec![1]))
+        );
+        assert_eq!(
+            config.sound_removal("ring", SoundRemoval::Reassign),
+            Ok(vec![(1, AlarmEdit::Sound("beep beep".to_string()))])
+        );
+        assert_eq!(
+            config.sound_removal("ring", SoundRemoval::Disable),
+            Ok(vec![
+                (1, AlarmEdit::Sound("beep beep".to_string())),
+                (1, AlarmEdit::Enable(false)),
+            ])
+        );
+        assert_eq!(
+            config.sound_removal("beep beep", SoundRemoval::Reassign),
+            Err(SoundRemovalError::IsDefault)
+        );
+        assert_eq!(
+            config.sound_removal("gong", SoundRemoval::Reassign),
+            Err(SoundRemovalError::NotFound)
+        );
+    }
+
+    #[test]
+    fn removed_sounds_are_taken_out_of_playlists() {
+        let mut config = Config::new();
+        config.alarms.insert(Alarm {
+            playlist: vec!["tone".to_string(), "chime".to_string()],
+            sound_mode: SoundMode::Shuffle,
+            ..seven_am(1)
+        });
+        assert_eq!(
+            config.sound_removal("chime", SoundRemoval::Refuse),
+            Err(SoundRemovalError::InUse(vec![1]))
+        );
+        assert_eq!(
+            config.sound_removal("chime", SoundRemoval::Reassign),
+            Ok(vec![(1, AlarmEdit::Playlist(vec!["tone".to_string()]))])
+        );
+    }
+
+    #[test]
+    fn unused_sounds_are_removed_without_edits() {
+        let mut config = Config::new();
+        config.alarms.insert(seven_am(1));
+        for removal in [
+            SoundRemoval::Refuse,
+            SoundRemoval::Reassign,
+            SoundRemoval::Disable,
+        ] {
+            assert_eq!(config.sound_removal("tick tock", removal), Ok(Vec::new()));
+        }
+    }
+
+    #[test]
+    fn configs_with_duplicate_ids_dont_load() {
+        let config = r#"
+            default_sound = "ring"
+            [sounds]
+            [[alarms]]
+            time = "07:00:00"
+            volume = 100.0
+            id = 3
+            [[alarms]]
+            time = "08:00:00"
+            volume = 100.0
+            id = 3
+        "#;
+        let error = toml::from_str::<Config>(config).unwrap_err();
+        assert!(error.to_string().contains("the id 3"), "{error}");
+    }
+
+    #[test]
+    fn synths_with_silent_beeps_dont_load() {
+        let sound = r#"
+            name = "silent"
+            kind = "tone"
+            [synth]
+            on_ms = 0
+        "#;
+        let error = toml::from_str::<Sound>(sound).unwrap_err();
+        assert!(error.to_string().contains("on_ms can't be 0"), "{error}");
+        let sound = sound.replace("on_ms = 0", "off_ms = 0");
+        assert_eq!(
+            toml::from_str::<Sound>(&sound)
+                .unwrap()
+                .synth
+                .unwrap()
+                .on_ms,
+            250
+        );
+    }
+
+    #[test]
+    fn alarms_without_ids_dont_take_saved_ones() {
+        let config = r#"
+            default_sound = "ring"
+            [sounds]
+            [[alarms]]
+            time = "07:00:00"
+            volume = 100.0
+            [[alarms]]
+            time = "08:00:00"
+            volume = 100.0
+            id = 1
+            [[alarms]]
+            time = "09:00:00"
+            volume = 100.0
+        "#;
+        let config = toml::from_str::<Config>(config).unwrap();
+        let mut ids: Vec<_> = config.alarms.data.keys().copied().collect();
+        ids.sort_unstable();
+        assert_eq!(ids.len(), 3);
+        assert_eq!(ids[0], 1);
+        // and ids handed out later are new too
+        assert!(get_uid() > ids[2]);
+    }
+}
@@ -0,0 +1,422 @@
+//! cron expressions, for alarms that don't ring at the same time every day, see
+//! [`config::Alarm::cron`](crate::config::Alarm::cron)
+//!
+//! An expression has five fields, `minute hour day-of-month month day-of-week`. Each field is `*`,
+//! a number, a range (`1-5`), a step (`*/2`, `8-18/2`) or a list of those (`1,15`). Months and
+//! days of the week can be names (`JAN`, `MON`), Sunday is 0 or 7. On top of that `L` as the day
+//! of the month is its last day, `5L` as the day of the week the last Friday of the month and
+//! `5#3` the third Friday. `@hourly`, `@daily`, `@weekly`, `@monthly` and `@yearly` are short for
+//! the usual expressions.
+//!
+//! Like in cron, if both the day of the month and the day of the week are restricted a day only
+//! has to match one of them (`0 7 1 * MON` rings on the 1st and on every Monday).
+use std::{error, fmt, str::FromStr};
+
+use chrono::{Datelike, Days, NaiveDate, NaiveDateTime, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+/// how many days ahead to look for the next time, the longest gap is February 29th (8 years
+/// around 2100)
+const SEARCH_DAYS: u32 = 366 * 9;
+
+const MONTHS: [&str; 12] = [
+    "JAN", "FEB", "MAR", "APR", "MAY", "JUN", "JUL", "AUG", "SEP", "OCT", "NOV", "DEC",
+];
+const WEEKDAYS: [&str; 7] = ["SUN", "MON", "TUE", "WED", "THU", "FRI", "SAT"];
+
+/// one of the five fields of an expression
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Field {
+    Minute,
+    Hour,
+    DayOfMonth,
+    Month,
+    DayOfWeek,
+}
+
+impl Field {
+    /// the values it can have (7 is also Sunday)
+    const fn range(self) -> (u32, u32) {
+        match self {
+            Self::Minute => (0, 59),
+            Self::Hour => (0, 23),
+            Self::DayOfMonth => (1, 31),
+            Self::Month => (1, 12),
+            Self::DayOfWeek => (0, 7),
+        }
+    }
+
+    /// a number or, for months and days of the week, a name
+    fn value(self, text: &str) -> Result<u32, Error> {
+        let names: &[&str] = match self {
+            Self::Month => &MONTHS,
+            Self::DayOfWeek => &WEEKDAYS,
+            _ => &[],
+        };
+        let value = names
+            .iter()
+            .position(|name| name.eq_ignore_ascii_case(text))
+            .map(|i| {
+                // months start at 1
+                let i = u32::try_from(i).unwrap_or_default();
+                if self == Self::Month { i + 1 } else { i }
+            })
+            .or_else(|| text.parse().ok())
+            .ok_or_else(|| Error::Invalid(self, text.to_string()))?;
+        let (min, max) = self.range();
+        if (min..=max).contains(&value) {
+            Ok(value)
+        } else {
+            Err(Error::OutOfRange(self, value))
+        }
+    }
+}
+
+impl fmt::Display for Field {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Minute => "minute",
+            Self::Hour => "hour",
+            Self::DayOfMonth => "day of the month",
+            Self::Month => "month",
+            Self::DayOfWeek => "day of the week",
+        })
+    }
+}
+
+/// why an expression couldn't be parsed
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum Error {
+    /// it didn't have five fields
+    FieldCount(usize),
+    UnknownMacro(String),
+    /// something in a field that isn't a number, name, range or step
+    Invalid(Field, String),
+    OutOfRange(Field, u32),
+    ZeroStep(Field),
+    /// it never matches a day, like February 30th
+    Never,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::FieldCount(count) => write!(
+                f,
+                "expected 5 fields (minute hour day-of-month month day-of-week), not {count}"
+            ),
+            Self::UnknownMacro(name) => write!(
+                f,
+                "{name} isn't one of @hourly, @daily, @weekly, @monthly or @yearly"
+            ),
+            Self::Invalid(field, text) => write!(f, "\"{text}\" isn't a valid {field}"),
+            Self::OutOfRange(field, value) => {
+                let (min, max) = field.range();
+                write!(
+                    f,
+                    "the {field} has to be between {min} and {max}, not {value}"
+                )
+            }
+            Self::ZeroStep(field) => write!(f, "the step of the {field} can't be 0"),
+            Self::Never => write!(f, "there is no day that matches"),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+/// A parsed cron expression, [`Schedule::next`] is when it matches next.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    // a bit for each value that matches
+    minutes: u64,
+    hours: u32,
+    days: u32,
+    months: u16,
+    weekdays: u8,
+    /// `L` in the day of the month
+    last_day: bool,
+    /// the weekdays whose last one in the month matches (`5L`)
+    last_weekdays: u8,
+    /// `(weekday, n)` for the nth weekday of the month (`5#3`)
+    nth_weekdays: Vec<(u32, u32)>,
+    // `*` as the day of the month or the week, then only the other one counts
+    any_day: bool,
+    any_weekday: bool,
+    source: String,
+}
+
+/// the bits a field sets, each part of the list is given to `special` first which can take it
+fn parse_field(
+    field: Field,
+    text: &str,
+    mut special: impl FnMut(&str) -> Result<bool, Error>,
+) -> Result<u64, Error> {
+    let (min, max) = field.range();
+    let mut bits = 0;
+    for part in text.split(',') {
+        if special(part)? {
+            continue;
+        }
+        let (range, step) = match part.split_once('/') {
+            Some((range, step)) => (
+                range,
+                step.parse::<u32>()
+                    .map_err(|_| Error::Invalid(field, part.to_string()))?,
+            ),
+            None => (part, 1),
+        };
+        if step == 0 {
+            return Err(Error::ZeroStep(field));
+        }
+        let (start, end) = if range == "*" {
+            (min, max)
+        } else if let Some((start, end)) = range.split_once('-') {
+            (field.value(start)?, field.value(end)?)
+        } else {
+            let start = field.value(range)?;
+            // `5/15` is from 5 to the end
+            (start, if part.contains('/') { max } else { start })
+        };
+        if start > end {
+            return Err(Error::Invalid(field, part.to_string()));
+        }
+        for value in (start..=end).step_by(step as usize) {
+            bits |= 1 << value;
+        }
+    }
+    Ok(bits)
+}
+
+impl FromStr for Schedule {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let source = s.trim();
+        let expression = match source {
+            "@yearly" | "@annually" => "0 0 1 1 *",
+            "@monthly" => "0 0 1 * *",
+            "@weekly" => "0 0 * * 0",
+            "@daily" | "@midnight" => "0 0 * * *",
+            "@hourly" => "0 * * * *",
+            name if name.starts_with('@') => return Err(Error::UnknownMacro(name.to_string())),
+            expression => expression,
+        };
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day, month, weekday] = fields[..] else {
+            return Err(Error::FieldCount(fields.len()));
+        };
+
+        let mut last_day = false;
+        let days = parse_field(Field::DayOfMonth, day, |part| {
+            let last = part.eq_ignore_ascii_case("L");
+            last_day |= last;
+            Ok(last)
+        })?;
+        let mut last_weekdays = 0;
+        let mut nth_weekdays = Vec::new();
+        let weekdays = parse_field(Field::DayOfWeek, weekday, |part| {
+            if let Some(weekday) = part.strip_suffix(['L', 'l']) {
+                last_weekdays |= 1 << (Field::DayOfWeek.value(weekday)? % 7);
+                Ok(true)
+            } else if let Some((weekday, n)) = part.split_once('#') {
+                let n = n
+                    .parse()
+                    .ok()
+                    .filter(|n| (1..=5).contains(n))
+                    .ok_or_else(|| Error::Invalid(Field::DayOfWeek, part.to_string()))?;
+                nth_weekdays.push((Field::DayOfWeek.value(weekday)? % 7, n));
+                Ok(true)
+            } else {
+                Ok(false)
+            }
+        })?;
+        let no_special = |_: &str| Ok(false);
+        #[allow(clippy::cast_possible_truncation)]
+        let schedule = Self {
+            minutes: parse_field(Field::Minute, minute, no_special)?,
+            hours: parse_field(Field::Hour, hour, no_special)? as u32,
+            days: days as u32,
+            months: parse_field(Field::Month, month, no_special)? as u16,
+            // 7 is sunday too
+            weekdays: ((weekdays | weekdays >> 7) & 0x7f) as u8,
+            last_day,
+            last_weekdays,
+            nth_weekdays,
+            any_day: day == "*",
+            any_weekday: weekday == "*",
+            source: source.to_string(),
+        };
+        // any year has every day of the month and weekday, except for a February 29th that has
+        // to be a specific weekday, and that's an "or"
+        let start = NaiveDate::from_ymd_opt(2000, 1, 1).unwrap_or_default();
+        if schedule.next(start.and_time(NaiveTime::MIN)).is_none() {
+            return Err(Error::Never);
+        }
+        Ok(schedule)
+    }
+}
+
+impl fmt::Display for Schedule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.source)
+    }
+}
+
+impl Schedule {
+    /// if the expression matches any time on `day`
+    #[must_use]
+    pub fn matches_day(&self, day: NaiveDate) -> bool {
+        if self.months & 1 << day.month() == 0 {
+            return false;
+        }
+        let last_of_month = day
+            .checked_add_days(Days::new(7))
+            .is_none_or(|next_week| next_week.month() != day.month());
+        let weekday = day.weekday().num_days_from_sunday();
+        let day_matches = self.days & 1 << day.day() != 0
+            || self.last_day
+                && day
+                    .succ_opt()
+                    .is_none_or(|next| next.month() != day.month());
+        let weekday_matches = self.weekdays & 1 << weekday != 0
+            || last_of_month && self.last_weekdays & 1 << weekday != 0
+            || self
+                .nth_weekdays
+                .iter()
+                .any(|&(nth, n)| nth == weekday && day.day().div_ceil(7) == n);
+        match (self.any_day, self.any_weekday) {
+            (true, true) => true,
+            (true, false) => weekday_matches,
+            (false, true) => day_matches,
+            (false, false) => day_matches || weekday_matches,
+        }
+    }
+
+    /// the first time of day at or after `after` that matches, if any
+    fn first_time(&self, after: NaiveTime) -> Option<NaiveTime> {
+        (0..24)
+            .filter(|hour| self.hours & 1 << hour != 0)
+            .flat_map(|hour| {
+                (0..60)
+                    .filter(|minute| self.minutes & 1 << minute != 0)
+                    .filter_map(move |minute| NaiveTime::from_hms_opt(hour, minute, 0))
+            })
+            .find(|&time| time >= after)
+    }
+
+    /// The first time at or after `from` that matches, `None` if there isn't one in the next few
+    /// years.
+    #[must_use]
+    pub fn next(&self, from: NaiveDateTime) -> Option<NaiveDateTime> {
+        let mut day = from.date();
+        let mut after = from.time();
+        for _ in 0..SEARCH_DAYS {
+            if self.matches_day(day)
+                && let Some(time) = self.first_time(after)
+            {
+                return Some(day.and_time(time));
+            }
+            day = day.succ_opt()?;
+            after = NaiveTime::MIN;
+        }
+        None
+    }
+
+    /// The next `count` times at or after `from`, like the GUI's preview.
+    #[must_use]
+    pub fn upcoming(&self, from: NaiveDateTime, count: usize) -> Vec<NaiveDateTime> {
+        std::iter::successors(self.next(from), |last| {
+            self.next(*last + chrono::Duration::minutes(1))
+        })
+        .take(count)
+        .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(date: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(date, "%Y-%m-%d %H:%M").unwrap()
+    }
+
+    #[test]
+    fn every_two_hours_on_weekdays() {
+        let schedule: Schedule = "0 */2 * * 1-5".parse().unwrap();
+        // friday 2026-10-16
+        assert_eq!(
+            schedule.upcoming(at("2026-10-16 21:30"), 3),
+            [
+                at("2026-10-16 22:00"),
+                at("2026-10-19 00:00"),
+                at("2026-10-19 02:00")
+            ]
+        );
+        // `next` counts `from` itself
+        assert_eq!(
+            schedule.next(at("2026-10-19 02:00")),
+            Some(at("2026-10-19 02:00"))
+        );
+    }
+
+    #[test]
+    fn special_days() {
+        let last_friday: Schedule = "30 7 * * 5L".parse().unwrap();
+        assert_eq!(
+            last_friday.upcoming(at("2026-10-01 00:00"), 3),
+            [
+                at("2026-10-30 07:30"),
+                at("2026-11-27 07:30"),
+                at("2026-12-25 07:30")
+            ]
+        );
+        let third_monday: Schedule = "0 9 * * MON#3".parse().unwrap();
+        assert_eq!(
+            third_monday.next(at("2026-10-01 00:00")),
+            Some(at("2026-10-19 09:00"))
+        );
+        let last_day: Schedule = "0 0 L FEB *".parse().unwrap();
+        assert_eq!(
+            last_day.next(at("2027-03-01 00:00")),
+            Some(at("2028-02-29 00:00"))
+        );
+        // either day field
+        let first_or_sunday: Schedule = "0 8 1 * 7".parse().unwrap();
+        assert_eq!(
+            first_or_sunday.upcoming(at("2026-10-26 00:00"), 2),
+            [at("2026-11-01 08:00"), at("2026-11-08 08:00")]
+        );
+        let weekly: Schedule = "@weekly".parse().unwrap();
+        assert_eq!(
+            weekly.next(at("2026-10-18 00:01")),
+            Some(at("2026-10-25 00:00"))
+        );
+    }
+
+    #[test]
+    fn helpful_errors() {
+        let error = |expression: &str| expression.parse::<Schedule>().unwrap_err();
+        assert_eq!(error("0 7 * *"), Error::FieldCount(4));
+        assert_eq!(error("0 25 * * *"), Error::OutOfRange(Field::Hour, 25));
+        assert_eq!(
+            error("0 7 * * FUN"),
+            Error::Invalid(Field::DayOfWeek, "FUN".to_string())
+        );
+        assert_eq!(error("*/0 * * * *"), Error::ZeroStep(Field::Minute));
+        assert_eq!(
+            error("0 7 5-1 * *"),
+            Error::Invalid(Field::DayOfMonth, "5-1".to_string())
+        );
+        assert_eq!(error("0 0 30 2 *"), Error::Never);
+        assert_eq!(
+            error("@fortnightly"),
+            Error::UnknownMacro("@fortnightly".to_string())
+        );
+        assert_eq!(
+            error("0 25 * * *").to_string(),
+            "the hour has to be between 0 and 23, not 25"
+        );
+    }
+}
@@ -0,0 +1,674 @@
+//! how messages are encoded on the socket
+//!
+//! The first thing a client sends is a handshake line, `bitcode` or `json`, which picks the
+//! encoding for the rest of the connection (in both directions):
+//! - bitcode: every message is a native endian `usize` length followed by that many bytes of
+//!   bitcode (see [`crate::read`] and [`crate::write`])
+//! - json: every message is one line of json, so the daemon can be controlled from a shell
+//!   ```sh
+//!   printf 'json\n"Init"\n{"StopAlarm":3}\n' | socat - UNIX-CONNECT:$XDG_RUNTIME_DIR/roosty-clockd.sock
+//!   ```
+//!
+//! Messages use serdes default (externally tagged) representation, so unit variants are just
+//! strings (`"Ping"`), and variants with data are objects with one key
+//! (`{"SetAlarm":[3,{"Enable":false}]}`).
+use std::{
+    fmt,
+    io::{self, BufRead, Read, Write},
+    str::FromStr,
+};
+
+use serde::{Serialize, de::DeserializeOwned};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Encoding {
+    #[default]
+    Bitcode,
+    /// newline delimited json
+    Json,
+}
+
+impl Encoding {
+    /// the longest handshake line that is accepted
+    const MAX_HANDSHAKE: usize = 16;
+
+    /// Tells the daemon which encoding the client uses, has to be sent before any message.
+    ///
+    /// # Errors
+    /// If the handshake couldn't be written.
+    pub fn handshake(self, w: &mut impl Write) -> io::Result<()> {
+        writeln!(w, "{self}")
+    }
+
+    /// Reads the handshake a client sent.
+    ///
+    /// This reads a byte at a time so nothing past the handshake is consumed.
+    ///
+    /// # Errors
+    /// If the handshake couldn't be read or it isn't a known encoding.
+    pub fn accept(r: &mut impl Read) -> io::Result<Self> {
+        let mut line = Vec::new();
+        let mut byte = [0];
+        while line.len() <= Self::MAX_HANDSHAKE {
+            r.read_exact(&mut byte)?;
+            if byte[0] == b'\n' {
+                return String::from_utf8_lossy(&line)
+                    .trim()
+                    .parse()
+                    .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e));
+            }
+            line.push(byte[0]);
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "handshake line too long",
+        ))
+    }
+
+    /// Encodes `message` including its framing.
+    ///
+    /// # Errors
+    /// If `message` couldn't be serialized.
+    pub fn encode<T: Serialize>(self, message: &T) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Bitcode => {
+                let message = bitcode::serialize(message).map_err(io::Error::other)?;
+                let mut bytes = message.len().to_ne_bytes().to_vec();
+                bytes.extend_from_slice(&message);
+                Ok(bytes)
+            }
+            Self::Json => {
+                let mut bytes = serde_json::to_vec(message)?;
+                bytes.push(b'\n');
+                Ok(bytes)
+            }
+        }
+    }
+
+    /// Decodes one message (without its framing).
+    ///
+    /// # Errors
+    /// If `message` isn't a valid `T`.
+    pub fn decode<T: DeserializeOwned>(self, message: &[u8]) -> io::Result<T> {
+        match self {
+            Self::Bitcode => bitcode::deserialize(message)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+            Self::Json => Ok(serde_json::from_slice(message)?),
+        }
+    }
+
+    /// # Errors
+    /// If `message` couldn't be serialized or written.
+    pub fn send<T: Serialize>(self, w: &mut impl Write, message: &T) -> io::Result<()> {
+        // in one write so that messages don't end up interleaved
+        w.write_all(&self.encode(message)?)
+    }
+
+    /// Reads the next message.
+    ///
+    /// With json, if reading would block (on a nonblocking stream) the part of the line that was
+    /// read is kept in `buf`, so the next call has to use the same `buf`.
+    ///
+    /// # Errors
+    /// If reading fails ([`io::ErrorKind::UnexpectedEof`] when the other side is gone), or the
+    /// message isn't a valid `T` or is bigger than [`crate::MAX_MESSAGE_SIZE`]
+    /// ([`io::ErrorKind::InvalidData`]). After a bitcode message that is too big it's unknown
+    /// where the next one starts, so the connection can't be used anymore.
+    pub fn recieve<T: DeserializeOwned>(
+        self,
+        r: &mut impl BufRead,
+        buf: &mut Vec<u8>,
+    ) -> io::Result<T> {
+        match self {
+            Self::Bitcode => {
+                crate::read(r, buf)?;
+                let message = self.decode(buf);
+                buf.clear();
+                message
+            }
+            Self::Json => {
+                // a byte more than allowed, so a line that is too long can be told apart
+                let limit = (crate::MAX_MESSAGE_SIZE + 1).saturating_sub(buf.len());
+                r.by_ref().take(limit as u64).read_until(b'\n', buf)?;
+                if buf.last() != Some(&b'\n') {
+                    if buf.len() > crate::MAX_MESSAGE_SIZE {
+                        buf.clear();
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            "message line is too long",
+                        ));
+                    }
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                let message = self.decode(buf);
+                buf.clear();
+                message
+            }
+        }
+    }
+}
+
+impl fmt::Display for Encoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Bitcode => write!(f, "bitcode"),
+            Self::Json => write!(f, "json"),
+        }
+    }
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "bitcode" => Ok(Self::Bitcode),
+            "json" => Ok(Self::Json),
+            _ => Err(format!("unknown encoding {s:?}, expected bitcode or json")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::{HashMap, HashSet},
+        thread,
+    };
+
+    use chrono::{DateTime, Local, NaiveDate, NaiveTime};
+
+    use super::*;
+    use crate::{
+        Alarm, AlarmEdit, AlarmError, AlarmSpec, Change, ClientMessage, Op, ServerMessage,
+        SoundRemoval, SoundRemovalError,
+        config::{self, SoundMode},
+        cron,
+        filter::{Filter, Topic},
+        history, ics,
+    };
+
+    const ENCODINGS: [Encoding; 2] = [Encoding::Bitcode, Encoding::Json];
+
+    fn time() -> NaiveTime {
+        NaiveTime::from_hms_opt(7, 30, 0).unwrap()
+    }
+
+    fn date() -> DateTime<Local> {
+        DateTime::from_timestamp(1_700_000_000, 0).unwrap().into()
+    }
+
+    fn alarm() -> Alarm {
+        Alarm {
+            name: Some("wake up".to_string()),
+            time: time(),
+            volume: 80.,
+            sound: "Bell".to_string(),
+            playlist: vec!["Bell".to_string(), "Beep".to_string()],
+            sound_mode: SoundMode::Shuffle,
+            id: 3,
+        }
+    }
+
+    fn sound() -> config::Sound {
+        config::Sound {
+            name: "Beep".to_string(),
+            kind: config::SoundKind::Tone,
+            path: "beep.wav".into(),
+            synth: Some(config::Synth::default()),
+        }
+    }
+
+    fn exception() -> config::Exception {
+        config::Exception {
+            name: Some("holiday".to_string()),
+            start: NaiveDate::from_ymd_opt(2026, 12, 24).unwrap(),
+            end: NaiveDate::from_ymd_opt(2026, 12, 26).unwrap(),
+        }
+    }
+
+    fn spec() -> AlarmSpec {
+        AlarmSpec {
+            name: None,
+            time: time(),
+            volume: 100.,
+            sound: "Bell".to_string(),
+            playlist: Vec::new(),
+            sound_mode: SoundMode::Single,
+            enabled: true,
+            start: None,
+            end: NaiveDate::from_ymd_opt(2026, 11, 30),
+            date: None,
+            exceptions: vec![exception()],
+            cron: Some("0 */2 * * 1-5".to_string()),
+        }
+    }
+
+    /// every variant of `ClientMessage`, `client_variant` has to be updated alongside this
+    fn client_messages() -> Vec<ClientMessage> {
+        vec![
+            ClientMessage::Init,
+            ClientMessage::SetAlarm(3, AlarmEdit::Time(time())),
+            ClientMessage::SetAlarm(3, AlarmEdit::Name(None)),
+            ClientMessage::SetAlarm(3, AlarmEdit::Playlist(vec!["Bell".to_string()])),
+            ClientMessage::SetAlarm(3, AlarmEdit::SoundMode(SoundMode::Playlist)),
+            ClientMessage::SetAlarm(3, AlarmEdit::Volume(12.5)),
+            ClientMessage::SetAlarm(3, AlarmEdit::Enable(false)),
+            ClientMessage::SetAlarm(3, AlarmEdit::Start(NaiveDate::from_ymd_opt(2026, 11, 1))),
+            ClientMessage::SetAlarm(3, AlarmEdit::End(None)),
+            ClientMessage::SetAlarm(3, AlarmEdit::Date(NaiveDate::from_ymd_opt(2026, 11, 3))),
+            ClientMessage::SetAlarm(3, AlarmEdit::Skip(NaiveDate::from_ymd_opt(2026, 11, 4))),
+            ClientMessage::SetAlarm(3, AlarmEdit::Exceptions(Vec::new())),
+            ClientMessage::SetAlarm(3, AlarmEdit::Cron(Some("@daily".to_string()))),
+            ClientMessage::AddAlarm(alarm()),
+            ClientMessage::RemoveAlarm(3),
+            ClientMessage::AddedSounds(vec![sound()]),
+            ClientMessage::RemoveSound("Beep".to_string(), SoundRemoval::Reassign),
+            ClientMessage::StopAlarm(3),
+            ClientMessage::GetNewUID,
+            ClientMessage::Ping,
+            ClientMessage::Shutdown,
+            ClientMessage::QueryHistory {
+                since: Some(date()),
+                until: None,
+                alarm: Some(3),
+            },
+            ClientMessage::Request(1, Box::new(ClientMessage::GetNewUID)),
+            ClientMessage::CreateAlarm(spec()),
+            ClientMessage::Batch(vec![
+                Op::Create(spec()),
+                Op::Set(3, AlarmEdit::Enable(false)),
+                Op::Remove(4),
+            ]),
+            ClientMessage::Subscribe(Filter {
+                topics: HashSet::from([Topic::Alarms, Topic::Ringing]),
+                alarms: Some(HashSet::from([3])),
+            }),
+            ClientMessage::SkipNext(3),
+            ClientMessage::GetExceptions,
+            ClientMessage::SetExceptions(vec![exception()]),
+            ClientMessage::ExportCalendar,
+            ClientMessage::ImportCalendar("BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n".to_string()),
+        ]
+    }
+
+    // adding a variant fails to compile here, so it can't be forgotten in the list above
+    const fn client_variant(message: &ClientMessage) -> usize {
+        match message {
+            ClientMessage::Init => 0,
+            ClientMessage::SetAlarm(..) => 1,
+            ClientMessage::AddAlarm(_) => 2,
+            ClientMessage::RemoveAlarm(_) => 3,
+            ClientMessage::AddedSounds(_) => 4,
+            ClientMessage::RemoveSound(..) => 5,
+            ClientMessage::StopAlarm(_) => 6,
+            ClientMessage::GetNewUID => 7,
+            ClientMessage::Ping => 8,
+            ClientMessage::Shutdown => 9,
+            ClientMessage::QueryHistory { .. } => 10,
+            ClientMessage::Request(..) => 11,
+            ClientMessage::CreateAlarm(_) => 12,
+            ClientMessage::Batch(_) => 13,
+            ClientMessage::Subscribe(_) => 14,
+            ClientMessage::SkipNext(_) => 15,
+            ClientMessage::GetExceptions => 16,
+            ClientMessage::SetExceptions(_) => 17,
+            ClientMessage::ExportCalendar => 18,
+            ClientMessage::ImportCalendar(_) => 19,
+        }
+    }
+
+    /// every variant of `ServerMessage`, `server_variant` has to be updated alongside this
+    fn server_messages() -> Vec<ServerMessage> {
+        let config_alarm = config::Alarm {
+            name: None,
+            time: time(),
+            volume: 100.,
+            sound: "Bell".to_string(),
+            playlist: Vec::new(),
+            sound_mode: SoundMode::Single,
+            enabled: false,
+            start: NaiveDate::from_ymd_opt(2026, 11, 1),
+            end: None,
+            date: NaiveDate::from_ymd_opt(2026, 11, 3),
+            skip: NaiveDate::from_ymd_opt(2026, 11, 2),
+            exceptions: vec![exception()],
+            cron: Some("0 7 * * 5L".to_string()),
+            id: 3,
+        };
+        vec![
+            ServerMessage::Init {
+                alarms: HashMap::from([(3, config_alarm.clone())]),
+                sounds: HashMap::from([("Beep".to_string(), sound())]),
+                ringing_alarms: HashSet::from([3]),
+            },
+            ServerMessage::AlarmSet(3, AlarmEdit::Sound("Beep".to_string())),
+            ServerMessage::AlaramAdded(alarm()),
+            ServerMessage::AlarmRemoved(3),
+            ServerMessage::SoundsAdded(vec![sound()]),
+            ServerMessage::SoundRemoved("Beep".to_string(), vec![3, 4]),
+            ServerMessage::SoundNotRemoved("Beep".to_string(), SoundRemovalError::InUse(vec![3])),
+            ServerMessage::SoundNotRemoved("Bell".to_string(), SoundRemovalError::IsDefault),
+            ServerMessage::AlarmRinging(3),
+            ServerMessage::AlarmStopped(3),
+            ServerMessage::UID(4),
+            ServerMessage::Pong(1234),
+            ServerMessage::ShuttingDown,
+            ServerMessage::History(vec![
+                history::Entry {
+                    time: date(),
+                    alarm: 3,
+                    event: history::Event::Added(alarm()),
+                },
+                history::Entry {
+                    time: date(),
+                    alarm: 3,
+                    event: history::Event::Rang,
+                },
+            ]),
+            ServerMessage::Response(1, Box::new(ServerMessage::UID(4))),
+            ServerMessage::AlarmCreated(config_alarm.clone()),
+            ServerMessage::AlarmNotCreated(AlarmError::UnknownSound("Boom".to_string())),
+            ServerMessage::AlarmNotCreated(AlarmError::VolumeOutOfRange(250.)),
+            ServerMessage::AlarmNotCreated(AlarmError::EndsBeforeStart(
+                NaiveDate::from_ymd_opt(2026, 11, 3).unwrap(),
+                NaiveDate::from_ymd_opt(2026, 11, 1).unwrap(),
+            )),
+            ServerMessage::AlarmNotCreated(AlarmError::InvalidCron(
+                "0 25 * * *".to_string(),
+                cron::Error::OutOfRange(cron::Field::Hour, 25),
+            )),
+            ServerMessage::BatchApplied(vec![
+                Change::Created(config_alarm),
+                Change::Set(3, AlarmEdit::Volume(50.)),
+                Change::Removed(4),
+            ]),
+            ServerMessage::BatchRejected(1, AlarmError::NoSuchAlarm(4)),
+            ServerMessage::Subscribed(Filter::default()),
+            ServerMessage::ResyncRequired,
+            ServerMessage::BatchRejected(0, AlarmError::WontRing(3)),
+            ServerMessage::Exceptions(vec![exception()]),
+            ServerMessage::Calendar("BEGIN:VCALENDAR\r\nEND:VCALENDAR\r\n".to_string()),
+            ServerMessage::CalendarImported(
+                vec![Change::Removed(4)],
+                vec![ics::Warning {
+                    entry: "Gym".to_string(),
+                    problem: "repeats by FREQ=WEEKLY, which alarms can't, skipped".to_string(),
+                }],
+            ),
+            ServerMessage::CalendarNotImported(ics::ParseError::BadLine(3)),
+        ]
+    }
+
+    const fn server_variant(message: &ServerMessage) -> usize {
+        match message {
+            ServerMessage::Init { .. } => 0,
+            ServerMessage::AlarmSet(..) => 1,
+            ServerMessage::AlaramAdded(_) => 2,
+            ServerMessage::AlarmRemoved(_) => 3,
+            ServerMessage::SoundsAdded(_) => 4,
+            ServerMessage::SoundRemoved(..) => 5,
+            ServerMessage::SoundNotRemoved(..) => 6,
+            ServerMessage::AlarmRinging(_) => 7,
+            ServerMessage::AlarmStopped(_) => 8,
+            ServerMessage::UID(_) => 9,
+            ServerMessage::Pong(_) => 10,
+            ServerMessage::ShuttingDown => 11,
+            ServerMessage::History(_) => 12,
+            ServerMessage::Response(..) => 13,
+            ServerMessage::AlarmCreated(_) => 14,
+            ServerMessage::AlarmNotCreated(_) => 15,
+            ServerMessage::BatchApplied(_) => 16,
+            ServerMessage::BatchRejected(..) => 17,
+            ServerMessage::Subscribed(_) => 18,
+            ServerMessage::ResyncRequired => 19,
+            ServerMessage::Exceptions(_) => 20,
+            ServerMessage::Calendar(_) => 21,
+            ServerMessage::CalendarImported(..) => 22,
+            ServerMessage::CalendarNotImported(_) => 23,
+        }
+    }
+
+    /// runs `f` on a thread with the stack of a client thread in the daemon
+    fn on_client_stack<T: Send>(f: impl FnOnce() -> T + Send) -> T {
+        thread::scope(|scope| {
+            thread::Builder::new()
+                .stack_size(crate::CLIENT_STACK_SIZE)
+                .spawn_scoped(scope, f)
+                .unwrap()
+                .join()
+                .unwrap()
+        })
+    }
+
+    /// sends all of `messages` through a buffer and reads them back
+    fn round_trip<T>(encoding: Encoding, messages: &[T])
+    where
+        T: Serialize + DeserializeOwned + PartialEq + fmt::Debug,
+    {
+        let mut bytes = Vec::new();
+        for message in messages {
+            encoding.send(&mut bytes, message).unwrap();
+        }
+        let mut reader = &bytes[..];
+        let mut buf = Vec::new();
+        for message in messages {
+            assert_eq!(
+                &encoding.recieve::<T>(&mut reader, &mut buf).unwrap(),
+                message
+            );
+        }
+        assert_eq!(
+            encoding
+                .recieve::<T>(&mut reader, &mut buf)
+                .unwrap_err()
+                .kind(),
+            io::ErrorKind::UnexpectedEof
+        );
+    }
+
+    #[test]
+    fn every_variant_is_tested() {
+        let clients: HashSet<_> = client_messages().iter().map(client_variant).collect();
+        assert_eq!(clients, (0..=19).collect());
+        let servers: HashSet<_> = server_messages().iter().map(server_variant).collect();
+        assert_eq!(servers, (0..=23).collect());
+    }
+
+    #[test]
+    fn client_messages_round_trip() {
+        for encoding in ENCODINGS {
+            round_trip(encoding, &client_messages());
+        }
+    }
+
+    #[test]
+    fn server_messages_round_trip() {
+        for encoding in ENCODINGS {
+            round_trip(encoding, &server_messages());
+        }
+    }
+
+    #[test]
+    fn json_is_one_readable_line() {
+        let bytes = Encoding::Json
+            .encode(&ClientMessage::SetAlarm(3, AlarmEdit::Enable(false)))
+            .unwrap();
+        assert_eq!(bytes, b"{\"SetAlarm\":[3,{\"Enable\":false}]}\n");
+        let message: ClientMessage = Encoding::Json.decode(b"\"Ping\"").unwrap();
+        assert_eq!(message, ClientMessage::Ping);
+    }
+
+    #[test]
+    fn nested_requests_are_refused() {
+        let nested = ClientMessage::Request(
+            1,
+            Box::new(ClientMessage::Request(2, Box::new(ClientMessage::Ping))),
+        );
+        let response = ServerMessage::Response(
+            1,
+            Box::new(ServerMessage::Response(2, Box::new(ServerMessage::Pong(3)))),
+        );
+        for encoding in ENCODINGS {
+            let bytes = encoding.encode(&nested).unwrap();
+            let error = encoding
+                .recieve::<ClientMessage>(&mut &bytes[..], &mut Vec::new())
+                .unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            let bytes = encoding.encode(&response).unwrap();
+            assert!(
+                encoding
+                    .recieve::<ServerMessage>(&mut &bytes[..], &mut Vec::new())
+                    .is_err()
+            );
+            // and after an error the next request still decodes
+            let bytes = encoding
+                .encode(&ClientMessage::Request(3, Box::new(ClientMessage::Ping)))
+                .unwrap();
+            assert!(
+                encoding
+                    .recieve::<ClientMessage>(&mut &bytes[..], &mut Vec::new())
+                    .is_ok()
+            );
+        }
+    }
+
+    #[test]
+    fn deeply_nested_requests_dont_overflow_the_stack() {
+        const DEPTH: u64 = 10_000;
+        // building (and dropping) the message recurses as well, that needs a bigger stack
+        let bitcode = thread::Builder::new()
+            .stack_size(1024 * 1024 * 1024)
+            .spawn(|| {
+                let mut message = ClientMessage::Ping;
+                for id in 0..DEPTH {
+                    message = ClientMessage::Request(id, Box::new(message));
+                }
+                Encoding::Bitcode.encode(&message).unwrap()
+            })
+            .unwrap()
+            .join()
+            .unwrap();
+        let json = format!(
+            "{}\"Ping\"{}\n",
+            "{\"Request\":[1,".repeat(DEPTH as usize),
+            "]}".repeat(DEPTH as usize)
+        );
+        on_client_stack(|| {
+            for (encoding, bytes) in [
+                (Encoding::Bitcode, &bitcode[..]),
+                (Encoding::Json, json.as_bytes()),
+            ] {
+                let error = encoding
+                    .recieve::<ClientMessage>(&mut &bytes[..], &mut Vec::new())
+                    .unwrap_err();
+                assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            }
+        });
+    }
+
+    #[test]
+    fn json_keeps_partial_lines() {
+        let mut buf = Vec::new();
+        let error = Encoding::Json
+            .recieve::<ClientMessage>(&mut &b"{\"StopAl"[..], &mut buf)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::UnexpectedEof);
+        let message: ClientMessage = Encoding::Json
+            .recieve(&mut &b"arm\":3}\n"[..], &mut buf)
+            .unwrap();
+        assert_eq!(message, ClientMessage::StopAlarm(3));
+    }
+
+    #[test]
+    fn bitcode_keeps_partial_messages() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut send = std::net::TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (recv, _) = listener.accept().unwrap();
+        recv.set_nonblocking(true).unwrap();
+        let mut recv = io::BufReader::new(recv);
+        let message = ClientMessage::ImportCalendar("BEGIN:VCALENDAR\r\n".repeat(500));
+        let frame = Encoding::Bitcode.encode(&message).unwrap();
+        let (first, rest) = frame.split_at(frame.len() / 2);
+        let mut buf = Vec::new();
+        send.write_all(first).unwrap();
+        thread::sleep(std::time::Duration::from_millis(100));
+        let error = Encoding::Bitcode
+            .recieve::<ClientMessage>(&mut recv, &mut buf)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::WouldBlock);
+        send.write_all(rest).unwrap();
+        thread::sleep(std::time::Duration::from_millis(100));
+        let received: ClientMessage = Encoding::Bitcode.recieve(&mut recv, &mut buf).unwrap();
+        assert_eq!(received, message);
+        assert!(buf.is_empty());
+    }
+
+    #[test]
+    fn handshake() {
+        for encoding in ENCODINGS {
+            let mut bytes = Vec::new();
+            encoding.handshake(&mut bytes).unwrap();
+            bytes.extend_from_slice(b"rest");
+            let mut reader = &bytes[..];
+            assert_eq!(Encoding::accept(&mut reader).unwrap(), encoding);
+            // nothing after the handshake is consumed
+            assert_eq!(reader, b"rest");
+        }
+        assert!(Encoding::accept(&mut &b"yaml\n"[..]).is_err());
+        assert!(Encoding::accept(&mut &[0; 64][..]).is_err());
+    }
+
+    #[test]
+    fn too_big_messages_are_refused() {
+        let mut bytes = (crate::MAX_MESSAGE_SIZE + 1).to_ne_bytes().to_vec();
+        bytes.extend_from_slice(b"rest");
+        let mut reader = &bytes[..];
+        let mut buf = Vec::new();
+        let error = Encoding::Bitcode
+            .recieve::<ClientMessage>(&mut reader, &mut buf)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(reader, b"rest");
+
+        let mut line = vec![b' '; crate::MAX_MESSAGE_SIZE + 1];
+        line.extend_from_slice(b"\"Ping\"\n");
+        let mut reader = &line[..];
+        let error = Encoding::Json
+            .recieve::<ClientMessage>(&mut reader, &mut buf)
+            .unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert!(buf.is_empty());
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn garbage_is_an_error(bytes: Vec<u8>) {
+            on_client_stack(|| {
+                for encoding in ENCODINGS {
+                    let mut reader = &bytes[..];
+                    let mut buf = Vec::new();
+                    // it's fine if the garbage happens to be messages, just no panics
+                    while encoding.recieve::<ClientMessage>(&mut reader, &mut buf).is_ok() {}
+                }
+            });
+        }
+
+        #[test]
+        fn frames_round_trip(messages: Vec<Vec<u8>>) {
+            let mut bytes = Vec::new();
+            for message in &messages {
+                bytes.extend_from_slice(&message.len().to_ne_bytes());
+                bytes.extend_from_slice(message);
+            }
+            let mut reader = &bytes[..];
+            let mut buf = Vec::new();
+            for message in &messages {
+                crate::read(&mut reader, &mut buf).unwrap();
+                proptest::prop_assert_eq!(&buf, message);
+                buf.clear();
+            }
+            proptest::prop_assert!(reader.is_empty());
+        }
+    }
+}
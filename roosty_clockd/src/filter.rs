@@ -0,0 +1,134 @@
+//! which events a client is sent, see [`ClientMessage::Subscribe`](crate::ClientMessage::Subscribe)
+use std::collections::HashSet;
+
+use serde::{Deserialize, Serialize};
+
+use crate::ServerMessage;
+
+/// a kind of event (there are no timers yet, so they have no topic)
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Topic {
+    /// alarms being added, edited and removed
+    Alarms,
+    /// alarms starting and stopping to ring
+    Ringing,
+    /// sounds being added and removed
+    Sounds,
+}
+
+impl Topic {
+    /// The topic of an event, `None` for everything that isn't one (like answers to requests), those
+    /// are always sent.
+    #[must_use]
+    pub const fn of(message: &ServerMessage) -> Option<Self> {
+        match message {
+            ServerMessage::AlarmSet(..)
+            | ServerMessage::AlaramAdded(_)
+            | ServerMessage::AlarmCreated(_)
+            | ServerMessage::AlarmRemoved(_)
+            | ServerMessage::BatchApplied(_)
+            | ServerMessage::Exceptions(_) => Some(Self::Alarms),
+            ServerMessage::AlarmRinging(_) | ServerMessage::AlarmStopped(_) => Some(Self::Ringing),
+            ServerMessage::SoundsAdded(_) | ServerMessage::SoundRemoved(..) => Some(Self::Sounds),
+            _ => None,
+        }
+    }
+}
+
+/// Which events to send a client, by default all of them.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct Filter {
+    /// every topic if empty
+    pub topics: HashSet<Topic>,
+    /// only events about these alarms (events about sounds still get through), every alarm if
+    /// `None`
+    pub alarms: Option<HashSet<u64>>,
+}
+
+impl Filter {
+    /// The part of `message` that gets through the filter, if any.
+    #[must_use]
+    pub fn apply(&self, message: ServerMessage) -> Option<ServerMessage> {
+        let Some(topic) = Topic::of(&message) else {
+            return Some(message);
+        };
+        if !self.topics.is_empty() && !self.topics.contains(&topic) {
+            return None;
+        }
+        let Some(alarms) = &self.alarms else {
+            return Some(message);
+        };
+        let alarm = match message {
+            ServerMessage::BatchApplied(changes) => {
+                let changes: Vec<_> = changes
+                    .into_iter()
+                    .filter(|change| alarms.contains(&change.alarm()))
+                    .collect();
+                return (!changes.is_empty()).then_some(ServerMessage::BatchApplied(changes));
+            }
+            ServerMessage::AlarmSet(id, _)
+            | ServerMessage::AlarmRemoved(id)
+            | ServerMessage::AlarmRinging(id)
+            | ServerMessage::AlarmStopped(id) => id,
+            ServerMessage::AlaramAdded(ref alarm) => alarm.id,
+            ServerMessage::AlarmCreated(ref alarm) => alarm.id,
+            message => return Some(message),
+        };
+        alarms.contains(&alarm).then_some(message)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{AlarmEdit, Change};
+
+    #[test]
+    fn filters_by_topic() {
+        let filter = Filter {
+            topics: HashSet::from([Topic::Ringing]),
+            alarms: None,
+        };
+        assert_eq!(
+            filter.apply(ServerMessage::AlarmRinging(3)),
+            Some(ServerMessage::AlarmRinging(3))
+        );
+        assert_eq!(filter.apply(ServerMessage::AlarmRemoved(3)), None);
+        // not an event
+        assert_eq!(
+            filter.apply(ServerMessage::UID(3)),
+            Some(ServerMessage::UID(3))
+        );
+    }
+
+    #[test]
+    fn filters_by_alarm() {
+        let filter = Filter {
+            topics: HashSet::new(),
+            alarms: Some(HashSet::from([3])),
+        };
+        assert_eq!(
+            filter.apply(ServerMessage::AlarmStopped(3)),
+            Some(ServerMessage::AlarmStopped(3))
+        );
+        assert_eq!(filter.apply(ServerMessage::AlarmStopped(4)), None);
+        assert_eq!(
+            filter.apply(ServerMessage::BatchApplied(vec![
+                Change::Removed(4),
+                Change::Set(3, AlarmEdit::Enable(false)),
+            ])),
+            Some(ServerMessage::BatchApplied(vec![Change::Set(
+                3,
+                AlarmEdit::Enable(false)
+            )]))
+        );
+        assert_eq!(
+            filter.apply(ServerMessage::BatchApplied(vec![Change::Removed(4)])),
+            None
+        );
+        assert_eq!(
+            filter.apply(ServerMessage::SoundRemoved("Beep".to_string(), vec![4])),
+            Some(ServerMessage::SoundRemoved("Beep".to_string(), vec![4]))
+        );
+    }
+}
@@ -0,0 +1,249 @@
+//! a journal of what happened to alarms (when they rang, were stopped or changed), kept as json
+//! lines so its easy to look through
+use std::{
+    collections::HashMap,
+    fmt,
+    fs::{self, File, OpenOptions},
+    io::{self, BufRead, BufReader, Write},
+    path::PathBuf,
+};
+
+use chrono::{DateTime, Local, TimeDelta};
+use serde::{Deserialize, Serialize};
+
+use crate::{Alarm, AlarmEdit};
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Entry {
+    pub time: DateTime<Local>,
+    pub alarm: u64,
+    pub event: Event,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Event {
+    /// the alarm was added (or replaced by an edit)
+    Added(Alarm),
+    Edited(AlarmEdit),
+    Removed,
+    Rang,
+    Stopped,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Added(alarm) => write!(f, "added for {}", alarm.time),
+            Self::Edited(AlarmEdit::Time(time)) => write!(f, "time changed to {time}"),
+            Self::Edited(AlarmEdit::Name(Some(name))) => write!(f, "renamed to {name}"),
+            Self::Edited(AlarmEdit::Name(None)) => write!(f, "name removed"),
+            Self::Edited(AlarmEdit::Sound(sound)) => write!(f, "sound changed to {sound}"),
+            Self::Edited(AlarmEdit::Playlist(playlist)) => {
+                write!(f, "playlist changed to {}", playlist.join(", "))
+            }
+            Self::Edited(AlarmEdit::SoundMode(mode)) => write!(f, "sound mode changed to {mode:?}"),
+            Self::Edited(AlarmEdit::Volume(volume)) => write!(f, "volume changed to {volume}"),
+            Self::Edited(AlarmEdit::Enable(true)) => write!(f, "enabled"),
+            Self::Edited(AlarmEdit::Enable(false)) => write!(f, "disabled"),
+            Self::Edited(AlarmEdit::Start(Some(start))) => write!(f, "starts on {start}"),
+            Self::Edited(AlarmEdit::Start(None)) => write!(f, "start date removed"),
+            Self::Edited(AlarmEdit::End(Some(end))) => write!(f, "ends on {end}"),
+            Self::Edited(AlarmEdit::End(None)) => write!(f, "end date removed"),
+            Self::Edited(AlarmEdit::Date(Some(date))) => write!(f, "only rings on {date}"),
+            Self::Edited(AlarmEdit::Date(None)) => write!(f, "rings every day"),
+            Self::Edited(AlarmEdit::Skip(Some(day))) => write!(f, "skips {day}"),
+            Self::Edited(AlarmEdit::Skip(None)) => write!(f, "doesn't skip anymore"),
+            Self::Edited(AlarmEdit::Exceptions(exceptions)) => {
+                write!(f, "{} exceptions", exceptions.len())
+            }
+            Self::Edited(AlarmEdit::Cron(Some(cron))) => write!(f, "rings at {cron}"),
+            Self::Edited(AlarmEdit::Cron(None)) => write!(f, "cron expression removed"),
+            Self::Removed => write!(f, "removed"),
+            Self::Rang => write!(f, "rang"),
+            Self::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+/// The journal file, entries are only ever appended to it.
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: File,
+}
+
+impl Journal {
+    /// # Errors
+    /// If the journal couldn't be created or opened.
+    pub fn open(path: PathBuf) -> io::Result<Self> {
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let file = OpenOptions::new().create(true).append(true).open(&path)?;
+        Ok(Self { path, file })
+    }
+
+    /// Records that `event` happened to `alarm` just now.
+    ///
+    /// # Errors
+    /// If the entry couldn't be written.
+    pub fn record(&mut self, alarm: u64, event: Event) -> io::Result<()> {
+        let entry = Entry {
+            time: Local::now(),
+            alarm,
+            event,
+        };
+        let mut line = serde_json::to_string(&entry).map_err(io::Error::other)?;
+        line.push('\n');
+        // in one write so that entries don't end up half written
+        self.file.write_all(line.as_bytes())
+    }
+
+    /// The entries between `since` and `until` (for `alarm` if given), oldest first.
+    ///
+    /// Lines that can't be parsed are skipped.
+    ///
+    /// # Errors
+    /// If the journal couldn't be read.
+    pub fn query(
+        &self,
+        since: Option<DateTime<Local>>,
+        until: Option<DateTime<Local>>,
+        alarm: Option<u64>,
+    ) -> io::Result<Vec<Entry>> {
+        let mut entries = Vec::new();
+        for line in BufReader::new(File::open(&self.path)?).lines() {
+            let Ok(entry) = serde_json::from_str::<Entry>(&line?) else {
+                continue;
+            };
+            if since.is_none_or(|since| entry.time >= since)
+                && until.is_none_or(|until| entry.time <= until)
+                && alarm.is_none_or(|alarm| entry.alarm == alarm)
+            {
+                entries.push(entry);
+            }
+        }
+        Ok(entries)
+    }
+}
+
+/// How an alarm has been doing.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Stats {
+    pub rang: usize,
+    /// times it was stopped while ringing
+    pub dismissed: usize,
+    /// times it rang and wasn't stopped (it rang again, or was disabled or removed instead)
+    pub ignored: usize,
+    /// how long it took to stop it on average
+    pub average_dismiss: Option<TimeDelta>,
+}
+
+impl Stats {
+    /// The stats of every alarm in `entries` (which should be oldest first).
+    #[must_use]
+    pub fn of(entries: &[Entry]) -> HashMap<u64, Self> {
+        let mut stats: HashMap<u64, (Self, Option<DateTime<Local>>, TimeDelta)> = HashMap::new();
+        for entry in entries {
+            let (stats, ringing_since, total) = stats.entry(entry.alarm).or_default();
+            match entry.event {
+                Event::Rang => {
+                    stats.rang += 1;
+                    if ringing_since.replace(entry.time).is_some() {
+                        stats.ignored += 1;
+                    }
+                }
+                Event::Stopped => {
+                    if let Some(since) = ringing_since.take() {
+                        stats.dismissed += 1;
+                        *total += entry.time - since;
+                    }
+                }
+                Event::Removed | Event::Edited(AlarmEdit::Enable(false)) => {
+                    if ringing_since.take().is_some() {
+                        stats.ignored += 1;
+                    }
+                }
+                Event::Added(_) | Event::Edited(_) => {}
+            }
+        }
+        stats
+            .into_iter()
+            .map(|(id, (mut stats, _, total))| {
+                stats.average_dismiss = i32::try_from(stats.dismissed)
+                    .ok()
+                    .filter(|dismissed| *dismissed > 0)
+                    .map(|dismissed| total / dismissed);
+                (id, stats)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entry(seconds: i64, alarm: u64, event: Event) -> Entry {
+        Entry {
+            time: DateTime::from_timestamp(seconds, 0).unwrap().into(),
+            alarm,
+            event,
+        }
+    }
+
+    #[test]
+    fn stats_count_dismissed_and_ignored() {
+        let entries = [
+            entry(0, 1, Event::Rang),
+            entry(30, 1, Event::Stopped),
+            entry(100, 1, Event::Rang),
+            entry(200, 1, Event::Rang),
+            entry(290, 1, Event::Stopped),
+            entry(300, 2, Event::Rang),
+            entry(310, 2, Event::Edited(AlarmEdit::Enable(false))),
+            // stopping something that isn't ringing doesn't count
+            entry(320, 2, Event::Stopped),
+        ];
+        let stats = Stats::of(&entries);
+        assert_eq!(
+            stats[&1],
+            Stats {
+                rang: 3,
+                dismissed: 2,
+                ignored: 1,
+                average_dismiss: Some(TimeDelta::seconds(60)),
+            }
+        );
+        assert_eq!(
+            stats[&2],
+            Stats {
+                rang: 1,
+                dismissed: 0,
+                ignored: 1,
+                average_dismiss: None,
+            }
+        );
+    }
+
+    #[test]
+    fn journal_round_trips_and_filters() {
+        let path = std::env::temp_dir().join(format!(
+            "roosty-clockd-history-{}.jsonl",
+            std::process::id()
+        ));
+        let _ = fs::remove_file(&path);
+        let mut journal = Journal::open(path.clone()).unwrap();
+        journal.record(1, Event::Rang).unwrap();
+        journal.record(2, Event::Rang).unwrap();
+        journal.record(1, Event::Stopped).unwrap();
+
+        let entries = journal.query(None, None, Some(1)).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(matches!(entries[0].event, Event::Rang));
+        assert!(matches!(entries[1].event, Event::Stopped));
+        let later = Local::now() + TimeDelta::hours(1);
+        assert!(journal.query(Some(later), None, None).unwrap().is_empty());
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,790 @@
+//! an optional HTTP/JSON API, for scripts and browsers that can't speak bitcode
+//!
+//! Every request is turned into [`ClientMessage`]s sent to the daemon over its socket, like any
+//! other client would.
+//!
+//! | method | path | does |
+//! | --- | --- | --- |
+//! | `GET` | `/alarms` | every alarm |
+//! | `POST` | `/alarms` | add an alarm (`time` is required, everything else is optional), `422` if it uses a missing sound, is too loud or ends before it starts |
+//! | `GET` | `/alarms/{id}` | one alarm |
+//! | `PATCH` | `/alarms/{id}` | apply an [`AlarmEdit`] (or a list of them, all or none), like `{"Volume": 50}` |
+//! | `DELETE` | `/alarms/{id}` | remove an alarm |
+//! | `POST` | `/alarms/{id}/stop` | stop a ringing alarm |
+//! | `GET` | `/sounds` | every sound |
+//! | `POST` | `/sounds` | add a sound (or a list of them) |
+//! | `DELETE` | `/sounds/{name}?policy=refuse\|reassign\|disable` | remove a sound |
+//! | `POST` | `/batch` | apply a list of [`Op`]s all at once, `422` (with the failed `op`) if any can't be |
+//! | `GET` | `/history?since=&until=&alarm=` | the alarm history (times are RFC 3339) |
+//! | `GET` | `/events?topics=alarms,ringing,sounds&alarms=` | a server-sent events stream of every [`ServerMessage`] (or only some [`Topic`]s and alarms) |
+//!
+//! The daemon has no timers, so there is no resource for them.
+//!
+//! Bodies over [`MAX_BODY`] are refused with `413`. So that web pages can't use the API through
+//! DNS rebinding or cross-site requests, a `Host` that isn't `localhost` (or the address being
+//! listened on) and an `Origin` that isn't `localhost` are refused with `403`.
+//!
+//! There is no authentication, so the API only listens on loopback addresses. Other machines
+//! have to go through the remote clients (see [`crate::remote`]), which need a token.
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, BufReader, Read, Write},
+    net::{IpAddr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    thread,
+    time::Duration,
+};
+
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AlarmEdit, AlarmError, AlarmSpec, ClientMessage, Op, ServerMessage, SoundRemoval,
+    client::{Client, Pending},
+    config::{self, Exception, Sound, SoundMode},
+    filter::{Filter, Topic},
+    socket::Address,
+};
+
+/// where the API listens if no address is given
+pub const DEFAULT_ADDRESS: &str = "127.0.0.1:8737";
+
+/// how long to wait for the daemon to answer
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// how long a connection can stay quiet (or not take what we send) before it's dropped
+const IDLE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// the biggest request body we accept
+pub const MAX_BODY: usize = 1024 * 1024;
+
+/// the most bytes the request line and headers can take together
+const MAX_HEAD: u64 = 16 * 1024;
+
+/// Listens on `address` for [`serve`], which has to be a loopback address.
+///
+/// # Errors
+/// If the address isn't loopback or couldn't be listened on.
+pub fn bind(address: SocketAddr) -> io::Result<TcpListener> {
+    if !address.ip().is_loopback() {
+        return Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "the HTTP API has no authentication, so it only listens on loopback addresses",
+        ));
+    }
+    TcpListener::bind(address)
+}
+
+/// Serves the API on `listener` until `shutdown` is set, talking to the daemon at `address`.
+#[must_use]
+pub fn serve(
+    listener: TcpListener,
+    address: Address,
+    shutdown: Arc<AtomicBool>,
+) -> thread::JoinHandle<()> {
+    thread::spawn(move || {
+        // so that we notice when to shut down
+        if let Err(e) = listener.set_nonblocking(true) {
+            log::error!("couldn't start the HTTP API: {e}");
+            return;
+        }
+        while !shutdown.load(Ordering::SeqCst) {
+            match listener.accept() {
+                Ok((stream, peer)) => {
+                    log::debug!("HTTP request from {peer}");
+                    let address = address.clone();
+                    thread::spawn(move || {
+                        if let Err(e) = handle(stream, &address) {
+                            log::warn!("HTTP request from {peer} failed: {e}");
+                        }
+                    });
+                }
+                Err(e) if e.kind() == io::ErrorKind::WouldBlock => {
+                    thread::sleep(Duration::from_millis(50));
+                }
+                Err(e) => log::warn!("incoming HTTP connection failed: {e}"),
+            }
+        }
+    })
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct Request {
+    method: String,
+    path: String,
+    query: HashMap<String, String>,
+    host: Option<String>,
+    origin: Option<String>,
+    body: Vec<u8>,
+}
+
+impl Request {
+    fn read(stream: &mut impl BufRead) -> Result<Self, Response> {
+        let bad = |e: io::Error| Response::error(400, &e.to_string());
+        let mut head = (&mut *stream).take(MAX_HEAD);
+        let mut line = String::new();
+        head.read_line(&mut line).map_err(bad)?;
+        let mut parts = line.split_whitespace();
+        let (Some(method), Some(target)) = (parts.next(), parts.next()) else {
+            return Err(Response::error(400, "bad request line"));
+        };
+        let (method, target) = (method.to_string(), target.to_string());
+        let (mut length, mut host, mut origin) = (0, None, None);
+        loop {
+            line.clear();
+            if head.read_line(&mut line).map_err(bad)? == 0 {
+                if head.limit() == 0 {
+                    return Err(Response::error(431, "headers too long"));
+                }
+                break;
+            }
+            if line.trim().is_empty() {
+                break;
+            }
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            let (name, value) = (name.trim(), value.trim());
+            if name.eq_ignore_ascii_case("content-length") {
+                length = value
+                    .parse()
+                    .map_err(|_| Response::error(400, "bad content-length"))?;
+            } else if name.eq_ignore_ascii_case("host") {
+                host = Some(value.to_string());
+            } else if name.eq_ignore_ascii_case("origin") {
+                origin = Some(value.to_string());
+            }
+        }
+        if length > MAX_BODY {
+            return Err(Response::error(
+                413,
+                &format!("bodies can't be over {MAX_BODY} bytes"),
+            ));
+        }
+        let mut body = vec![0; length];
+        stream.read_exact(&mut body).map_err(bad)?;
+        let (path, query) = target.split_once('?').unwrap_or((&target, ""));
+        Ok(Self {
+            method,
+            path: percent_decode(path),
+            query: query
+                .split('&')
+                .filter_map(|pair| pair.split_once('='))
+                .map(|(key, value)| (percent_decode(key), percent_decode(value)))
+                .collect(),
+            host,
+            origin,
+            body,
+        })
+    }
+
+    /// Refuses requests for another host or from another site, `local` is where we're listening.
+    fn check_origin(&self, local: IpAddr) -> Result<(), Response> {
+        if let Some(host) = &self.host
+            && !is_local(host, local)
+        {
+            return Err(Response::error(403, &format!("not serving {host}")));
+        }
+        if let Some(origin) = &self.origin
+            && !origin.split_once("://").is_some_and(|(scheme, host)| {
+                matches!(scheme, "http" | "https") && is_local(host, local)
+            })
+        {
+            return Err(Response::error(403, &format!("not serving {origin}")));
+        }
+        Ok(())
+    }
+
+    fn json<'a, T: Deserialize<'a>>(&'a self) -> Result<T, Response> {
+        serde_json::from_slice(&self.body)
+            .map_err(|e| Response::error(400, &format!("bad request body: {e}")))
+    }
+}
+
+/// whether `host` (maybe with a port) is this machine, or the address we're listening on
+fn is_local(host: &str, local: IpAddr) -> bool {
+    // an IPv6 host is in brackets, so its colons aren't taken for the port
+    let name = match host.rsplit_once(':') {
+        Some((name, port)) if !port.contains(']') => name,
+        _ => host,
+    };
+    let name = name.trim_start_matches('[').trim_end_matches(']');
+    name.eq_ignore_ascii_case("localhost")
+        || name
+            .parse::<IpAddr>()
+            .is_ok_and(|ip| ip.is_loopback() || ip == local)
+}
+
+fn percent_decode(s: &str) -> String {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let [first, tail @ ..] = rest {
+        if *first == b'%'
+            && let [high, low, tail @ ..] = tail
+            && let Ok(byte) = u8::from_str_radix(&format!("{}{}", *high as char, *low as char), 16)
+        {
+            bytes.push(byte);
+            rest = tail;
+        } else {
+            bytes.push(*first);
+            rest = tail;
+        }
+    }
+    String::from_utf8_lossy(&bytes).into_owned()
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Route {
+    Alarms,
+    Alarm(u64),
+    StopAlarm(u64),
+    Sounds,
+    Sound(String),
+    Batch,
+    History,
+    Events,
+}
+
+impl Route {
+    fn parse(path: &str) -> Option<Self> {
+        let segments: Vec<_> = path.trim_matches('/').split('/').collect();
+        match segments.as_slice() {
+            ["alarms"] => Some(Self::Alarms),
+            ["alarms", id] => id.parse().ok().map(Self::Alarm),
+            ["alarms", id, "stop"] => id.parse().ok().map(Self::StopAlarm),
+            ["sounds"] => Some(Self::Sounds),
+            ["sounds", name] => Some(Self::Sound((*name).to_string())),
+            ["batch"] => Some(Self::Batch),
+            ["history"] => Some(Self::History),
+            ["events"] => Some(Self::Events),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Response {
+    status: u16,
+    body: String,
+}
+
+impl Response {
+    fn json(status: u16, body: &impl Serialize) -> Self {
+        match serde_json::to_string(body) {
+            Ok(body) => Self { status, body },
+            Err(e) => Self::error(500, &e.to_string()),
+        }
+    }
+
+    fn error(status: u16, message: &str) -> Self {
+        Self::json(status, &serde_json::json!({ "error": message }))
+    }
+
+    const fn empty() -> Self {
+        Self {
+            status: 204,
+            body: String::new(),
+        }
+    }
+
+    fn write(&self, stream: &mut impl Write) -> io::Result<()> {
+        let reason = match self.status {
+            200 => "OK",
+            201 => "Created",
+            204 => "No Content",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            403 => "Forbidden",
+            409 => "Conflict",
+            413 => "Content Too Large",
+            422 => "Unprocessable Content",
+            431 => "Request Header Fields Too Large",
+            503 => "Service Unavailable",
+            _ => "Internal Server Error",
+        };
+        write!(
+            stream,
+            "HTTP/1.1 {} {reason}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+            self.status,
+            self.body.len(),
+            self.body
+        )?;
+        stream.flush()
+    }
+}
+
+/// An alarm to add, the daemon picks its id.
+#[derive(Debug, Deserialize)]
+struct NewAlarm {
+    #[serde(default)]
+    name: Option<String>,
+    time: NaiveTime,
+    #[serde(default = "NewAlarm::default_volume")]
+    volume: f32,
+    #[serde(default = "Sound::get_default_name")]
+    sound: String,
+    #[serde(default)]
+    playlist: Vec<String>,
+    #[serde(default)]
+    sound_mode: SoundMode,
+    #[serde(default = "NewAlarm::enabled")]
+    enabled: bool,
+    #[serde(default)]
+    start: Option<NaiveDate>,
+    #[serde(default)]
+    end: Option<NaiveDate>,
+    #[serde(default)]
+    date: Option<NaiveDate>,
+    #[serde(default)]
+    exceptions: Vec<Exception>,
+    #[serde(default)]
+    cron: Option<String>,
+}
+
+impl NewAlarm {
+    const fn default_volume() -> f32 {
+        100.
+    }
+
+    const fn enabled() -> bool {
+        true
+    }
+}
+
+impl From<NewAlarm> for AlarmSpec {
+    fn from(new: NewAlarm) -> Self {
+        Self {
+            name: new.name,
+            time: new.time,
+            volume: new.volume,
+            sound: new.sound,
+            playlist: new.playlist,
+            sound_mode: new.sound_mode,
+            enabled: new.enabled,
+            start: new.start,
+            end: new.end,
+            date: new.date,
+            exceptions: new.exceptions,
+            cron: new.cron,
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum OneOrMany<T> {
+    One(T),
+    Many(Vec<T>),
+}
+
+impl<T> From<OneOrMany<T>> for Vec<T> {
+    fn from(value: OneOrMany<T>) -> Self {
+        match value {
+            OneOrMany::One(one) => vec![one],
+            OneOrMany::Many(many) => many,
+        }
+    }
+}
+
+fn daemon_error(e: io::Error) -> Response {
+    Response::error(503, &format!("couldn't talk to roosty_clockd: {e}"))
+}
+
+/// Waits for the answer to a request.
+fn wait<T>(pending: io::Result<Pending<T>>) -> Result<T, Response> {
+    pending.and_then(Pending::wait).map_err(daemon_error)
+}
+
+fn alarm(client: &Client, id: u64) -> Result<config::Alarm, Response> {
+    wait(client.init())?
+        .alarms
+        .remove(&id)
+        .ok_or_else(|| Response::error(404, &format!("no alarm {id}")))
+}
+
+fn handle(stream: TcpStream, address: &Address) -> io::Result<()> {
+    stream.set_nonblocking(false)?;
+    stream.set_read_timeout(Some(IDLE_TIMEOUT))?;
+    stream.set_write_timeout(Some(IDLE_TIMEOUT))?;
+    let local = stream.local_addr()?.ip();
+    let mut reader = BufReader::new(stream.try_clone()?);
+    let mut stream = stream;
+    let request = match Request::read(&mut reader).and_then(|request| {
+        request.check_origin(local)?;
+        Ok(request)
+    }) {
+        Ok(request) => request,
+        Err(response) => return response.write(&mut stream),
+    };
+    log::debug!("HTTP {} {}", request.method, request.path);
+    if request.method == "GET" && Route::parse(&request.path) == Some(Route::Events) {
+        return events(stream, &request, address);
+    }
+    respond(&request, address)
+        .unwrap_or_else(|response| response)
+        .write(&mut stream)
+}
+
+fn respond(request: &Request, address: &Address) -> Result<Response, Response> {
+    let route =
+        Route::parse(&request.path).ok_or_else(|| Response::error(404, "no such resource"))?;
+    let mut client = Client::connect(address).map_err(daemon_error)?;
+    client.set_timeout(TIMEOUT);
+    match (request.method.as_str(), route) {
+        ("GET", Route::Alarms) => {
+            let mut alarms: Vec<_> = wait(client.init())?.alarms.into_values().collect();
+            alarms.sort_by_key(|alarm| alarm.id);
+            Ok(Response::json(200, &alarms))
+        }
+        ("POST", Route::Alarms) => {
+            let new: NewAlarm = request.json()?;
+            match wait(client.create_alarm(new.into()))? {
+                Ok(alarm) => Ok(Response::json(201, &alarm)),
+                Err(error) => Err(Response::error(422, &error.to_string())),
+            }
+        }
+        ("GET", Route::Alarm(id)) => Ok(Response::json(200, &alarm(&client, id)?)),
+        ("PATCH", Route::Alarm(id)) => {
+            let edits: OneOrMany<AlarmEdit> = request.json()?;
+            let ops = Vec::from(edits)
+                .into_iter()
+                .map(|edit| Op::Set(id, edit))
+                .collect();
+            match wait(client.batch(ops))? {
+                Ok(_) => Ok(Response::json(200, &alarm(&client, id)?)),
+                Err((_, AlarmError::NoSuchAlarm(id))) => {
+                    Err(Response::error(404, &format!("no alarm {id}")))
+                }
+                Err((_, error)) => Err(Response::error(422, &error.to_string())),
+            }
+        }
+        ("DELETE", Route::Alarm(id)) => {
+            alarm(&client, id)?;
+            wait(client.remove_alarm(id))?;
+            Ok(Response::empty())
+        }
+        ("POST", Route::StopAlarm(id)) => {
+            alarm(&client, id)?;
+            wait(client.stop_alarm(id))?;
+            Ok(Response::empty())
+        }
+        ("GET", Route::Sounds) => {
+            let mut sounds: Vec<_> = wait(client.init())?.sounds.into_values().collect();
+            sounds.sort_by(|a, b| a.name.cmp(&b.name));
+            Ok(Response::json(200, &sounds))
+        }
+        ("POST", Route::Sounds) => {
+            let sounds: OneOrMany<Sound> = request.json()?;
+            wait(client.add_sounds(sounds.into()))?;
+            Ok(Response::empty())
+        }
+        ("DELETE", Route::Sound(name)) => {
+            let policy = match request.query.get("policy").map(String::as_str) {
+                None | Some("refuse") => SoundRemoval::Refuse,
+                Some("reassign") => SoundRemoval::Reassign,
+                Some("disable") => SoundRemoval::Disable,
+                Some(policy) => {
+                    return Err(Response::error(400, &format!("unknown policy {policy}")));
+                }
+            };
+            Ok(match wait(client.remove_sound(name, policy))? {
+                Ok(affected) => Response::json(200, &serde_json::json!({ "affected": affected })),
+                Err(error) => Response::json(409, &serde_json::json!({ "error": error })),
+            })
+        }
+        ("POST", Route::Batch) => {
+            let ops: Vec<Op> = request.json()?;
+            Ok(match wait(client.batch(ops))? {
+                Ok(changes) => Response::json(200, &changes),
+                Err((op, error)) => Response::json(
+                    422,
+                    &serde_json::json!({ "error": error.to_string(), "op": op }),
+                ),
+            })
+        }
+        ("GET", Route::History) => {
+            let time = |key| {
+                request
+                    .query
+                    .get(key)
+                    .map(|time: &String| {
+                        DateTime::parse_from_rfc3339(time)
+                            .map(|time| time.with_timezone(&Local))
+                            .map_err(|e| Response::error(400, &format!("bad {key}: {e}")))
+                    })
+                    .transpose()
+            };
+            let alarm = request
+                .query
+                .get("alarm")
+                .map(|alarm| alarm.parse())
+                .transpose()
+                .map_err(|e| Response::error(400, &format!("bad alarm: {e}")))?;
+            let entries = wait(client.history(time("since")?, time("until")?, alarm))?;
+            Ok(Response::json(200, &entries))
+        }
+        _ => Err(Response::error(405, "method not allowed")),
+    }
+}
+
+/// The filter from the `topics` and `alarms` (comma separated) of the query.
+fn query_filter(request: &Request) -> Result<Filter, Response> {
+    let list = |key| {
+        request
+            .query
+            .get(key)
+            .map(|list: &String| list.split(',').filter(|item| !item.is_empty()))
+    };
+    let topics = list("topics")
+        .into_iter()
+        .flatten()
+        .map(|topic| match topic {
+            "alarms" => Ok(Topic::Alarms),
+            "ringing" => Ok(Topic::Ringing),
+            "sounds" => Ok(Topic::Sounds),
+            topic => Err(Response::error(400, &format!("unknown topic {topic}"))),
+        })
+        .collect::<Result<_, _>>()?;
+    let alarms = list("alarms")
+        .map(|alarms| alarms.map(str::parse).collect::<Result<_, _>>())
+        .transpose()
+        .map_err(|e| Response::error(400, &format!("bad alarm: {e}")))?;
+    Ok(Filter { topics, alarms })
+}
+
+/// Streams every message from the daemon as server-sent events, starting with its current state.
+fn events(mut stream: TcpStream, request: &Request, address: &Address) -> io::Result<()> {
+    let filter = match query_filter(request) {
+        Ok(filter) => filter,
+        Err(response) => return response.write(&mut stream),
+    };
+    let client = match Client::connect(address) {
+        Ok(client) => client,
+        Err(e) => return daemon_error(e).write(&mut stream),
+    };
+    let events = client.subscribe();
+    if filter != Filter::default()
+        && let Err(response) = wait(client.set_filter(filter))
+    {
+        return response.write(&mut stream);
+    }
+    if let Err(e) = client.send(&ClientMessage::Init) {
+        return daemon_error(e).write(&mut stream);
+    }
+    write!(
+        stream,
+        "HTTP/1.1 200 OK\r\nContent-Type: text/event-stream\r\nCache-Control: no-cache\r\nConnection: close\r\n\r\n"
+    )?;
+    stream.flush()?;
+    for message in events {
+        let done = matches!(message, ServerMessage::ShuttingDown);
+        // nobody can send an Init through a stream, so do it for them
+        if message == ServerMessage::ResyncRequired {
+            client.send(&ClientMessage::Init)?;
+        }
+        let json = serde_json::to_string(&message).map_err(io::Error::other)?;
+        write!(stream, "data: {json}\n\n")?;
+        stream.flush()?;
+        if done {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{collections::HashSet, io::Read, path::PathBuf};
+
+    use interprocess::local_socket::prelude::*;
+
+    use super::*;
+    use crate::encoding::Encoding;
+
+    #[test]
+    fn parses_requests() {
+        let raw = b"PATCH /alarms/3?x=a%20b HTTP/1.1\r\nHost: localhost\r\nContent-Length: 13\r\n\r\n{\"Volume\":50}";
+        let request = Request::read(&mut &raw[..]).unwrap();
+        assert_eq!(request.method, "PATCH");
+        assert_eq!(request.path, "/alarms/3");
+        assert_eq!(request.query["x"], "a b");
+        assert!(matches!(
+            request.json::<OneOrMany<AlarmEdit>>(),
+            Ok(OneOrMany::One(AlarmEdit::Volume(50.)))
+        ));
+    }
+
+    #[test]
+    fn refuses_big_requests() {
+        let raw = format!(
+            "POST /batch HTTP/1.1\r\nContent-Length: {}\r\n\r\n",
+            MAX_BODY + 1
+        );
+        assert_eq!(Request::read(&mut raw.as_bytes()).unwrap_err().status, 413);
+        let raw = format!(
+            "GET /alarms HTTP/1.1\r\nX-Junk: {}\r\n\r\n",
+            "a".repeat(20_000)
+        );
+        assert_eq!(Request::read(&mut raw.as_bytes()).unwrap_err().status, 431);
+    }
+
+    #[test]
+    fn refuses_other_hosts_and_origins() {
+        let local = IpAddr::from([192, 168, 1, 2]);
+        let check = |headers: &str| {
+            let raw = format!("GET /alarms HTTP/1.1\r\n{headers}\r\n");
+            Request::read(&mut raw.as_bytes())
+                .unwrap()
+                .check_origin(local)
+                .map_err(|response| response.status)
+        };
+        assert_eq!(check(""), Ok(()));
+        assert_eq!(check("Host: localhost:8737\r\n"), Ok(()));
+        assert_eq!(check("Host: 127.0.0.1:8737\r\n"), Ok(()));
+        assert_eq!(check("Host: [::1]:8737\r\n"), Ok(()));
+        assert_eq!(check("Host: [::1]\r\n"), Ok(()));
+        assert_eq!(check("Host: 192.168.1.2:8737\r\n"), Ok(()));
+        assert_eq!(
+            check("Host: localhost:8737\r\nOrigin: http://localhost:8737\r\n"),
+            Ok(())
+        );
+        assert_eq!(check("Host: evil.example:8737\r\n"), Err(403));
+        assert_eq!(check("Host: 192.168.1.3:8737\r\n"), Err(403));
+        assert_eq!(
+            check("Host: localhost:8737\r\nOrigin: https://evil.example\r\n"),
+            Err(403)
+        );
+        assert_eq!(check("Host: localhost:8737\r\nOrigin: null\r\n"), Err(403));
+    }
+
+    #[test]
+    fn only_listens_on_loopback() {
+        let refused = |address: &str| {
+            bind(address.parse().unwrap())
+                .is_err_and(|e| e.kind() == io::ErrorKind::PermissionDenied)
+        };
+        assert!(refused("0.0.0.0:0"));
+        assert!(refused("[::]:0"));
+        assert!(refused("192.168.1.2:0"));
+        assert!(bind("127.0.0.1:0".parse().unwrap()).is_ok());
+    }
+
+    #[test]
+    fn parses_filters() {
+        let raw = b"GET /events?topics=ringing,alarms&alarms=3,4 HTTP/1.1\r\n\r\n";
+        let filter = query_filter(&Request::read(&mut &raw[..]).unwrap()).unwrap();
+        assert_eq!(
+            filter.topics,
+            HashSet::from([Topic::Ringing, Topic::Alarms])
+        );
+        assert_eq!(filter.alarms, Some(HashSet::from([3, 4])));
+        let raw = b"GET /events HTTP/1.1\r\n\r\n";
+        let filter = query_filter(&Request::read(&mut &raw[..]).unwrap()).unwrap();
+        assert_eq!(filter, Filter::default());
+        let raw = b"GET /events?topics=timers HTTP/1.1\r\n\r\n";
+        assert!(query_filter(&Request::read(&mut &raw[..]).unwrap()).is_err());
+    }
+
+    #[test]
+    fn parses_routes() {
+        assert_eq!(Route::parse("/alarms"), Some(Route::Alarms));
+        assert_eq!(Route::parse("/alarms/"), Some(Route::Alarms));
+        assert_eq!(Route::parse("/alarms/7"), Some(Route::Alarm(7)));
+        assert_eq!(Route::parse("/alarms/7/stop"), Some(Route::StopAlarm(7)));
+        assert_eq!(Route::parse("/batch"), Some(Route::Batch));
+        assert_eq!(
+            Route::parse(&percent_decode("/sounds/bing%20bong")),
+            Some(Route::Sound("bing bong".to_string()))
+        );
+        assert_eq!(Route::parse("/alarms/seven"), None);
+        assert_eq!(Route::parse("/timers"), None);
+    }
+
+    /// a daemon that answers an `Init` request with one alarm, for one client
+    fn fake_daemon(name: &str) -> Address {
+        let address = Address::Path(PathBuf::from(format!(
+            "{}/roosty-clockd-http-{}-{name}.sock",
+            std::env::temp_dir().display(),
+            std::process::id()
+        )));
+        let _ = std::fs::remove_file(address.to_string());
+        let listener = address.listen().unwrap();
+        thread::spawn(move || {
+            let (recv, mut send) = listener.accept().unwrap().split();
+            let mut recv = BufReader::new(recv);
+            let encoding = Encoding::accept(&mut recv).unwrap();
+            let mut bytes = Vec::new();
+            let Ok(ClientMessage::Request(id, message)) = encoding.recieve(&mut recv, &mut bytes)
+            else {
+                panic!("expected a request");
+            };
+            assert_eq!(*message, ClientMessage::Init);
+            let alarm = config::Alarm {
+                name: Some("wake up".to_string()),
+                time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+                volume: 80.,
+                sound: Sound::get_default_name(),
+                playlist: Vec::new(),
+                sound_mode: SoundMode::Single,
+                enabled: true,
+                start: None,
+                end: None,
+                date: None,
+                skip: None,
+                exceptions: Vec::new(),
+                cron: None,
+                id: 1,
+            };
+            let message = ServerMessage::Init {
+                alarms: HashMap::from([(1, alarm)]),
+                sounds: HashMap::new(),
+                ringing_alarms: HashSet::new(),
+            };
+            encoding
+                .send(&mut send, &message.respond_to(Some(id)))
+                .unwrap();
+            // keep the connection open until the client is done with it
+            let _ = encoding.recieve::<ClientMessage>(&mut recv, &mut bytes);
+        });
+        address
+    }
+
+    fn get(listener: &TcpListener, path: &str) -> String {
+        let mut stream = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_alarms_from_daemon() {
+        let address = fake_daemon("alarms");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let shutdown = Arc::new(AtomicBool::new(false));
+        let server = serve(
+            listener.try_clone().unwrap(),
+            address.clone(),
+            shutdown.clone(),
+        );
+
+        let response = get(&listener, "/alarms");
+        assert!(response.starts_with("HTTP/1.1 200 OK"), "{response}");
+        let body = response.split("\r\n\r\n").nth(1).unwrap();
+        let alarms: Vec<config::Alarm> = serde_json::from_str(body).unwrap();
+        assert_eq!(alarms.len(), 1);
+        assert_eq!(alarms[0].name.as_deref(), Some("wake up"));
+        assert_eq!(alarms[0].id, 1);
+
+        let response = get(&listener, "/nothing");
+        assert!(response.starts_with("HTTP/1.1 404"), "{response}");
+
+        shutdown.store(true, Ordering::SeqCst);
+        server.join().unwrap();
+        let _ = std::fs::remove_file(address.to_string());
+    }
+}
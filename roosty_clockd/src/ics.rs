@@ -0,0 +1,1171 @@
+//! iCalendar (`.ics`) files, for holidays and for moving alarms to and from calendar apps
+//!
+//! Only as much of [RFC 5545](https://www.rfc-editor.org/rfc/rfc5545) as is needed is understood:
+//! lines are unfolded and split into components and properties, and everything else about an
+//! entry that isn't understood is reported instead of guessed at.
+//!
+//! An alarm is a `VEVENT` with a `VALARM` when it starts. Alarms that ring every day repeat daily
+//! (`RRULE`) until their end date, their skipped day and exceptions are left out (`EXDATE`, only
+//! for the next year), and what calendars don't have (like the sound or the whole exceptions) is
+//! kept in `X-ROOSTY-` properties so importing the file again gives back the same alarms. That
+//! includes cron expressions, calendar apps only see those alarms at their time every day. The
+//! `UID` of an event is the alarm it came from, importing it again updates that alarm.
+use std::{
+    collections::{BTreeSet, HashMap, HashSet},
+    error, fmt,
+};
+
+use chrono::{DateTime, Days, Local, NaiveDate, NaiveDateTime, TimeDelta, TimeZone, Utc};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    AlarmSpec, Change,
+    config::{self, Exception, SoundMode},
+};
+
+/// the longest a line can be (in bytes), longer ones are folded
+const LINE_LENGTH: usize = 75;
+
+const PRODID: &str = "PRODID:-//roosty_clock//alarms//EN";
+
+/// how deep components are nested at most, like an alarm in an event in a calendar
+const MAX_DEPTH: usize = 3;
+
+/// how many days ahead the days an alarm doesn't ring on are written as `EXDATE`s
+const EXDATE_DAYS: u64 = 366;
+
+/// the end of the `UID` of an event for an alarm, after its id
+const UID_DOMAIN: &str = "@roosty_clock";
+
+/// how date-times are written, without a time zone (so in the local time wherever it's read)
+const DATE_TIME: &str = "%Y%m%dT%H%M%S";
+
+/// a `BEGIN:<name>` ... `END:<name>` block
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Component {
+    pub name: String,
+    pub properties: Vec<Property>,
+    pub components: Vec<Component>,
+}
+
+impl Component {
+    /// the first property called `name`
+    #[must_use]
+    pub fn property(&self, name: &str) -> Option<&Property> {
+        self.properties
+            .iter()
+            .find(|property| property.name.eq_ignore_ascii_case(name))
+    }
+
+    /// the components in this one called `name`
+    pub fn components<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Self> {
+        self.components
+            .iter()
+            .filter(move |component| component.name.eq_ignore_ascii_case(name))
+    }
+}
+
+/// a `NAME;PARAM=value:value` line
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Property {
+    pub name: String,
+    pub params: Vec<(String, String)>,
+    /// still escaped, see [`unescape`]
+    pub value: String,
+}
+
+impl Property {
+    #[must_use]
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// why a file couldn't be read
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// the line (counting from 1 after unfolding) isn't a property
+    BadLine(usize),
+    /// an `END` without a `BEGIN` (or for a different component)
+    Unexpected(String),
+    /// a `BEGIN` that is never ended
+    Unclosed(String),
+    /// there is no `VCALENDAR`
+    NotACalendar,
+    /// a `BEGIN` nested deeper than an alarm in an event in a calendar
+    TooDeep(String),
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::BadLine(line) => write!(f, "line {line} isn't a property"),
+            Self::Unexpected(name) => write!(f, "END:{name} without a BEGIN:{name}"),
+            Self::Unclosed(name) => write!(f, "BEGIN:{name} is never ended"),
+            Self::NotACalendar => write!(f, "there is no calendar in the file"),
+            Self::TooDeep(name) => write!(
+                f,
+                "BEGIN:{name} is nested deeper than {MAX_DEPTH} components"
+            ),
+        }
+    }
+}
+
+impl error::Error for ParseError {}
+
+/// an alarm from a calendar, with the id of the alarm it was exported from (if it was)
+pub type Imported = (Option<u64>, AlarmSpec);
+
+/// something about an entry that was left out when importing it (or all of it)
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq)]
+pub struct Warning {
+    /// the summary of the entry, or its start if it has none
+    pub entry: String,
+    pub problem: String,
+}
+
+impl Warning {
+    fn new(event: &Component, problem: impl Into<String>) -> Self {
+        let entry = event
+            .property("SUMMARY")
+            .map(|summary| unescape(&summary.value))
+            .or_else(|| event.property("DTSTART").map(|start| start.value.clone()))
+            .unwrap_or_else(|| "an event".to_string());
+        Self {
+            entry,
+            problem: problem.into(),
+        }
+    }
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}: {}", self.entry, self.problem)
+    }
+}
+
+/// joins the lines that were folded (continued on the next line, starting with a space or tab)
+fn unfold(text: &str) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    for line in text.lines() {
+        match (line.strip_prefix([' ', '\t']), lines.last_mut()) {
+            (Some(rest), Some(last)) => last.push_str(rest),
+            _ if line.is_empty() => {}
+            _ => lines.push(line.to_string()),
+        }
+    }
+    lines
+}
+
+fn property(line: &str) -> Option<Property> {
+    // the first colon that isn't in a quoted parameter value
+    let mut quoted = false;
+    let colon = line.char_indices().find_map(|(i, c)| {
+        match c {
+            '"' => quoted = !quoted,
+            ':' if !quoted => return Some(i),
+            _ => {}
+        }
+        None
+    })?;
+    let (name, value) = (&line[..colon], &line[colon + 1..]);
+    let mut parts = name.split(';');
+    let name = parts.next()?.trim().to_ascii_uppercase();
+    if name.is_empty() {
+        return None;
+    }
+    let params = parts
+        .map(|param| {
+            let (name, value) = param.split_once('=')?;
+            Some((
+                name.to_ascii_uppercase(),
+                value.trim_matches('"').to_string(),
+            ))
+        })
+        .collect::<Option<_>>()?;
+    Some(Property {
+        name,
+        params,
+        value: value.to_string(),
+    })
+}
+
+/// Reads the calendar in `text`.
+///
+/// # Errors
+/// If it isn't a calendar, or a line isn't a property.
+pub fn parse(text: &str) -> Result<Component, ParseError> {
+    // the components that haven't ended yet, the first one holds everything
+    let mut open = vec![Component::default()];
+    for (i, line) in unfold(text).iter().enumerate() {
+        let property = property(line).ok_or(ParseError::BadLine(i + 1))?;
+        match property.name.as_str() {
+            // the tree is dropped recursively, so it can't be as deep as the file says
+            "BEGIN" if open.len() > MAX_DEPTH => {
+                return Err(ParseError::TooDeep(property.value.to_ascii_uppercase()));
+            }
+            "BEGIN" => open.push(Component {
+                name: property.value.to_ascii_uppercase(),
+                ..Component::default()
+            }),
+            "END" => {
+                let name = property.value.to_ascii_uppercase();
+                if open.len() < 2 || open.last().is_some_and(|last| last.name != name) {
+                    return Err(ParseError::Unexpected(name));
+                }
+                let ended = open.pop().unwrap();
+                open.last_mut().unwrap().components.push(ended);
+            }
+            _ => open.last_mut().unwrap().properties.push(property),
+        }
+    }
+    let mut open = open.into_iter();
+    let root = open.next().unwrap();
+    if let Some(unclosed) = open.next_back() {
+        return Err(ParseError::Unclosed(unclosed.name));
+    }
+    root.components
+        .into_iter()
+        .find(|component| component.name == "VCALENDAR")
+        .ok_or(ParseError::NotACalendar)
+}
+
+/// Writes `line`, folding it so no line is longer than [`LINE_LENGTH`].
+fn line(out: &mut String, line: &str) {
+    let mut rest = line;
+    let mut length = LINE_LENGTH;
+    while rest.len() > length {
+        let mut at = length;
+        while !rest.is_char_boundary(at) {
+            at -= 1;
+        }
+        out.push_str(&rest[..at]);
+        out.push_str("\r\n ");
+        rest = &rest[at..];
+        // the space counts too
+        length = LINE_LENGTH - 1;
+    }
+    out.push_str(rest);
+    out.push_str("\r\n");
+}
+
+/// escapes what [`unescape`] unescapes
+#[must_use]
+pub fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace('\n', "\\n")
+}
+
+/// turns `\n`, `\,`, `\;` and `\\` back into what they stand for
+#[must_use]
+pub fn unescape(value: &str) -> String {
+    let mut unescaped = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('n' | 'N')) => {
+                chars.next();
+                unescaped.push('\n');
+            }
+            ('\\', Some(escaped @ (',' | ';' | '\\'))) => {
+                chars.next();
+                unescaped.push(escaped);
+            }
+            _ => unescaped.push(c),
+        }
+    }
+    unescaped
+}
+
+/// A date (`20261224`) or the date of a date-time (`20261224T070000`, with or without a `Z`).
+#[must_use]
+pub fn date(value: &str) -> Option<NaiveDate> {
+    NaiveDate::parse_from_str(value.get(..8)?, "%Y%m%d").ok()
+}
+
+/// A date-time (`20261224T070000`, with or without a `Z`), a date is at midnight.
+#[must_use]
+pub fn date_time(value: &str) -> Option<NaiveDateTime> {
+    let value = value.trim_end_matches('Z');
+    NaiveDateTime::parse_from_str(value, "%Y%m%dT%H%M%S")
+        .ok()
+        .or_else(|| date(value).and_then(|date| date.and_hms_opt(0, 0, 0)))
+}
+
+/// A date or date-time property in local time (ones in UTC, ending with a `Z`, are converted).
+fn local_time(value: &str) -> Option<NaiveDateTime> {
+    let time = date_time(value)?;
+    if value.ends_with('Z') {
+        Some(
+            Utc.from_utc_datetime(&time)
+                .with_timezone(&Local)
+                .naive_local(),
+        )
+    } else {
+        Some(time)
+    }
+}
+
+/// A duration, like `-PT15M` (how long before the start a reminder is) or `P1DT2H`.
+#[must_use]
+pub fn duration(value: &str) -> Option<TimeDelta> {
+    let (sign, rest) = match value.strip_prefix('-') {
+        Some(rest) => (-1, rest),
+        None => (1, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let mut total = TimeDelta::zero();
+    let mut number = String::new();
+    let mut in_time = false;
+    let mut any = false;
+    for c in rest.strip_prefix('P')?.chars() {
+        match c {
+            '0'..='9' => number.push(c),
+            'T' if number.is_empty() && !in_time => in_time = true,
+            _ => {
+                let n = number.parse().ok()?;
+                number.clear();
+                any = true;
+                total += match (c, in_time) {
+                    ('W', false) => TimeDelta::try_weeks(n)?,
+                    ('D', false) => TimeDelta::try_days(n)?,
+                    ('H', true) => TimeDelta::try_hours(n)?,
+                    ('M', true) => TimeDelta::try_minutes(n)?,
+                    ('S', true) => TimeDelta::try_seconds(n)?,
+                    _ => return None,
+                };
+            }
+        }
+    }
+    (any && number.is_empty()).then_some(total * sign)
+}
+
+const fn sound_mode_name(sound_mode: SoundMode) -> &'static str {
+    match sound_mode {
+        SoundMode::Single => "SINGLE",
+        SoundMode::Playlist => "PLAYLIST",
+        SoundMode::Shuffle => "SHUFFLE",
+        SoundMode::Daily => "DAILY",
+    }
+}
+
+fn sound_mode(name: &str) -> Option<SoundMode> {
+    [
+        SoundMode::Single,
+        SoundMode::Playlist,
+        SoundMode::Shuffle,
+        SoundMode::Daily,
+    ]
+    .into_iter()
+    .find(|sound_mode| sound_mode_name(*sound_mode).eq_ignore_ascii_case(name))
+}
+
+/// A calendar with an event for each of `alarms`. Daily alarms without a start date start on the
+/// day of `now`.
+#[must_use]
+pub fn export<'a>(
+    alarms: impl IntoIterator<Item = &'a config::Alarm>,
+    now: DateTime<Local>,
+) -> String {
+    let mut out = String::new();
+    for l in ["BEGIN:VCALENDAR", "VERSION:2.0", PRODID] {
+        line(&mut out, l);
+    }
+    for alarm in alarms {
+        event(&mut out, alarm, now);
+    }
+    line(&mut out, "END:VCALENDAR");
+    out
+}
+
+fn event(out: &mut String, alarm: &config::Alarm, now: DateTime<Local>) {
+    let first = alarm.date.or(alarm.start).unwrap_or(now.date_naive());
+    let at = |day: NaiveDate| day.and_time(alarm.time).format(DATE_TIME).to_string();
+    line(out, "BEGIN:VEVENT");
+    line(out, &format!("UID:{}{UID_DOMAIN}", alarm.id));
+    line(
+        out,
+        &format!(
+            "DTSTAMP:{}",
+            now.with_timezone(&Utc).format("%Y%m%dT%H%M%SZ")
+        ),
+    );
+    line(out, &format!("DTSTART:{}", at(first)));
+    if let Some(name) = &alarm.name {
+        line(out, &format!("SUMMARY:{}", escape(name)));
+    }
+    if alarm.date.is_none() {
+        match alarm.end {
+            Some(end) => line(out, &format!("RRULE:FREQ=DAILY;UNTIL={}", at(end))),
+            None => line(out, "RRULE:FREQ=DAILY"),
+        }
+    }
+    // exceptions can be years long, calendar apps only get the days of the next year
+    let today = now.date_naive();
+    let horizon = today + Days::new(EXDATE_DAYS);
+    let skip = alarm.skip.map(Exception::day);
+    let skipped: BTreeSet<_> = alarm
+        .exceptions
+        .iter()
+        .chain(&skip)
+        .flat_map(|exception| {
+            exception
+                .start
+                .max(today)
+                .iter_days()
+                .take_while(|day| *day <= exception.end && *day < horizon)
+        })
+        .collect();
+    if !skipped.is_empty() {
+        let skipped: Vec<_> = skipped.into_iter().map(at).collect();
+        line(out, &format!("EXDATE:{}", skipped.join(",")));
+    }
+    for exception in &alarm.exceptions {
+        let mut value = format!(
+            "X-ROOSTY-EXCEPTION:{}/{}",
+            exception.start.format("%Y%m%d"),
+            exception.end.format("%Y%m%d")
+        );
+        if let Some(name) = &exception.name {
+            value.push('/');
+            value.push_str(&escape(name));
+        }
+        line(out, &value);
+    }
+    line(out, &format!("X-ROOSTY-SOUND:{}", escape(&alarm.sound)));
+    if !alarm.playlist.is_empty() {
+        let playlist: Vec<_> = alarm.playlist.iter().map(|sound| escape(sound)).collect();
+        line(out, &format!("X-ROOSTY-PLAYLIST:{}", playlist.join(",")));
+    }
+    if alarm.sound_mode != SoundMode::Single {
+        line(
+            out,
+            &format!("X-ROOSTY-SOUND-MODE:{}", sound_mode_name(alarm.sound_mode)),
+        );
+    }
+    line(out, &format!("X-ROOSTY-VOLUME:{}", alarm.volume));
+    if !alarm.enabled {
+        line(out, "X-ROOSTY-ENABLED:FALSE");
+    }
+    if let Some(cron) = &alarm.cron {
+        line(out, &format!("X-ROOSTY-CRON:{}", escape(cron)));
+    }
+    for l in [
+        "BEGIN:VALARM",
+        "ACTION:AUDIO",
+        "TRIGGER:PT0S",
+        "END:VALARM",
+        "END:VEVENT",
+    ] {
+        line(out, l);
+    }
+}
+
+/// how long a repeating event repeats
+enum Repeat {
+    Forever,
+    /// the last time it can start
+    Until(NaiveDateTime),
+    Count(u64),
+}
+
+/// How long a rule that repeats every day repeats, or why the rule isn't one.
+fn daily(rule: &str) -> Result<Repeat, String> {
+    let unsupported = || format!("repeats by {rule}, which alarms can't, skipped");
+    let mut frequency = None;
+    let mut every_weekday = None;
+    let mut repeat = Repeat::Forever;
+    for part in rule.split(';') {
+        let (name, value) = part.split_once('=').ok_or_else(unsupported)?;
+        match (name.to_ascii_uppercase().as_str(), value) {
+            ("FREQ", value) => frequency = Some(value.to_ascii_uppercase()),
+            ("BYDAY", days) => {
+                let days: Vec<_> = days.split(',').map(str::to_ascii_uppercase).collect();
+                every_weekday = Some(
+                    ["MO", "TU", "WE", "TH", "FR", "SA", "SU"]
+                        .iter()
+                        .all(|day| days.iter().any(|d| d == day)),
+                );
+            }
+            ("INTERVAL", "1") | ("WKST", _) => {}
+            ("UNTIL", until) => {
+                repeat = Repeat::Until(local_time(until).ok_or_else(unsupported)?);
+            }
+            ("COUNT", count) => repeat = Repeat::Count(count.parse().map_err(|_| unsupported())?),
+            _ => return Err(unsupported()),
+        }
+    }
+    // every day of the week is every day too
+    match (frequency.as_deref(), every_weekday) {
+        (Some("DAILY"), None | Some(true)) | (Some("WEEKLY"), Some(true)) => Ok(repeat),
+        _ => Err(unsupported()),
+    }
+}
+
+/// The alarms for the reminders of one event (see [`alarms`]), with what couldn't be imported
+/// added to `warnings`.
+fn reminders(
+    event: &Component,
+    sounds: &config::Sounds,
+    warnings: &mut Vec<Warning>,
+) -> Vec<Imported> {
+    let warn =
+        |warnings: &mut Vec<Warning>, problem: String| warnings.push(Warning::new(event, problem));
+    let reminders: Vec<_> = event.components("VALARM").collect();
+    if reminders.is_empty() {
+        warn(warnings, "has no reminders, skipped".to_string());
+        return Vec::new();
+    }
+    let Some((start, start_value)) = event
+        .property("DTSTART")
+        .and_then(|start| Some((local_time(&start.value)?, start)))
+    else {
+        warn(warnings, "has no start, skipped".to_string());
+        return Vec::new();
+    };
+    if let Some(zone) = start_value.param("TZID") {
+        warn(
+            warnings,
+            format!("the time zone {zone} isn't supported, the times are taken as local times"),
+        );
+    }
+    let repeat = match event
+        .property("RRULE")
+        .map(|rule| daily(&rule.value))
+        .transpose()
+    {
+        Ok(repeat) => repeat,
+        Err(problem) => {
+            warn(warnings, problem);
+            return Vec::new();
+        }
+    };
+    for ignored in ["RDATE", "EXRULE"] {
+        if event.property(ignored).is_some() {
+            warn(
+                warnings,
+                format!("{ignored} isn't supported, it was left out"),
+            );
+        }
+    }
+    let end = event
+        .property("DTEND")
+        .and_then(|end| local_time(&end.value))
+        .or_else(|| Some(start + duration(&event.property("DURATION")?.value)?))
+        .unwrap_or(start);
+    // the whole exceptions if roosty_clock exported them, the other days left out (like the
+    // skipped day) are single days
+    let mut exceptions: Vec<_> = event
+        .properties
+        .iter()
+        .filter(|property| property.name == "X-ROOSTY-EXCEPTION")
+        .filter_map(|property| {
+            let mut parts = property.value.splitn(3, '/');
+            Some(Exception {
+                start: date(parts.next()?)?,
+                end: date(parts.next()?)?,
+                name: parts.next().map(unescape),
+            })
+        })
+        .collect();
+    let exdates: Vec<_> = event
+        .properties
+        .iter()
+        .filter(|property| property.name == "EXDATE")
+        .flat_map(|property| property.value.split(','))
+        .filter_map(|day| Some(local_time(day)?.date()))
+        .filter(|day| !exceptions.iter().any(|exception| exception.contains(*day)))
+        .collect();
+    exceptions.extend(exdates.into_iter().map(Exception::day));
+    // only the first reminder can update the alarm the event came from
+    let mut id = event
+        .property("UID")
+        .and_then(|uid| uid.value.strip_suffix(UID_DOMAIN)?.parse().ok());
+    let property = |name| {
+        event
+            .property(name)
+            .map(|property| unescape(&property.value))
+    };
+
+    let mut alarms = Vec::new();
+    for reminder in reminders {
+        let Some(trigger) = reminder.property("TRIGGER") else {
+            warn(
+                warnings,
+                "a reminder has no trigger, it was left out".to_string(),
+            );
+            continue;
+        };
+        let absolute = trigger
+            .param("VALUE")
+            .is_some_and(|value| value.eq_ignore_ascii_case("DATE-TIME"));
+        let ring = if absolute {
+            local_time(&trigger.value)
+        } else {
+            let from = if trigger
+                .param("RELATED")
+                .is_some_and(|related| related.eq_ignore_ascii_case("END"))
+            {
+                end
+            } else {
+                start
+            };
+            duration(&trigger.value).and_then(|offset| from.checked_add_signed(offset))
+        };
+        let Some(ring) = ring else {
+            warn(
+                warnings,
+                format!(
+                    "a reminder's trigger {} isn't understood, it was left out",
+                    trigger.value
+                ),
+            );
+            continue;
+        };
+        // a reminder a day before rings a day before every time
+        let shift = ring.date() - start.date();
+        let repeat = match &repeat {
+            Some(_) if absolute => {
+                warn(
+                    warnings,
+                    "a reminder at a fixed time only rings once".to_string(),
+                );
+                None
+            }
+            repeat => repeat.as_ref(),
+        };
+        let last = match repeat {
+            None | Some(Repeat::Forever) => None,
+            Some(Repeat::Until(until)) if until.time() >= start.time() => {
+                Some(until.date() + shift)
+            }
+            Some(Repeat::Until(until)) => until.date().pred_opt().map(|day| day + shift),
+            Some(Repeat::Count(count)) => start
+                .date()
+                .checked_add_days(chrono::Days::new(count.saturating_sub(1)))
+                .map(|day| day + shift),
+        };
+        let mut spec = AlarmSpec {
+            name: property("SUMMARY"),
+            time: ring.time(),
+            volume: property("X-ROOSTY-VOLUME")
+                .and_then(|volume| volume.parse().ok())
+                .unwrap_or(100.),
+            sound: property("X-ROOSTY-SOUND").unwrap_or_else(|| sounds.default_sound.clone()),
+            playlist: event
+                .property("X-ROOSTY-PLAYLIST")
+                .map(|playlist| split(&playlist.value))
+                .unwrap_or_default(),
+            sound_mode: property("X-ROOSTY-SOUND-MODE")
+                .and_then(|name| sound_mode(&name))
+                .unwrap_or_default(),
+            enabled: !property("X-ROOSTY-ENABLED")
+                .is_some_and(|enabled| enabled.eq_ignore_ascii_case("FALSE")),
+            start: repeat.map(|_| ring.date()),
+            end: last,
+            date: repeat.is_none().then(|| ring.date()),
+            exceptions: exceptions
+                .iter()
+                .map(|exception| Exception {
+                    start: exception.start + shift,
+                    end: exception.end + shift,
+                    ..exception.clone()
+                })
+                .collect(),
+            cron: property("X-ROOSTY-CRON"),
+        };
+        // sounds from another computer might not be here
+        if !sounds.sounds.contains_key(&spec.sound) {
+            warn(
+                warnings,
+                format!(
+                    "there is no sound {}, {} is used instead",
+                    spec.sound, sounds.default_sound
+                ),
+            );
+            spec.sound = sounds.default_sound.clone();
+        }
+        let (playlist, missing): (Vec<_>, Vec<_>) = spec
+            .playlist
+            .into_iter()
+            .partition(|sound| sounds.sounds.contains_key(sound));
+        if !missing.is_empty() {
+            warn(
+                warnings,
+                format!(
+                    "there are no sounds {}, they were left out of the playlist",
+                    missing.join(", ")
+                ),
+            );
+        }
+        spec.playlist = playlist;
+        match spec.validate(&sounds.sounds) {
+            Ok(()) => alarms.push((id.take(), spec)),
+            Err(error) => warn(warnings, format!("{error}, skipped")),
+        }
+    }
+    alarms
+}
+
+/// splits a list of text values (at the commas that aren't escaped)
+fn split(value: &str) -> Vec<String> {
+    let mut values = vec![String::new()];
+    let mut escaped = false;
+    for c in value.chars() {
+        match c {
+            ',' if !escaped => values.push(String::new()),
+            _ => values.last_mut().unwrap().push(c),
+        }
+        escaped = c == '\\' && !escaped;
+    }
+    values.iter().map(|value| unescape(value)).collect()
+}
+
+/// An alarm for each reminder (`VALARM`) of the events in a calendar, and what couldn't be
+/// imported about each event. Events that repeat every day make alarms that ring every day (from
+/// the first time to the last), events that repeat by anything else are skipped. Alarms that use
+/// sounds that aren't in `sounds` get the default sound instead. Events exported by roosty_clock
+/// come with the id of their alarm.
+///
+/// # Errors
+/// If `text` isn't a calendar.
+pub fn alarms(
+    text: &str,
+    sounds: &config::Sounds,
+) -> Result<(Vec<Imported>, Vec<Warning>), ParseError> {
+    let calendar = parse(text)?;
+    let mut warnings = Vec::new();
+    let alarms = calendar
+        .components("VEVENT")
+        .flat_map(|event| reminders(event, sounds, &mut warnings))
+        .collect();
+    Ok((alarms, warnings))
+}
+
+/// What importing `imported` (from [`alarms`]) does to `alarms`: the ones that were exported from
+/// an alarm that's still there update it, the others are created with ids from `uid`.
+pub fn changes(
+    imported: Vec<Imported>,
+    alarms: &HashMap<u64, config::Alarm>,
+    mut uid: impl FnMut() -> u64,
+) -> Vec<Change> {
+    let mut updated = HashSet::new();
+    let mut changes = Vec::new();
+    for (id, spec) in imported {
+        let Some(old) = id
+            .filter(|id| updated.insert(*id))
+            .and_then(|id| alarms.get(&id))
+        else {
+            changes.push(Change::Created(spec.with_id(uid())));
+            continue;
+        };
+        // calendars don't have skipped days, it came back as an exception
+        let mut new = spec.with_id(old.id);
+        new.skip = old.skip;
+        if let Some(skip) = old.skip.map(Exception::day)
+            && !old.exceptions.contains(&skip)
+        {
+            new.exceptions.retain(|exception| *exception != skip);
+        }
+        changes.extend(
+            old.edits(&new)
+                .into_iter()
+                .map(|edit| Change::Set(old.id, edit)),
+        );
+    }
+    changes
+}
+
+/// How many alarms importing a calendar created and updated, like "2 new alarms, 1 updated".
+#[must_use]
+pub fn summary(changes: &[Change]) -> String {
+    let created = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Created(_)))
+        .count();
+    let updated: HashSet<_> = changes
+        .iter()
+        .filter(|change| matches!(change, Change::Set(..)))
+        .map(Change::alarm)
+        .collect();
+    format!("{created} new alarms, {} updated", updated.len())
+}
+
+/// The days of every event in a calendar (like the public holidays a calendar app exports), and
+/// what couldn't be understood about any of them.
+///
+/// # Errors
+/// If `text` isn't a calendar.
+pub fn holidays(text: &str) -> Result<(Vec<Exception>, Vec<Warning>), ParseError> {
+    let calendar = parse(text)?;
+    let mut holidays = Vec::new();
+    let mut warnings = Vec::new();
+    for event in calendar.components("VEVENT") {
+        let Some(start) = event
+            .property("DTSTART")
+            .and_then(|start| date(&start.value))
+        else {
+            warnings.push(Warning::new(event, "no start date, skipped"));
+            continue;
+        };
+        let end = match event.property("DTEND") {
+            // the end of an all day event is the day after it
+            Some(end) if end.value.len() == 8 => date(&end.value).and_then(|end| end.pred_opt()),
+            // and an event that ends at midnight doesn't take up that day
+            Some(end) => date_time(&end.value).map(|end| {
+                if end.time() == chrono::NaiveTime::MIN && end.date() > start {
+                    end.date().pred_opt().unwrap_or(start)
+                } else {
+                    end.date()
+                }
+            }),
+            None => Some(start),
+        };
+        let Some(end) = end.filter(|end| *end >= start) else {
+            warnings.push(Warning::new(
+                event,
+                "the end isn't a date after the start, skipped",
+            ));
+            continue;
+        };
+        if event.property("RRULE").is_some() || event.property("RDATE").is_some() {
+            warnings.push(Warning::new(
+                event,
+                "repeating events aren't supported, only the first time is used",
+            ));
+        }
+        holidays.push(Exception {
+            name: event
+                .property("SUMMARY")
+                .map(|summary| unescape(&summary.value)),
+            start,
+            end,
+        });
+    }
+    Ok((holidays, warnings))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::AlarmEdit;
+
+    const HOLIDAYS: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+PRODID:-//example//holidays//EN\r
+BEGIN:VEVENT\r
+DTSTART;VALUE=DATE:20261225\r
+DTEND;VALUE=DATE:20261226\r
+SUMMARY:Christmas Day\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART;VALUE=DATE:20260803\r
+DTEND;VALUE=DATE:20260815\r
+SUMMARY:Summer\\, at the\r
+  lake\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART:20260101T000000Z\r
+RRULE:FREQ=YEARLY\r
+SUMMARY:New Year\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+SUMMARY:Someday\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    fn day(month: u32, day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2026, month, day).unwrap()
+    }
+
+    #[test]
+    fn reads_holidays() {
+        let (holidays, warnings) = holidays(HOLIDAYS).unwrap();
+        assert_eq!(
+            holidays,
+            [
+                Exception {
+                    name: Some("Christmas Day".to_string()),
+                    ..Exception::day(day(12, 25))
+                },
+                Exception {
+                    name: Some("Summer, at the lake".to_string()),
+                    start: day(8, 3),
+                    end: day(8, 14),
+                },
+                Exception {
+                    name: Some("New Year".to_string()),
+                    ..Exception::day(day(1, 1))
+                },
+            ]
+        );
+        let warnings: Vec<_> = warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            warnings,
+            [
+                "New Year: repeating events aren't supported, only the first time is used",
+                "Someday: no start date, skipped",
+            ]
+        );
+    }
+
+    fn sounds() -> config::Sounds {
+        let sound = |name: &str| config::Sound {
+            name: name.to_string(),
+            ..config::Sound::default()
+        };
+        config::Sounds {
+            sounds: ["Bell", "Beep"]
+                .into_iter()
+                .map(|name| (name.to_string(), sound(name)))
+                .collect(),
+            default_sound: "Bell".to_string(),
+        }
+    }
+
+    fn at(hour: u32, min: u32) -> chrono::NaiveTime {
+        chrono::NaiveTime::from_hms_opt(hour, min, 0).unwrap()
+    }
+
+    fn saved_alarms() -> [config::Alarm; 2] {
+        [
+            config::Alarm {
+                name: Some("wake up, now; really".to_string()),
+                time: at(7, 30),
+                volume: 80.5,
+                sound: "Beep".to_string(),
+                playlist: vec!["Bell".to_string(), "Beep".to_string()],
+                sound_mode: SoundMode::Shuffle,
+                enabled: false,
+                start: Some(day(11, 2)),
+                end: Some(day(11, 30)),
+                date: None,
+                skip: Some(day(11, 3)),
+                exceptions: vec![Exception {
+                    name: Some("trip".to_string()),
+                    start: day(11, 10),
+                    end: day(11, 12),
+                }],
+                cron: None,
+                id: 1,
+            },
+            config::Alarm {
+                name: None,
+                time: at(6, 0),
+                volume: 100.,
+                sound: "Bell".to_string(),
+                playlist: Vec::new(),
+                sound_mode: SoundMode::Single,
+                enabled: true,
+                start: None,
+                end: None,
+                date: Some(day(12, 24)),
+                skip: None,
+                exceptions: Vec::new(),
+                cron: Some("0 6 * * 1-5".to_string()),
+                id: 2,
+            },
+        ]
+    }
+
+    fn now() -> DateTime<Local> {
+        Local.with_ymd_and_hms(2026, 11, 1, 12, 0, 0).unwrap()
+    }
+
+    #[test]
+    fn alarms_round_trip() {
+        let alarms = saved_alarms();
+        let text = export(&alarms, now());
+        assert!(text.lines().all(|line| line.len() <= LINE_LENGTH));
+        let (imported, warnings) = super::alarms(&text, &sounds()).unwrap();
+        assert_eq!(warnings, []);
+        // the skipped day comes back as an exception
+        let [first, second] = alarms;
+        let mut exceptions = first.exceptions.clone();
+        exceptions.push(Exception::day(day(11, 3)));
+        assert_eq!(
+            imported,
+            [
+                (
+                    Some(1),
+                    AlarmSpec {
+                        exceptions,
+                        ..AlarmSpec::from(first)
+                    }
+                ),
+                (Some(2), AlarmSpec::from(second)),
+            ]
+        );
+    }
+
+    #[test]
+    fn long_exceptions_are_only_left_out_for_a_year() {
+        let [mut alarm, _] = saved_alarms();
+        alarm.end = None;
+        alarm.skip = None;
+        alarm.exceptions = vec![Exception {
+            name: Some("sabbatical".to_string()),
+            start: day(1, 1),
+            end: NaiveDate::from_ymd_opt(2036, 12, 31).unwrap(),
+        }];
+        let text = export([&alarm], now());
+        let exdates = super::unfold(&text)
+            .iter()
+            .filter_map(|line| line.strip_prefix("EXDATE:"))
+            .map(|days| days.split(',').count())
+            .sum::<usize>();
+        assert_eq!(exdates, 366);
+        // but all of it is imported again
+        let (imported, _) = super::alarms(&text, &sounds()).unwrap();
+        assert_eq!(imported[0].1.exceptions, alarm.exceptions);
+    }
+
+    #[test]
+    fn importing_again_updates_the_alarms() {
+        let mut alarms: HashMap<_, _> = saved_alarms()
+            .into_iter()
+            .map(|alarm| (alarm.id, alarm))
+            .collect();
+        let text = export(&saved_alarms(), now());
+        let import = |alarms: &HashMap<u64, config::Alarm>| {
+            let (imported, _) = super::alarms(&text, &sounds()).unwrap();
+            changes(imported, alarms, || 7)
+        };
+        // nothing changed since the export
+        assert_eq!(import(&alarms), []);
+        alarms.get_mut(&1).unwrap().volume = 50.;
+        let removed = alarms.remove(&2).unwrap();
+        assert_eq!(
+            import(&alarms),
+            [
+                Change::Set(1, AlarmEdit::Volume(80.5)),
+                Change::Created(AlarmSpec::from(removed).with_id(7)),
+            ]
+        );
+    }
+
+    const CALENDAR: &str = "BEGIN:VCALENDAR\r
+VERSION:2.0\r
+BEGIN:VEVENT\r
+DTSTART;TZID=Europe/Berlin:20261102T090000\r
+DURATION:PT30M\r
+RRULE:FREQ=WEEKLY;BYDAY=MO,TU,WE,TH,FR,SA,SU;COUNT=5\r
+SUMMARY:Standup\r
+BEGIN:VALARM\r
+ACTION:DISPLAY\r
+TRIGGER:-PT15M\r
+END:VALARM\r
+BEGIN:VALARM\r
+ACTION:AUDIO\r
+TRIGGER;RELATED=END:PT0S\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART:20261102T180000\r
+RRULE:FREQ=WEEKLY;BYDAY=MO\r
+SUMMARY:Gym\r
+BEGIN:VALARM\r
+TRIGGER:-PT1H\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART;VALUE=DATE:20261105\r
+SUMMARY:Dentist\r
+X-ROOSTY-SOUND:Gong\r
+BEGIN:VALARM\r
+TRIGGER:-PT15H\r
+END:VALARM\r
+END:VEVENT\r
+BEGIN:VEVENT\r
+DTSTART:20261106T100000\r
+SUMMARY:Lunch\r
+END:VEVENT\r
+END:VCALENDAR\r
+";
+
+    #[test]
+    fn reminders_become_alarms() {
+        let (alarms, warnings) = super::alarms(CALENDAR, &sounds()).unwrap();
+        let alarms: Vec<_> = alarms.into_iter().map(|(_, spec)| spec).collect();
+        let standup = |time| AlarmSpec {
+            name: Some("Standup".to_string()),
+            time,
+            volume: 100.,
+            sound: "Bell".to_string(),
+            playlist: Vec::new(),
+            sound_mode: SoundMode::Single,
+            enabled: true,
+            start: Some(day(11, 2)),
+            end: Some(day(11, 6)),
+            date: None,
+            exceptions: Vec::new(),
+            cron: None,
+        };
+        assert_eq!(
+            alarms,
+            [
+                standup(at(8, 45)),
+                standup(at(9, 30)),
+                AlarmSpec {
+                    name: Some("Dentist".to_string()),
+                    start: None,
+                    end: None,
+                    date: Some(day(11, 4)),
+                    ..standup(at(9, 0))
+                },
+            ]
+        );
+        let warnings: Vec<_> = warnings.iter().map(ToString::to_string).collect();
+        assert_eq!(
+            warnings,
+            [
+                "Standup: the time zone Europe/Berlin isn't supported, the times are taken as local times",
+                "Gym: repeats by FREQ=WEEKLY;BYDAY=MO, which alarms can't, skipped",
+                "Dentist: there is no sound Gong, Bell is used instead",
+                "Lunch: has no reminders, skipped",
+            ]
+        );
+    }
+
+    #[test]
+    fn reads_durations() {
+        assert_eq!(duration("-PT15M"), Some(TimeDelta::minutes(-15)));
+        assert_eq!(
+            duration("P1DT2H3S"),
+            Some(TimeDelta::days(1) + TimeDelta::hours(2) + TimeDelta::seconds(3))
+        );
+        assert_eq!(duration("+P2W"), Some(TimeDelta::weeks(2)));
+        for bad in ["", "P", "PT", "15M", "P1H", "PT1D", "PT1"] {
+            assert_eq!(duration(bad), None, "{bad}");
+        }
+    }
+
+    #[test]
+    fn broken_calendars_are_errors() {
+        assert_eq!(parse("hello"), Err(ParseError::BadLine(1)));
+        assert_eq!(
+            parse("BEGIN:VCALENDAR\nBEGIN:VEVENT\nEND:VCALENDAR\n"),
+            Err(ParseError::Unexpected("VCALENDAR".to_string()))
+        );
+        assert_eq!(
+            parse("BEGIN:VCALENDAR\n"),
+            Err(ParseError::Unclosed("VCALENDAR".to_string()))
+        );
+        assert_eq!(
+            parse("BEGIN:VTODO\nEND:VTODO\n"),
+            Err(ParseError::NotACalendar)
+        );
+        assert_eq!(
+            parse("BEGIN:VCALENDAR\nBEGIN:VEVENT\nBEGIN:VALARM\nBEGIN:X\n"),
+            Err(ParseError::TooDeep("X".to_string()))
+        );
+        let deep = "BEGIN:X\n".repeat(100_000);
+        assert_eq!(parse(&deep), Err(ParseError::TooDeep("X".to_string())));
+    }
+}
@@ -0,0 +1,200 @@
+//! making sure only one daemon runs per socket, and telling a running daemon apart from a socket
+//! left behind by one that crashed
+use std::{
+    error, fmt,
+    fs::{self, File, OpenOptions, TryLockError},
+    io::{self, BufReader, Read, Seek, SeekFrom, Write},
+    time::Duration,
+};
+
+use interprocess::local_socket::{Listener, prelude::*};
+
+use crate::{ClientMessage, ServerMessage, encoding::Encoding, socket::Address};
+
+/// how long a daemon has to answer a ping before its considered dead
+pub const PROBE_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// The lock file of a running daemon, its unlocked when dropped.
+///
+/// The lock is held by the OS, so it goes away when the daemon crashes, unlike the socket file.
+/// The file itself is left behind, removing it would let a new daemon lock a fresh file while an
+/// other one still locks the removed one.
+#[derive(Debug)]
+pub struct Lock {
+    file: File,
+}
+
+impl Lock {
+    /// Takes the lock for `address` and writes our PID into it.
+    ///
+    /// # Errors
+    /// [`Error::Running`] if another daemon already has the lock, or if the lock file couldn't
+    /// be written.
+    pub fn acquire(address: &Address) -> Result<Self, Error> {
+        let path = address.lock_path();
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        match file.try_lock() {
+            Ok(()) => {}
+            Err(TryLockError::WouldBlock) => {
+                return Err(Error::Running(
+                    read_pid(&mut file).or_else(|| probe(address)),
+                ));
+            }
+            Err(TryLockError::Error(e)) => return Err(e.into()),
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(Self { file })
+    }
+
+    /// Whether some daemon holds the lock for `address`, and its PID if it wrote one.
+    #[must_use]
+    pub fn holder(address: &Address) -> Option<Option<u32>> {
+        let mut file = File::open(address.lock_path()).ok()?;
+        match file.try_lock_shared() {
+            Err(TryLockError::WouldBlock) => Some(read_pid(&mut file)),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for Lock {
+    fn drop(&mut self) {
+        // the PID is cleared while still locked, so it never wipes out the next daemon's one
+        let _ = self.file.set_len(0);
+        let _ = self.file.unlock();
+    }
+}
+
+fn read_pid(file: &mut File) -> Option<u32> {
+    let mut pid = String::new();
+    file.read_to_string(&mut pid).ok()?;
+    pid.trim().parse().ok()
+}
+
+/// Pings the daemon at `address`, returning its PID if it answered.
+#[must_use]
+pub fn probe(address: &Address) -> Option<u32> {
+    let conn = address.connect().ok()?;
+    conn.set_recv_timeout(Some(PROBE_TIMEOUT)).ok()?;
+    let (recv, mut send) = conn.split();
+    Encoding::Bitcode.handshake(&mut send).ok()?;
+    Encoding::Bitcode
+        .send(&mut send, &ClientMessage::Ping)
+        .ok()?;
+    let mut recv = BufReader::new(recv);
+    let mut buffer = Vec::new();
+    // there might be alerts for other clients in the way
+    loop {
+        match Encoding::Bitcode.recieve(&mut recv, &mut buffer) {
+            Ok(ServerMessage::Pong(pid)) => return Some(pid),
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::InvalidData => {}
+            Err(_) => return None,
+        }
+    }
+}
+
+/// Starts listening on `address`, cleaning up the socket file of a daemon that crashed.
+///
+/// This should only be called while holding the [`Lock`], otherwise a daemon that is just
+/// starting up could have its socket removed.
+///
+/// # Errors
+/// [`Error::Running`] if something answers on the socket, or if it couldn't be created.
+pub fn listen(address: &Address) -> Result<Listener, Error> {
+    match address.listen() {
+        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
+            if let Some(pid) = probe(address) {
+                return Err(Error::Running(Some(pid)));
+            }
+            let Address::Path(path) = address else {
+                return Err(e.into());
+            };
+            log::info!("removing stale socket {}", path.display());
+            fs::remove_file(path)?;
+            Ok(address.listen()?)
+        }
+        listener => Ok(listener?),
+    }
+}
+
+/// What state the daemon at an address is in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    /// answering on the socket
+    Running(u32),
+    /// holds the lock but isn't answering
+    Unresponsive(Option<u32>),
+    /// not running, but its socket file is still there
+    Stale,
+    NotRunning,
+}
+
+impl Status {
+    #[must_use]
+    pub fn of(address: &Address) -> Self {
+        if let Some(pid) = probe(address) {
+            Self::Running(pid)
+        } else if let Some(pid) = Lock::holder(address) {
+            Self::Unresponsive(pid)
+        } else if matches!(address, Address::Path(path) if path.exists()) {
+            Self::Stale
+        } else {
+            Self::NotRunning
+        }
+    }
+}
+
+impl fmt::Display for Status {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Running(pid) => write!(f, "running (pid {pid})"),
+            Self::Unresponsive(Some(pid)) => write!(f, "not responding (pid {pid})"),
+            Self::Unresponsive(None) => write!(f, "not responding"),
+            Self::Stale => write!(f, "not running (stale socket left behind)"),
+            Self::NotRunning => write!(f, "not running"),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    /// another daemon is using the address (with its PID if it could be found)
+    Running(Option<u32>),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Running(Some(pid)) => write!(f, "roosty_clockd is already running (pid {pid})"),
+            Self::Running(None) => write!(f, "roosty_clockd is already running"),
+            Self::Io(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Self::Running(_) => None,
+            Self::Io(e) => Some(e),
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Self::Io(e)
+    }
+}
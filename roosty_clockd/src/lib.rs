@@ -1,42 +1,411 @@
-use chrono::NaiveTime;
+use chrono::{DateTime, Local, NaiveDate, NaiveTime};
 use interprocess::local_socket::SendHalf;
-use serde::{Deserialize, Serialize};
+use serde::{Deserialize, Deserializer, Serialize, de};
 use std::{
+    cell::Cell,
     collections::{HashMap, HashSet},
+    error, fmt,
     io::{self, Read, Write},
+    iter,
 };
 
+pub mod client;
 pub mod config;
-#[derive(Debug, Serialize, Deserialize, Clone)]
+pub mod cron;
+pub mod encoding;
+pub mod filter;
+pub mod history;
+pub mod http;
+pub mod ics;
+pub mod instance;
+pub mod logging;
+pub mod remote;
+pub mod socket;
+pub mod sound;
+pub mod systemd;
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 
 pub enum ClientMessage {
     Init,
+    /// edit an alarm, if the alarm wouldn't be valid after (see [`AlarmSpec::validate`]) only the
+    /// sender is answered, with [`ServerMessage::BatchRejected`]
     SetAlarm(u64, AlarmEdit),
+    /// add an alarm with an id from [`ClientMessage::GetNewUID`], checked like
+    /// [`ClientMessage::CreateAlarm`] (which should be used instead), only the sender is answered
+    /// if it's refused, with [`ServerMessage::AlarmNotCreated`]
     AddAlarm(Alarm),
+    /// add an alarm, the daemon picks its id and checks it, answered with
+    /// [`ServerMessage::AlarmCreated`] or [`ServerMessage::AlarmNotCreated`]
+    CreateAlarm(AlarmSpec),
     RemoveAlarm(u64),
     AddedSounds(Vec<config::Sound>),
-    RemoveSound(String),
+    RemoveSound(String, SoundRemoval),
     StopAlarm(u64),
     GetNewUID,
+    /// check that the daemon is alive, it answers with [`ServerMessage::Pong`]
+    Ping,
+    /// stop the daemon, ignored from remote clients
+    Shutdown,
+    /// what happened to alarms (all of them if `alarm` is `None`) between `since` and `until`,
+    /// answered with [`ServerMessage::History`]
+    QueryHistory {
+        since: Option<DateTime<Local>>,
+        until: Option<DateTime<Local>>,
+        alarm: Option<u64>,
+    },
+    /// a message with an id, the answer to it comes back as a [`ServerMessage::Response`] with the
+    /// same id (changes that every client is told about, like [`ServerMessage::AlaramAdded`], are
+    /// still sent as is), requests can't be nested
+    Request(
+        u64,
+        #[serde(deserialize_with = "not_nested")] Box<ClientMessage>,
+    ),
+    /// changes to apply all at once (or not at all if any of them can't be), answered with
+    /// [`ServerMessage::BatchApplied`] or [`ServerMessage::BatchRejected`]
+    Batch(Vec<Op>),
+    /// only send this client the events that get through the filter, answered with
+    /// [`ServerMessage::Subscribed`] (requests that wait for an event, like
+    /// [`ClientMessage::SetAlarm`], need its topic)
+    Subscribe(filter::Filter),
+    /// skip the next time the alarm would ring (only that one), answered like a
+    /// [`ClientMessage::Batch`] with the [`AlarmEdit::Skip`] (undo it by setting that to `None`)
+    SkipNext(u64),
+    /// answered with [`ServerMessage::Exceptions`]
+    GetExceptions,
+    /// replace the days no alarm rings on ([`config::Config::exceptions`]), every client is told
+    /// with [`ServerMessage::Exceptions`]
+    SetExceptions(Vec<config::Exception>),
+    /// every alarm as an iCalendar file (see [`ics`]), answered with [`ServerMessage::Calendar`]
+    ExportCalendar,
+    /// create an alarm for each reminder in an iCalendar file, answered with
+    /// [`ServerMessage::CalendarImported`] or [`ServerMessage::CalendarNotImported`]
+    ImportCalendar(String),
 }
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct Alarm {
     pub name: Option<String>,
     pub time: NaiveTime,
     pub volume: f32,
     pub sound: String,
+    pub playlist: Vec<String>,
+    pub sound_mode: config::SoundMode,
     pub id: u64,
 }
-#[derive(Debug, Serialize, Deserialize, Clone)]
+impl From<config::Alarm> for Alarm {
+    fn from(alarm: config::Alarm) -> Self {
+        Self {
+            name: alarm.name,
+            time: alarm.time,
+            volume: alarm.volume,
+            sound: alarm.sound,
+            playlist: alarm.playlist,
+            sound_mode: alarm.sound_mode,
+            id: alarm.id,
+        }
+    }
+}
+
+/// an enabled alarm that rings every day, with nothing else set
+impl From<Alarm> for config::Alarm {
+    fn from(alarm: Alarm) -> Self {
+        let id = alarm.id;
+        AlarmSpec::from(alarm).with_id(id)
+    }
+}
+
+/// An alarm to create, everything but the id (which the daemon picks).
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AlarmSpec {
+    pub name: Option<String>,
+    pub time: NaiveTime,
+    pub volume: f32,
+    pub sound: String,
+    pub playlist: Vec<String>,
+    pub sound_mode: config::SoundMode,
+    pub enabled: bool,
+    /// see [`config::Alarm::start`]
+    pub start: Option<NaiveDate>,
+    /// see [`config::Alarm::end`]
+    pub end: Option<NaiveDate>,
+    /// see [`config::Alarm::date`]
+    pub date: Option<NaiveDate>,
+    /// see [`config::Alarm::exceptions`]
+    pub exceptions: Vec<config::Exception>,
+    /// see [`config::Alarm::cron`]
+    pub cron: Option<String>,
+}
+
+impl AlarmSpec {
+    /// Checks that the alarm only uses `sounds`, isn't too loud, doesn't end before it starts,
+    /// that its date is between its start and end and that its cron expression is valid.
+    ///
+    /// # Errors
+    /// The first problem with the alarm.
+    pub fn validate(&self, sounds: &HashMap<String, config::Sound>) -> Result<(), AlarmError> {
+        if let Some(sound) = iter::once(&self.sound)
+            .chain(&self.playlist)
+            .find(|sound| !sounds.contains_key(*sound))
+        {
+            return Err(AlarmError::UnknownSound(sound.clone()));
+        }
+        if !(0.0..=config::Alarm::MAX_VOLUME).contains(&self.volume) {
+            return Err(AlarmError::VolumeOutOfRange(self.volume));
+        }
+        if let (Some(start), Some(end)) = (self.start, self.end)
+            && end < start
+        {
+            return Err(AlarmError::EndsBeforeStart(start, end));
+        }
+        if let Some(date) = self.date
+            && (self.start.is_some_and(|start| date < start)
+                || self.end.is_some_and(|end| end < date))
+        {
+            return Err(AlarmError::DateOutOfRange(date));
+        }
+        if let Some(expression) = &self.cron
+            && let Err(e) = expression.parse::<cron::Schedule>()
+        {
+            return Err(AlarmError::InvalidCron(expression.clone(), e));
+        }
+        Ok(())
+    }
+
+    #[must_use]
+    pub fn with_id(self, id: u64) -> config::Alarm {
+        config::Alarm {
+            name: self.name,
+            time: self.time,
+            volume: self.volume,
+            sound: self.sound,
+            playlist: self.playlist,
+            sound_mode: self.sound_mode,
+            enabled: self.enabled,
+            start: self.start,
+            end: self.end,
+            date: self.date,
+            skip: None,
+            exceptions: self.exceptions,
+            cron: self.cron,
+            id,
+        }
+    }
+}
+
+impl From<Alarm> for AlarmSpec {
+    fn from(alarm: Alarm) -> Self {
+        Self {
+            name: alarm.name,
+            time: alarm.time,
+            volume: alarm.volume,
+            sound: alarm.sound,
+            playlist: alarm.playlist,
+            sound_mode: alarm.sound_mode,
+            enabled: true,
+            start: None,
+            end: None,
+            date: None,
+            exceptions: Vec::new(),
+            cron: None,
+        }
+    }
+}
+
+impl From<config::Alarm> for AlarmSpec {
+    fn from(alarm: config::Alarm) -> Self {
+        Self {
+            name: alarm.name,
+            time: alarm.time,
+            volume: alarm.volume,
+            sound: alarm.sound,
+            playlist: alarm.playlist,
+            sound_mode: alarm.sound_mode,
+            enabled: alarm.enabled,
+            start: alarm.start,
+            end: alarm.end,
+            date: alarm.date,
+            exceptions: alarm.exceptions,
+            cron: alarm.cron,
+        }
+    }
+}
+
+/// why an alarm couldn't be created
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum AlarmError {
+    UnknownSound(String),
+    /// the volume has to be between 0 and [`config::Alarm::MAX_VOLUME`]
+    VolumeOutOfRange(f32),
+    NoSuchAlarm(u64),
+    /// the end date is before the start date
+    EndsBeforeStart(NaiveDate, NaiveDate),
+    /// the alarm only rings on a day before its start or after its end
+    DateOutOfRange(NaiveDate),
+    /// the alarm has no next time to skip
+    WontRing(u64),
+    /// a cron expression that couldn't be parsed, and why
+    InvalidCron(String, cron::Error),
+}
+
+impl fmt::Display for AlarmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnknownSound(sound) => write!(f, "there is no sound called {sound}"),
+            Self::VolumeOutOfRange(volume) => write!(
+                f,
+                "the volume has to be between 0 and {}, not {volume}",
+                config::Alarm::MAX_VOLUME
+            ),
+            Self::NoSuchAlarm(id) => write!(f, "there is no alarm {id}"),
+            Self::EndsBeforeStart(start, end) => {
+                write!(f, "the alarm ends ({end}) before it starts ({start})")
+            }
+            Self::DateOutOfRange(date) => {
+                write!(
+                    f,
+                    "the alarm only rings on {date}, outside its start and end"
+                )
+            }
+            Self::WontRing(id) => write!(f, "alarm {id} won't ring again"),
+            Self::InvalidCron(expression, e) => {
+                write!(f, "the cron expression \"{expression}\" is invalid: {e}")
+            }
+        }
+    }
+}
+
+impl error::Error for AlarmError {}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum AlarmEdit {
     Time(NaiveTime),
     Name(Option<String>),
     Sound(String),
+    Playlist(Vec<String>),
+    SoundMode(config::SoundMode),
     Volume(f32),
     Enable(bool),
+    Start(Option<NaiveDate>),
+    End(Option<NaiveDate>),
+    /// makes the alarm ring only on that day, clearing its start and end
+    Date(Option<NaiveDate>),
+    /// see [`ClientMessage::SkipNext`]
+    Skip(Option<NaiveDate>),
+    Exceptions(Vec<config::Exception>),
+    /// see [`config::Alarm::cron`]
+    Cron(Option<String>),
+}
+
+/// one change in a [`ClientMessage::Batch`]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Op {
+    Create(AlarmSpec),
+    Set(u64, AlarmEdit),
+    Remove(u64),
+}
+
+/// what a [`ClientMessage::Batch`] did, with the ids the daemon picked for created alarms
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Change {
+    Created(config::Alarm),
+    Set(u64, AlarmEdit),
+    Removed(u64),
+}
+
+impl Change {
+    /// the alarm that was changed
+    #[must_use]
+    pub const fn alarm(&self) -> u64 {
+        match self {
+            Self::Created(alarm) => alarm.id,
+            Self::Set(id, _) | Self::Removed(id) => *id,
+        }
+    }
+}
+
+/// what a batch changed, or the index of the op that couldn't be applied and why
+pub type BatchResult = Result<Vec<Change>, (usize, AlarmError)>;
+
+/// the alarms created from a calendar and what was left out of it, or why it couldn't be read
+pub type ImportResult = Result<(Vec<Change>, Vec<ics::Warning>), ics::ParseError>;
+
+impl Op {
+    /// Checks that `ops` can be applied to `alarms` one after another, and what that would
+    /// change. Created alarms get their id from `new_id`.
+    ///
+    /// Alarms are only validated once all ops are applied, so edits that are only valid together
+    /// (like moving both the start and end of an alarm) can be sent one after another.
+    ///
+    /// # Errors
+    /// The index of the first op on a missing alarm, or of the last op on an alarm that ends up
+    /// invalid, and why.
+    pub fn plan(
+        ops: Vec<Self>,
+        alarms: &HashMap<u64, config::Alarm>,
+        sounds: &HashMap<String, config::Sound>,
+        mut new_id: impl FnMut() -> u64,
+    ) -> BatchResult {
+        // later ops see what earlier ones did
+        let mut alarms = alarms.clone();
+        let mut changes = Vec::with_capacity(ops.len());
+        // the last op on each alarm that was created or edited
+        let mut touched = HashMap::new();
+        for (i, op) in ops.into_iter().enumerate() {
+            let change = match op {
+                Self::Create(spec) => {
+                    let alarm = spec.with_id(new_id());
+                    alarms.insert(alarm.id, alarm.clone());
+                    touched.insert(alarm.id, i);
+                    Change::Created(alarm)
+                }
+                Self::Set(id, edit) => {
+                    let alarm = alarms
+                        .get_mut(&id)
+                        .ok_or((i, AlarmError::NoSuchAlarm(id)))?;
+                    alarm.apply(edit.clone());
+                    touched.insert(id, i);
+                    Change::Set(id, edit)
+                }
+                Self::Remove(id) => {
+                    alarms.remove(&id).ok_or((i, AlarmError::NoSuchAlarm(id)))?;
+                    touched.remove(&id);
+                    Change::Removed(id)
+                }
+            };
+            changes.push(change);
+        }
+        let mut touched: Vec<_> = touched.into_iter().collect();
+        touched.sort_unstable_by_key(|(_, i)| *i);
+        for (id, i) in touched {
+            AlarmSpec::from(alarms[&id].clone())
+                .validate(sounds)
+                .map_err(|e| (i, e))?;
+        }
+        Ok(changes)
+    }
+}
+
+/// what to do with alarms that still use a sound that is being removed
+#[derive(Debug, Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SoundRemoval {
+    /// dont remove the sound if any alarm uses it
+    #[default]
+    Refuse,
+    /// switch the alarms over to the default sound (and take it out of their playlists)
+    Reassign,
+    /// disable the alarms and switch them over to the default sound
+    Disable,
 }
 
-#[derive(Debug, Serialize, Deserialize, Clone)]
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum SoundRemovalError {
+    /// the alarms that still use the sound
+    InUse(Vec<u64>),
+    /// the default sound cannot be removed
+    IsDefault,
+    NotFound,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub enum ServerMessage {
     Init {
         alarms: HashMap<u64, config::Alarm>,
@@ -45,12 +414,78 @@ pub enum ServerMessage {
     },
     AlarmSet(u64, AlarmEdit),
     AlaramAdded(Alarm),
+    /// an alarm was created with [`ClientMessage::CreateAlarm`], every client is told about it
+    AlarmCreated(config::Alarm),
+    /// the answer to a [`ClientMessage::CreateAlarm`] that failed
+    AlarmNotCreated(AlarmError),
     AlarmRemoved(u64),
     SoundsAdded(Vec<config::Sound>),
-    SoundRemoved(String),
+    /// the removed sound and the alarms that were reassigned or disabled because of it
+    SoundRemoved(String, Vec<u64>),
+    SoundNotRemoved(String, SoundRemovalError),
     AlarmRinging(u64),
     AlarmStopped(u64),
     UID(u64),
+    /// the PID of the daemon
+    Pong(u32),
+    /// the daemon is stopping, this is the last message before the connection closes
+    ShuttingDown,
+    /// oldest first
+    History(Vec<history::Entry>),
+    /// the answer to the [`ClientMessage::Request`] with the same id
+    Response(
+        u64,
+        #[serde(deserialize_with = "not_nested")] Box<ServerMessage>,
+    ),
+    /// a [`ClientMessage::Batch`] was applied, every client is told about it
+    BatchApplied(Vec<Change>),
+    /// the answer to a [`ClientMessage::Batch`] that wasn't applied, with the index of the op
+    /// that couldn't be
+    BatchRejected(usize, AlarmError),
+    /// the filter a client is subscribed with now
+    Subscribed(filter::Filter),
+    /// the client fell behind and missed some events, it should get the current state again with
+    /// [`ClientMessage::Init`]
+    ResyncRequired,
+    /// the days no alarm rings on
+    Exceptions(Vec<config::Exception>),
+    /// the alarms as an iCalendar file
+    Calendar(String),
+    /// the alarms that were created from a calendar, and what about its entries wasn't
+    CalendarImported(Vec<Change>, Vec<ics::Warning>),
+    CalendarNotImported(ics::ParseError),
+}
+
+impl ServerMessage {
+    /// Wraps the message in a [`ServerMessage::Response`] if its the answer to a request.
+    #[must_use]
+    pub fn respond_to(self, request: Option<u64>) -> Self {
+        match request {
+            Some(id) => Self::Response(id, Box::new(self)),
+            None => self,
+        }
+    }
+}
+
+thread_local! {
+    /// if the message in a request or response is being decoded on this thread
+    static NESTED: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Decodes the message in a [`ClientMessage::Request`] or [`ServerMessage::Response`], unless it's
+/// inside another one. Every level of nesting takes stack, so a small message nested a few
+/// thousand times would overflow it and abort the daemon.
+fn not_nested<'de, D, T>(deserializer: D) -> Result<Box<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    if NESTED.replace(true) {
+        return Err(de::Error::custom("requests and responses can't be nested"));
+    }
+    let message = Box::<T>::deserialize(deserializer);
+    NESTED.set(false);
+    message
 }
 
 pub fn write(w: &mut SendHalf, message: &[u8]) -> io::Result<usize> {
@@ -58,10 +493,204 @@ pub fn write(w: &mut SendHalf, message: &[u8]) -> io::Result<usize> {
     len.extend_from_slice(message);
     w.write(&len)
 }
+
+/// the biggest message that is read, so a bad length can't make the reader allocate everything
+pub const MAX_MESSAGE_SIZE: usize = 16 * 1024 * 1024;
+
+/// the stack of the daemons client threads, where messages are decoded (the fuzz targets and
+/// tests decode on threads this size too, so they'd catch a message that overflows it)
+pub const CLIENT_STACK_SIZE: usize = 2 * 1024 * 1024;
+
+/// Reads one length prefixed message into `buf`.
+///
+/// If reading fails part way through (like with [`io::ErrorKind::WouldBlock`] on a nonblocking
+/// stream, where a message can arrive in pieces) what was read is kept in `buf`, and calling this
+/// again with the same `buf` carries on from there. So `buf` has to be cleared once the message in
+/// it is used.
+///
+/// # Errors
+/// If the message couldn't be read, or it claims to be bigger than [`MAX_MESSAGE_SIZE`]
+/// ([`io::ErrorKind::InvalidData`], nothing after the length is read then).
 pub fn read<R: Read + ?Sized>(r: &mut R, buf: &mut Vec<u8>) -> io::Result<()> {
-    let mut header = 0_usize.to_ne_bytes();
-    r.read_exact(&mut header)?;
+    const HEADER: usize = size_of::<usize>();
+    if buf.len() < HEADER {
+        (&mut *r)
+            .take((HEADER - buf.len()) as u64)
+            .read_to_end(buf)?;
+        if buf.len() < HEADER {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+    }
+    let mut header = [0; HEADER];
+    header.copy_from_slice(&buf[..HEADER]);
     let size = usize::from_ne_bytes(header);
-    buf.resize(size, 0);
-    r.read_exact(buf.as_mut_slice())
+    if size > MAX_MESSAGE_SIZE {
+        buf.clear();
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("message of {size} bytes is too big"),
+        ));
+    }
+    let wanted = HEADER + size;
+    (&mut *r)
+        .take((wanted - buf.len()) as u64)
+        .read_to_end(buf)?;
+    if buf.len() < wanted {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    buf.drain(..HEADER);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec(volume: f32) -> AlarmSpec {
+        AlarmSpec {
+            name: None,
+            time: NaiveTime::from_hms_opt(7, 0, 0).unwrap(),
+            volume,
+            sound: "Bell".to_string(),
+            playlist: Vec::new(),
+            sound_mode: config::SoundMode::Single,
+            enabled: true,
+            start: None,
+            end: None,
+            date: None,
+            exceptions: Vec::new(),
+            cron: None,
+        }
+    }
+
+    fn sounds() -> HashMap<String, config::Sound> {
+        HashMap::from([("Bell".to_string(), config::Sound::default())])
+    }
+
+    #[test]
+    fn old_alarms_convert_to_enabled_daily_alarms() {
+        let alarm = Alarm::from(spec(50.).with_id(4));
+        let converted = config::Alarm::from(alarm.clone());
+        assert_eq!(converted, spec(50.).with_id(4));
+        assert_eq!(Alarm::from(converted), alarm);
+    }
+
+    #[test]
+    fn batches_see_earlier_ops() {
+        let alarms = HashMap::from([(1, spec(100.).with_id(1))]);
+        let mut ids = 10..;
+        let changes = Op::plan(
+            vec![
+                Op::Create(spec(50.)),
+                Op::Set(10, AlarmEdit::Enable(false)),
+                Op::Remove(1),
+            ],
+            &alarms,
+            &sounds(),
+            || ids.next().unwrap(),
+        )
+        .unwrap();
+        assert_eq!(
+            changes,
+            [
+                Change::Created(spec(50.).with_id(10)),
+                Change::Set(10, AlarmEdit::Enable(false)),
+                Change::Removed(1),
+            ]
+        );
+    }
+
+    #[test]
+    fn batches_stop_at_the_first_bad_op() {
+        let alarms = HashMap::from([(1, spec(100.).with_id(1))]);
+        let plan = |ops| Op::plan(ops, &alarms, &sounds(), || 10);
+        assert_eq!(
+            plan(vec![Op::Remove(1), Op::Remove(1)]),
+            Err((1, AlarmError::NoSuchAlarm(1)))
+        );
+        assert_eq!(
+            plan(vec![
+                Op::Set(1, AlarmEdit::Volume(50.)),
+                Op::Set(1, AlarmEdit::Sound("Gong".to_string())),
+            ]),
+            Err((1, AlarmError::UnknownSound("Gong".to_string())))
+        );
+        assert_eq!(
+            plan(vec![Op::Create(spec(500.))]),
+            Err((0, AlarmError::VolumeOutOfRange(500.)))
+        );
+    }
+
+    #[test]
+    fn ranges_can_be_moved_later() {
+        let day = |m, d| NaiveDate::from_ymd_opt(2026, m, d);
+        let mut old = spec(100.).with_id(1);
+        old.start = day(1, 1);
+        old.end = day(1, 31);
+        let mut new = old.clone();
+        new.start = day(3, 1);
+        new.end = day(3, 31);
+        let alarms = HashMap::from([(1, old.clone())]);
+        let ops: Vec<_> = old.edits(&new).into_iter().map(|e| Op::Set(1, e)).collect();
+        // the start is moved first, past the old end
+        assert_eq!(ops[0], Op::Set(1, AlarmEdit::Start(day(3, 1))));
+        let changes = Op::plan(ops, &alarms, &sounds(), || 10).unwrap();
+        assert_eq!(changes.len(), 2);
+        // but only the final state has to be valid
+        let ops = vec![
+            Op::Set(1, AlarmEdit::Start(day(3, 1))),
+            Op::Set(1, AlarmEdit::Volume(50.)),
+        ];
+        assert_eq!(
+            Op::plan(ops, &alarms, &sounds(), || 10),
+            Err((
+                1,
+                AlarmError::EndsBeforeStart(day(3, 1).unwrap(), day(1, 31).unwrap())
+            ))
+        );
+    }
+
+    #[test]
+    fn dates_have_to_be_in_range() {
+        let day = |d| NaiveDate::from_ymd_opt(2026, 11, d);
+        let mut alarm = spec(100.);
+        alarm.start = day(1);
+        alarm.end = day(30);
+        alarm.date = day(3);
+        assert_eq!(alarm.validate(&sounds()), Ok(()));
+        alarm.start = day(4);
+        assert_eq!(
+            alarm.validate(&sounds()),
+            Err(AlarmError::DateOutOfRange(day(3).unwrap()))
+        );
+        alarm.start = None;
+        alarm.end = day(2);
+        assert_eq!(
+            alarm.validate(&sounds()),
+            Err(AlarmError::DateOutOfRange(day(3).unwrap()))
+        );
+    }
+
+    #[test]
+    fn switching_between_a_date_and_a_range_works_both_ways() {
+        let day = |d| NaiveDate::from_ymd_opt(2026, 11, d);
+        let mut ranged = spec(100.).with_id(1);
+        ranged.start = day(10);
+        ranged.end = day(20);
+        let mut once = spec(100.).with_id(1);
+        once.date = day(3);
+        for (old, new) in [(&ranged, &once), (&once, &ranged)] {
+            let alarms = HashMap::from([(1, old.clone())]);
+            let ops = old.edits(new).into_iter().map(|edit| Op::Set(1, edit));
+            let changes = Op::plan(ops.collect(), &alarms, &sounds(), || 10).unwrap();
+            let mut alarm = old.clone();
+            for change in changes {
+                let Change::Set(_, edit) = change else {
+                    panic!("expected an edit");
+                };
+                alarm.apply(edit);
+            }
+            assert_eq!(&alarm, new);
+        }
+    }
 }
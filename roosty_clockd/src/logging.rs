@@ -0,0 +1,57 @@
+//! where the daemon logs to, and how much
+use flexi_logger::{
+    Cleanup, Criterion, Duplicate, FileSpec, FlexiLoggerError, Logger, LoggerHandle, Naming,
+};
+
+use crate::config::{Config, Logging};
+
+/// environment variable to override the log level from the config
+pub const LOG_ENV: &str = "ROOSTY_CLOCKD_LOG";
+
+/// The log level (or filter, like `info,roosty_clockd::sound=debug`) to use.
+///
+/// `--verbose` takes precedence over [`LOG_ENV`], which takes precedence over the config.
+#[must_use]
+pub fn level(config: &Logging, verbose: u8) -> String {
+    match verbose {
+        0 => std::env::var(LOG_ENV).unwrap_or_else(|_| config.level.clone()),
+        1 => "debug".to_string(),
+        _ => "trace".to_string(),
+    }
+}
+
+/// Starts logging to a rotating file in [`Config::log_path`].
+///
+/// Warnings and errors also go to stderr, and everything else too if `foreground` is set.
+///
+/// The returned handle has to be kept around, the log is flushed when its dropped.
+///
+/// # Errors
+/// If the level is invalid or the log file couldn't be opened.
+pub fn init(
+    config: &Logging,
+    verbose: u8,
+    foreground: bool,
+) -> Result<LoggerHandle, FlexiLoggerError> {
+    Logger::try_with_str(level(config, verbose))?
+        .log_to_file(
+            FileSpec::default()
+                .directory(Config::log_path())
+                .basename("roosty_clockd")
+                .suppress_timestamp(),
+        )
+        .rotate(
+            Criterion::Size(config.max_file_size),
+            Naming::Numbers,
+            Cleanup::KeepLogFiles(config.keep_files),
+        )
+        .append()
+        .format_for_files(flexi_logger::detailed_format)
+        .format_for_stderr(flexi_logger::detailed_format)
+        .duplicate_to_stderr(if foreground {
+            Duplicate::All
+        } else {
+            Duplicate::Warn
+        })
+        .start()
+}
@@ -5,35 +5,283 @@
     missing_debug_implementations,
     clippy::missing_panics_doc
 )]
-use chrono::Duration;
-use interprocess::local_socket::{GenericNamespaced, ListenerOptions, Stream, prelude::*};
-use rodio::{Source, decoder};
+use chrono::{DateTime, Duration, Local};
+use clap::{Parser, Subcommand};
+use interprocess::local_socket::{Listener, Stream, prelude::*};
+use rodio::mixer::Mixer;
 use roosty_clockd::config::Config;
 use roosty_clockd::config::{self, get_uid};
-use roosty_clockd::read;
-use roosty_clockd::{Alarm, AlarmEdit};
-use roosty_clockd::{ClientMessage, ServerMessage};
+use roosty_clockd::encoding::Encoding;
+use roosty_clockd::filter::Filter;
+use roosty_clockd::history::{self, Journal};
+use roosty_clockd::instance::{self, Status};
+use roosty_clockd::{Alarm, AlarmEdit, AlarmError, AlarmSpec, Change, Op};
+use roosty_clockd::{ClientMessage, ServerMessage, SoundRemoval, SoundRemovalError};
+use roosty_clockd::{ics, remote, socket, sound, systemd};
 use serde::{Deserialize, Serialize};
 use std::collections::{HashMap, HashSet};
 use std::fs;
 use std::io::{self, BufReader, prelude::*};
-use std::sync::mpsc;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, mpsc};
 use std::thread;
+use std::time;
 
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub enum Alert {
     AlarmSet(u64, AlarmEdit),
     AlaramAdded(Alarm),
+    AlarmCreated(config::Alarm),
     AlarmRemoved(u64),
     SoundAdded(Vec<config::Sound>),
-    SoundRemoved(String),
+    SoundRemoved(String, Vec<u64>),
     AlarmRinging(u64),
     AlarmStopped(u64),
+    /// everything a [`ClientMessage::Batch`] changed
+    Batch(Vec<Change>),
+    /// the days no alarm rings on changed
+    ExceptionsSet(Vec<config::Exception>),
+    /// the config was reloaded from disk (and nothing is ringing anymore)
+    Reloaded {
+        alarms: HashMap<u64, config::Alarm>,
+        sounds: HashMap<String, config::Sound>,
+        exceptions: Vec<config::Exception>,
+    },
+    /// every thread should finish up and exit
+    ShuttingDown,
+}
+
+/// Where alerts go: the config and audio threads get every one of them, clients (through the
+/// dispatcher) can fall behind and have to resync.
+#[derive(Clone)]
+struct Alerts {
+    clients: async_broadcast::Sender<Alert>,
+    threads: [mpsc::Sender<Alert>; 2],
+}
+
+impl Alerts {
+    fn send(&self, alert: Alert) {
+        for thread in &self.threads {
+            // a thread that has finished doesn't need to know
+            let _ = thread.send(alert.clone());
+        }
+        if let Err(e) = self.clients.broadcast_blocking(alert) {
+            log::error!("couldn't tell the clients: {e}");
+        }
+    }
+}
+
+impl Alert {
+    /// what clients are told
+    fn into_message(self) -> ServerMessage {
+        match self {
+            Self::AlarmSet(id, alarm_edit) => ServerMessage::AlarmSet(id, alarm_edit),
+            Self::AlaramAdded(alarm) => ServerMessage::AlaramAdded(alarm),
+            Self::AlarmCreated(alarm) => ServerMessage::AlarmCreated(alarm),
+            Self::Batch(changes) => ServerMessage::BatchApplied(changes),
+            Self::AlarmRemoved(id) => ServerMessage::AlarmRemoved(id),
+            Self::SoundAdded(sound) => ServerMessage::SoundsAdded(sound),
+            Self::SoundRemoved(sound, affected) => ServerMessage::SoundRemoved(sound, affected),
+            Self::AlarmRinging(id) => ServerMessage::AlarmRinging(id),
+            Self::AlarmStopped(id) => ServerMessage::AlarmStopped(id),
+            Self::ExceptionsSet(exceptions) => ServerMessage::Exceptions(exceptions),
+            // the client has to start over after a reload
+            Self::Reloaded { alarms, sounds, .. } => ServerMessage::Init {
+                alarms,
+                sounds,
+                ringing_alarms: HashSet::new(),
+            },
+            Self::ShuttingDown => ServerMessage::ShuttingDown,
+        }
+    }
+
+    /// what goes in the history for this alert
+    fn history(&self) -> Vec<(u64, history::Event)> {
+        match self {
+            Self::AlarmSet(id, alarm_edit) => {
+                vec![(*id, history::Event::Edited(alarm_edit.clone()))]
+            }
+            Self::AlaramAdded(alarm) => vec![(alarm.id, history::Event::Added(alarm.clone()))],
+            Self::AlarmCreated(alarm) => {
+                vec![(alarm.id, history::Event::Added(alarm.clone().into()))]
+            }
+            Self::AlarmRemoved(id) => vec![(*id, history::Event::Removed)],
+            Self::AlarmRinging(id) => vec![(*id, history::Event::Rang)],
+            Self::AlarmStopped(id) => vec![(*id, history::Event::Stopped)],
+            Self::Batch(changes) => changes
+                .iter()
+                .map(|change| match change {
+                    Change::Created(alarm) => {
+                        (alarm.id, history::Event::Added(alarm.clone().into()))
+                    }
+                    Change::Set(id, alarm_edit) => {
+                        (*id, history::Event::Edited(alarm_edit.clone()))
+                    }
+                    Change::Removed(id) => (*id, history::Event::Removed),
+                })
+                .collect(),
+            Self::SoundAdded(_)
+            | Self::SoundRemoved(..)
+            | Self::ExceptionsSet(_)
+            | Self::Reloaded { .. }
+            | Self::ShuttingDown => Vec::new(),
+        }
+    }
+}
+/// A clients queue of events, so that a slow client only holds up itself.
+#[allow(missing_debug_implementations)]
+pub struct Subscriber {
+    queue: mpsc::SyncSender<ServerMessage>,
+    filter: Arc<Mutex<Filter>>,
+    /// the queue was full so events were dropped, until the client is told to resync
+    behind: Arc<AtomicBool>,
+}
+
+impl Subscriber {
+    /// how many events a client can fall behind before it has to resync
+    const CAPACITY: usize = 64;
+
+    /// Queues `message` if it gets through the filter, `false` if the client is gone.
+    fn notify(&self, message: &ServerMessage) -> bool {
+        // nothing is queued until the client has caught up and been told to resync
+        if self.behind.load(Ordering::SeqCst) {
+            return true;
+        }
+        let Some(message) = self.filter.lock().unwrap().apply(message.clone()) else {
+            return true;
+        };
+        match self.queue.try_send(message) {
+            Ok(()) => true,
+            Err(mpsc::TrySendError::Full(_)) => {
+                self.behind.store(true, Ordering::SeqCst);
+                true
+            }
+            Err(mpsc::TrySendError::Disconnected(_)) => false,
+        }
+    }
+}
+
+/// the audio side of an alarm
+#[allow(missing_debug_implementations)]
+pub struct AlarmPlayer {
+    /// when the alarm rings next, `None` if it never does again
+    time: Option<DateTime<Local>>,
+    player: rodio::Player,
+    alarm: config::Alarm,
+    // so that a stopped alarm doesnt start ringing again right away
+    rang: Option<DateTime<Local>>,
+}
+
+impl AlarmPlayer {
+    fn new(alarm: config::Alarm, mixer: &Mixer, holidays: &[config::Exception]) -> Self {
+        let player = rodio::Player::connect_new(mixer);
+        player.pause();
+        player.set_volume(alarm.volume / 100.0);
+        let mut player = Self {
+            time: None,
+            player,
+            alarm,
+            rang: None,
+        };
+        player.schedule(holidays);
+        player
+    }
+
+    /// Picks when the alarm rings next (after it rang, or its time, dates or exceptions changed).
+    fn schedule(&mut self, holidays: &[config::Exception]) {
+        self.time = self
+            .alarm
+            .next_ring(Local::now().naive_local(), holidays)
+            // a time skipped by daylight saving time
+            .and_then(|time| time.and_local_timezone(Local).earliest());
+        self.log_schedule();
+    }
+
+    /// so that the log says why an alarm did or didn't ring
+    fn log_schedule(&self) {
+        let id = self.alarm.id;
+        if !self.alarm.enabled {
+            log::debug!("alarm {id} is disabled");
+        } else if let Some(time) = self.time {
+            log::info!("alarm {id} will ring at {time}");
+        } else {
+            log::info!("alarm {id} won't ring again");
+        }
+    }
+
+    fn edit(
+        &mut self,
+        alarm_edit: AlarmEdit,
+        sounds: &HashMap<String, config::Sound>,
+        holidays: &[config::Exception],
+    ) {
+        match alarm_edit {
+            AlarmEdit::Time(naive_time) => {
+                self.alarm.time = naive_time;
+                self.schedule(holidays);
+            }
+            AlarmEdit::Start(_)
+            | AlarmEdit::End(_)
+            | AlarmEdit::Date(_)
+            | AlarmEdit::Skip(_)
+            | AlarmEdit::Exceptions(_)
+            | AlarmEdit::Cron(_) => {
+                self.alarm.apply(alarm_edit);
+                self.schedule(holidays);
+            }
+            AlarmEdit::Name(_) => {}
+            AlarmEdit::Sound(sound) => {
+                self.alarm.sound = sound;
+                self.reload(sounds);
+            }
+            AlarmEdit::Playlist(playlist) => {
+                self.alarm.playlist = playlist;
+                self.reload(sounds);
+            }
+            AlarmEdit::SoundMode(sound_mode) => {
+                self.alarm.sound_mode = sound_mode;
+                self.reload(sounds);
+            }
+            AlarmEdit::Volume(volume) => {
+                self.alarm.volume = volume;
+                self.player.set_volume(volume / 100.);
+            }
+            AlarmEdit::Enable(enable) => {
+                if !enable {
+                    self.player.clear();
+                }
+                self.alarm.enabled = enable;
+                self.log_schedule();
+            }
+        }
+    }
+
+    fn is_ringing(&self) -> bool {
+        !self.player.is_paused()
+    }
+
+    /// replaces whatever is queued with the alarms sounds, it keeps playing if it was ringing
+    fn reload(&self, sounds: &HashMap<String, config::Sound>) {
+        let ringing = self.is_ringing();
+        self.player.clear();
+        // the day it rang, the next ring could be tomorrow already
+        let day = self.rang.unwrap_or_else(Local::now).date_naive();
+        for source in sound::open_alarm(&self.alarm, sounds, day) {
+            self.player.append(source);
+        }
+        if ringing {
+            self.player.play();
+        }
+    }
 }
+
 #[allow(missing_debug_implementations)]
 pub struct ServerCommand {
     kind: ServerCommandKind,
-    reciever: mpsc::Sender<ServerResponce>,
+    reciever: mpsc::Sender<(Option<u64>, ServerResponce)>,
+    /// the id of the request this is for, if any
+    request: Option<u64>,
 }
 #[allow(missing_debug_implementations)]
 pub enum ServerResponce {
@@ -43,24 +291,371 @@ pub enum ServerResponce {
         sounds: HashMap<String, config::Sound>,
         ringing_alarms: HashSet<u64>,
     },
+    SoundNotRemoved(String, SoundRemovalError),
+    History(Vec<history::Entry>),
+    AlarmCreated(config::Alarm),
+    AlarmNotCreated(AlarmError),
+    BatchApplied(Vec<Change>),
+    BatchRejected(usize, AlarmError),
+    Exceptions(Vec<config::Exception>),
+    Calendar(String),
+    CalendarImported(Vec<Change>, Vec<ics::Warning>),
+    CalendarNotImported(ics::ParseError),
+}
+
+impl From<ServerResponce> for ServerMessage {
+    fn from(responce: ServerResponce) -> Self {
+        match responce {
+            ServerResponce::NewUID(id) => Self::UID(id),
+            ServerResponce::Init {
+                alarms,
+                sounds,
+                ringing_alarms,
+            } => Self::Init {
+                alarms,
+                sounds,
+                ringing_alarms,
+            },
+            ServerResponce::SoundNotRemoved(sound, error) => Self::SoundNotRemoved(sound, error),
+            ServerResponce::History(entries) => Self::History(entries),
+            ServerResponce::AlarmCreated(alarm) => Self::AlarmCreated(alarm),
+            ServerResponce::AlarmNotCreated(error) => Self::AlarmNotCreated(error),
+            ServerResponce::BatchApplied(changes) => Self::BatchApplied(changes),
+            ServerResponce::BatchRejected(op, error) => Self::BatchRejected(op, error),
+            ServerResponce::Exceptions(exceptions) => Self::Exceptions(exceptions),
+            ServerResponce::Calendar(calendar) => Self::Calendar(calendar),
+            ServerResponce::CalendarImported(changes, warnings) => {
+                Self::CalendarImported(changes, warnings)
+            }
+            ServerResponce::CalendarNotImported(error) => Self::CalendarNotImported(error),
+        }
+    }
 }
+
 #[allow(missing_debug_implementations)]
 pub enum ServerCommandKind {
     NewUID,
     Init,
+    SetAlarm(u64, AlarmEdit),
+    AddAlarm(Alarm),
+    RemoveAlarm(u64),
+    SetExceptions(Vec<config::Exception>),
+    AddSounds(Vec<config::Sound>),
+    CreateAlarm(AlarmSpec),
+    Batch(Vec<Op>),
+    SkipNext(u64),
+    GetExceptions,
+    ExportCalendar,
+    ImportCalendar(String),
+    RemoveSound(String, SoundRemoval),
+    QueryHistory {
+        since: Option<chrono::DateTime<Local>>,
+        until: Option<chrono::DateTime<Local>>,
+        alarm: Option<u64>,
+    },
+}
+
+/// What the config thread has to do for `message`, `None` for the ones client threads answer
+/// themselves.
+fn command(message: ClientMessage) -> Option<ServerCommandKind> {
+    Some(match message {
+        ClientMessage::GetNewUID => ServerCommandKind::NewUID,
+        ClientMessage::Init => ServerCommandKind::Init,
+        ClientMessage::SetAlarm(id, alarm_edit) => ServerCommandKind::SetAlarm(id, alarm_edit),
+        ClientMessage::AddAlarm(alarm) => ServerCommandKind::AddAlarm(alarm),
+        ClientMessage::CreateAlarm(spec) => ServerCommandKind::CreateAlarm(spec),
+        ClientMessage::Batch(ops) => ServerCommandKind::Batch(ops),
+        ClientMessage::RemoveAlarm(id) => ServerCommandKind::RemoveAlarm(id),
+        ClientMessage::SkipNext(id) => ServerCommandKind::SkipNext(id),
+        ClientMessage::GetExceptions => ServerCommandKind::GetExceptions,
+        ClientMessage::ExportCalendar => ServerCommandKind::ExportCalendar,
+        ClientMessage::ImportCalendar(calendar) => ServerCommandKind::ImportCalendar(calendar),
+        ClientMessage::SetExceptions(exceptions) => ServerCommandKind::SetExceptions(exceptions),
+        ClientMessage::AddedSounds(sounds) => ServerCommandKind::AddSounds(sounds),
+        ClientMessage::RemoveSound(sound, removal) => {
+            ServerCommandKind::RemoveSound(sound, removal)
+        }
+        ClientMessage::QueryHistory {
+            since,
+            until,
+            alarm,
+        } => ServerCommandKind::QueryHistory {
+            since,
+            until,
+            alarm,
+        },
+        ClientMessage::StopAlarm(_)
+        | ClientMessage::Shutdown
+        | ClientMessage::Ping
+        | ClientMessage::Subscribe(_)
+        | ClientMessage::Request(..) => return None,
+    })
+}
+
+#[derive(Parser)]
+#[command(author, version, about, long_about = None)]
+struct Args {
+    /// the socket to listen on, a namespaced name or a path (see also `ROOSTY_CLOCKD_SOCKET`)
+    #[clap(long)]
+    socket: Option<String>,
+    /// log to the terminal as well as the log file
+    #[clap(long)]
+    foreground: bool,
+    /// log more (debug, or trace if given twice), overrides `ROOSTY_CLOCKD_LOG` and the config
+    #[clap(long, short, action = clap::ArgAction::Count)]
+    verbose: u8,
+    /// serve the HTTP API, on localhost unless an address is given (which has to be loopback, use
+    /// `--listen` for other machines)
+    #[clap(long, num_args = 0..=1, default_missing_value = roosty_clockd::http::DEFAULT_ADDRESS)]
+    http: Option<std::net::SocketAddr>,
+    /// let remote clients in on this address (can be given more than once), overrides
+    /// `remote.listen` in the config, they need the token from `remote.token` or
+    /// `ROOSTY_CLOCKD_TOKEN`
+    #[clap(long)]
+    listen: Vec<SocketAddr>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// report whether `roosty_clockd` is running and its PID
+    Status,
+    /// install systemd user units that start `roosty_clockd` when something connects to it
+    InstallService {
+        /// print the units instead of installing them
+        #[clap(long)]
+        print: bool,
+    },
+}
+
+/// Applies an edit to the saved alarm, an alarm that was moved or disabled isn't ringing anymore.
+fn edit_alarm(
+    config: &mut Config,
+    ringing_alarms: &mut HashSet<u64>,
+    id: u64,
+    alarm_edit: AlarmEdit,
+) {
+    if let Some(alarm) = config.alarms.data.get_mut(&id) {
+        if matches!(
+            alarm_edit,
+            AlarmEdit::Time(_) | AlarmEdit::Cron(_) | AlarmEdit::Enable(false)
+        ) {
+            ringing_alarms.remove(&id);
+        }
+        alarm.apply(alarm_edit);
+    }
+}
+
+/// Applies what a batch changed to the config.
+fn apply_changes(config: &mut Config, ringing_alarms: &mut HashSet<u64>, changes: &[Change]) {
+    for change in changes.iter().cloned() {
+        match change {
+            Change::Created(alarm) => {
+                config.alarms.insert(alarm);
+            }
+            Change::Set(id, alarm_edit) => {
+                edit_alarm(config, ringing_alarms, id, alarm_edit);
+            }
+            Change::Removed(id) => {
+                config.alarms.data.remove(&id);
+                ringing_alarms.remove(&id);
+            }
+        }
+    }
+}
+
+/// how many alerts the dispatcher can fall behind
+const ALERT_CAPACITY: usize = 256;
+
+/// how often the config thread looks for expired alarms
+const EXPIRY_CHECK: time::Duration = time::Duration::from_secs(30);
+
+/// how long a client has to send its whole handshake
+const HANDSHAKE_TIMEOUT: time::Duration = time::Duration::from_secs(5);
+
+/// Reads from a connection until a deadline, however the reads are spread out.
+struct Deadline<R> {
+    conn: R,
+    until: time::Instant,
+    set_timeout: fn(&R, Option<time::Duration>) -> io::Result<()>,
+}
+
+impl<R: Read> Deadline<R> {
+    fn new(conn: R, set_timeout: fn(&R, Option<time::Duration>) -> io::Result<()>) -> Self {
+        Self {
+            conn,
+            until: time::Instant::now() + HANDSHAKE_TIMEOUT,
+            set_timeout,
+        }
+    }
+}
+
+impl<R: Read> Read for Deadline<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let left = self.until.saturating_duration_since(time::Instant::now());
+        if left.is_zero() {
+            return Err(io::Error::new(
+                io::ErrorKind::TimedOut,
+                "the handshake took too long",
+            ));
+        }
+        (self.set_timeout)(&self.conn, Some(left))?;
+        self.conn.read(buf)
+    }
+}
+
+/// a new client, on the local socket or over TCP
+enum Incoming {
+    Local(Stream),
+    Remote(TcpStream),
+}
+
+impl Incoming {
+    /// Checks the token of remote clients and reads which [`Encoding`] the client wants, and makes
+    /// the connection nonblocking for the messages after.
+    fn handshake(&self, token: Option<&str>, backoff: &remote::Backoff) -> io::Result<Encoding> {
+        match self {
+            Self::Local(conn) => {
+                conn.set_nonblocking(false)?;
+                let mut deadline =
+                    Deadline::new(conn, |conn, timeout| conn.set_recv_timeout(timeout));
+                let encoding = Encoding::accept(&mut deadline)?;
+                conn.set_nonblocking(true)?;
+                Ok(encoding)
+            }
+            Self::Remote(conn) => {
+                conn.set_nonblocking(false)?;
+                let mut deadline =
+                    Deadline::new(conn, |conn, timeout| conn.set_read_timeout(timeout));
+                // remote clients are only accepted if there is a token
+                let peer = conn.peer_addr()?.ip();
+                remote::accept_token(&mut deadline, token.unwrap_or_default(), peer, backoff)?;
+                let encoding = Encoding::accept(&mut deadline)?;
+                conn.set_nonblocking(true)?;
+                Ok(encoding)
+            }
+        }
+    }
+
+    fn split(self) -> io::Result<(Box<dyn Read + Send>, Box<dyn Write + Send>)> {
+        Ok(match self {
+            Self::Local(conn) => {
+                let (reader, writer) = conn.split();
+                (Box::new(reader), Box::new(writer))
+            }
+            Self::Remote(conn) => (Box::new(conn.try_clone()?), Box::new(conn)),
+        })
+    }
+}
+
+/// The next client on the local socket or any of the remote ones, if one is waiting.
+fn accept(listener: &Listener, remote: &[TcpListener]) -> Option<Incoming> {
+    match listener.accept() {
+        Ok(conn) => return Some(Incoming::Local(conn)),
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+        Err(e) => log::warn!("Incoming connection failed: {e}"),
+    }
+    remote.iter().find_map(|listener| match listener.accept() {
+        Ok((conn, peer)) => {
+            log::info!("remote client from {peer}");
+            Some(Incoming::Remote(conn))
+        }
+        Err(e) if e.kind() == io::ErrorKind::WouldBlock => None,
+        Err(e) => {
+            log::warn!("Incoming remote connection failed: {e}");
+            None
+        }
+    })
+}
+
+/// Listens on `addresses` for remote clients, they need a `token` to be let in.
+fn listen_remote(addresses: &[SocketAddr], token: Option<&str>) -> io::Result<Vec<TcpListener>> {
+    if addresses.is_empty() {
+        return Ok(Vec::new());
+    }
+    if token.is_none() {
+        log::error!(
+            "not listening for remote clients without a token, set remote.token in the config or {}",
+            remote::TOKEN_ENV
+        );
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "remote control needs a token",
+        ));
+    }
+    addresses
+        .iter()
+        .map(|address| {
+            let listener = TcpListener::bind(address).inspect_err(|e| {
+                log::error!("couldn't listen for remote clients on {address}: {e}");
+            })?;
+            listener.set_nonblocking(true)?;
+            log::info!("listening for remote clients on {address}");
+            if !address.ip().is_loopback() {
+                log::warn!(
+                    "remote clients on {address} aren't encrypted, only use it on a trusted network or through a tunnel"
+                );
+            }
+            Ok(listener)
+        })
+        .collect()
+}
+
+/// Sets `shutdown` on SIGINT/SIGTERM and `reload` on SIGHUP.
+fn handle_signals(shutdown: &Arc<AtomicBool>, reload: &Arc<AtomicBool>) -> io::Result<()> {
+    for signal in [libc::SIGINT, libc::SIGTERM] {
+        let shutdown = shutdown.clone();
+        // SAFETY: storing to an atomic is async signal safe
+        unsafe {
+            signal_hook_registry::register(signal, move || shutdown.store(true, Ordering::SeqCst))
+        }?;
+    }
+    #[cfg(unix)]
+    {
+        let reload = reload.clone();
+        // SAFETY: storing to an atomic is async signal safe
+        unsafe {
+            signal_hook_registry::register(libc::SIGHUP, move || {
+                reload.store(true, Ordering::SeqCst);
+            })
+        }?;
+    }
+    #[cfg(not(unix))]
+    let _ = reload;
+    Ok(())
 }
 
 fn main() -> std::io::Result<()> {
-    // Define a function that checks for errors in incoming connections. We'll use this to filter
-    // through connections that fail on initialization for one reason or another.
-    fn handle_error(conn: io::Result<Stream>) -> Option<Stream> {
-        match conn {
-            Ok(c) => Some(c),
-            Err(e) => {
-                eprintln!("Incoming connection failed: {e}");
-                None
+    let args = Args::parse();
+    let address = socket::Address::resolve(args.socket.as_deref());
+    match args.command {
+        Some(Command::Status) => {
+            let status = Status::of(&address);
+            println!("roosty_clockd on {address} is {status}");
+            std::process::exit(i32::from(!matches!(status, Status::Running(_))));
+        }
+        Some(Command::InstallService { print }) => {
+            let units = systemd::Units::new(&std::env::current_exe()?, &address);
+            if print {
+                print!("{units}");
+                return Ok(());
             }
+            let dir = systemd::Units::user_dir().ok_or_else(|| {
+                io::Error::new(
+                    io::ErrorKind::NotFound,
+                    "couldn't find the config directory",
+                )
+            })?;
+            let (service, socket) = units.install(&dir)?;
+            println!("installed {} and {}", service.display(), socket.display());
+            println!(
+                "run `systemctl --user daemon-reload && systemctl --user enable --now {}` to start it",
+                systemd::SOCKET_NAME
+            );
+            return Ok(());
         }
+        None => {}
     }
 
     if !Config::is_config_present() {
@@ -74,134 +669,167 @@ fn main() -> std::io::Result<()> {
             .unwrap();
     }
     let mut config = config::Config::load(config::Config::config_path());
-    // Pick a name.
-    let printname = "roosty-clockd.sock";
-    let name = printname.to_ns_name::<GenericNamespaced>()?;
-
-    // Configure our listener...
-    let opts = ListenerOptions::new().name(name);
-
-    // ...then create it.
-    let listener = match opts.create_sync() {
-        Err(e) if e.kind() == io::ErrorKind::AddrInUse => {
-            eprintln!(
-                "Error: could not start server because the socket file is occupied. Please check
-                if {printname} is in use by another process and try again."
+    // flushed when dropped
+    let _logger = roosty_clockd::logging::init(&config.log, args.verbose, args.foreground)
+        .map_err(io::Error::other)?;
+    log::info!(
+        "starting roosty_clockd {} (pid {}) on {address}",
+        env!("CARGO_PKG_VERSION"),
+        std::process::id()
+    );
+
+    // held until we exit
+    let _lock = match instance::Lock::acquire(&address) {
+        Err(instance::Error::Io(e)) => return Err(e),
+        lock => lock.map_err(|e| {
+            log::error!("{e} on {address}");
+            io::Error::new(io::ErrorKind::AddrInUse, e)
+        })?,
+    };
+
+    let inherited = systemd::listener();
+    if inherited.is_some() {
+        log::info!("using the socket passed by systemd");
+    }
+    let listener = match inherited.map_or_else(|| instance::listen(&address), Ok) {
+        Err(instance::Error::Io(e)) if e.kind() == io::ErrorKind::AddrInUse => {
+            log::error!(
+                "could not start server because the socket is occupied. Please check
+                if {address} is in use by another process and try again."
             );
             return Err(e);
         }
-        x => x?,
+        Err(instance::Error::Io(e)) => return Err(e),
+        Err(e) => {
+            log::error!("{e} on {address}");
+            return Err(io::Error::new(io::ErrorKind::AddrInUse, e));
+        }
+        Ok(listener) => listener,
     };
 
-    listener.set_nonblocking(interprocess::local_socket::ListenerNonblockingMode::Stream);
+    let token: Option<Arc<str>> = config.remote.token().map(Into::into);
+    let remote_listeners = listen_remote(
+        if args.listen.is_empty() {
+            &config.remote.listen
+        } else {
+            &args.listen
+        },
+        token.as_deref(),
+    )?;
+    let backoff = Arc::new(remote::Backoff::default());
 
-    let (mut s, r) = async_broadcast::broadcast(10);
-    s.set_overflow(true);
+    // accepting doesn't block either, so that we notice when to shut down
+    listener.set_nonblocking(interprocess::local_socket::ListenerNonblockingMode::Both)?;
+
+    let shutdown = Arc::new(AtomicBool::new(false));
+    let reload = Arc::new(AtomicBool::new(false));
+    handle_signals(&shutdown, &reload)?;
+
+    // clients get their events from the dispatcher, the config and audio threads can't miss any
+    // so they get their own
+    let (mut clients, r) = async_broadcast::broadcast(ALERT_CAPACITY);
+    clients.set_overflow(true);
+    let (s_audio, r_audio) = mpsc::channel();
+    let (s_config, r_config) = mpsc::channel();
+    let s = Alerts {
+        clients,
+        threads: [s_audio, s_config],
+    };
+    let subscribers: Arc<Mutex<Vec<Subscriber>>> = Arc::default();
+    let dispatcher = {
+        let mut r = r.new_receiver();
+        let subscribers = subscribers.clone();
+        thread::spawn(move || {
+            loop {
+                let alert: Alert = match r.recv_blocking() {
+                    Ok(alert) => alert,
+                    Err(async_broadcast::RecvError::Overflowed(missed)) => {
+                        log::error!("the dispatcher missed {missed} events");
+                        for subscriber in subscribers.lock().unwrap().iter() {
+                            subscriber.behind.store(true, Ordering::SeqCst);
+                        }
+                        continue;
+                    }
+                    Err(async_broadcast::RecvError::Closed) => return,
+                };
+                let message = alert.into_message();
+                let mut subscribers = subscribers.lock().unwrap();
+                if message == ServerMessage::ShuttingDown {
+                    // every client has to hear about this one, even if it has to wait
+                    for subscriber in subscribers.drain(..) {
+                        let _ = subscriber.queue.send(ServerMessage::ShuttingDown);
+                    }
+                    return;
+                }
+                subscribers.retain(|subscriber| subscriber.notify(&message));
+            }
+        })
+    };
     let (s_server, r_server) = mpsc::channel();
 
-    {
+    let audio_thread = {
         let mut sounds = config.sounds.sounds.clone();
+        let mut holidays = config.exceptions.clone();
         let s = s.clone();
 
         let alarms = config.alarms.data.clone();
-        let mut r = r.new_receiver();
-        let stream_handle = rodio::DeviceSinkBuilder::open_default_sink().unwrap();
+        let r = r_audio;
+        let stream_handle = rodio::DeviceSinkBuilder::open_default_sink().map_err(|e| {
+            log::error!("couldn't open the audio output: {e}");
+            io::Error::other(e)
+        })?;
         thread::spawn(move || {
             let mut alarms: HashMap<_, _> = alarms
                 .into_iter()
-                .map(
-                    |(
-                        id,
-                        config::Alarm {
-                            time,
-                            volume,
-                            enabled,
-                            sound,
-                            ..
-                        },
-                    )| {
-                        let path = sounds.get(&sound).unwrap().path.clone();
-                        let input = decoder::Decoder::new(BufReader::new(
-                            std::fs::File::open(path).unwrap(),
-                        ))
-                        .unwrap()
-                        .repeat_infinite();
-                        let sink = rodio::Player::connect_new(stream_handle.mixer());
-                        sink.pause();
-                        sink.set_volume(volume / 100.0);
-                        sink.append(input);
-                        (
-                            id,
-                            (
-                                chrono::Local::now().with_time(time).unwrap(),
-                                enabled,
-                                sink,
-                                volume,
-                            ),
-                        )
-                    },
-                )
+                .map(|(id, alarm)| {
+                    let player = AlarmPlayer::new(alarm, stream_handle.mixer(), &holidays);
+                    (id, player)
+                })
                 .collect();
             loop {
                 if let Ok(a) = r.try_recv() {
                     match a {
                         Alert::AlarmSet(id, alarm_edit) => {
                             if let Some(a) = alarms.get_mut(&id) {
-                                match alarm_edit {
-                                    AlarmEdit::Time(naive_time) => {
-                                        a.0 = chrono::Local::now().with_time(naive_time).unwrap();
-                                    }
-                                    AlarmEdit::Name(_) => {}
-                                    AlarmEdit::Sound(sound) => {
-                                        let is_paused = a.2.is_paused();
-                                        a.2.clear();
-                                        let path = sounds.get(&sound).unwrap().path.clone();
-                                        let input = decoder::Decoder::new(BufReader::new(
-                                            std::fs::File::open(path.clone()).unwrap(),
-                                        ))
-                                        .unwrap()
-                                        .repeat_infinite();
-                                        a.2.append(input);
-                                        a.2.pause();
-                                        if !is_paused {
-                                            a.2.play();
-                                        }
-                                    }
-
-                                    AlarmEdit::Volume(volume) => a.2.set_volume(volume / 100.),
-                                    AlarmEdit::Enable(enable) => {
-                                        if !enable {
-                                            a.2.stop();
-                                        }
-                                        a.1 = enable;
-                                    }
-                                }
+                                a.edit(alarm_edit, &sounds, &holidays);
                             }
                         }
                         Alert::AlaramAdded(alarm) => {
-                            let path = sounds.get(&alarm.sound).unwrap().path.clone();
-                            let input = decoder::Decoder::new(BufReader::new(
-                                std::fs::File::open(path.clone()).unwrap(),
-                            ))
-                            .unwrap()
-                            .repeat_infinite();
-                            let sink = rodio::Player::connect_new(stream_handle.mixer());
-                            sink.set_volume(alarm.volume / 100.0);
-                            sink.append(input);
-                            sink.pause();
-                            alarms.insert(
-                                alarm.id,
-                                (
-                                    chrono::Local::now().with_time(alarm.time).unwrap(),
-                                    true,
-                                    sink,
-                                    alarm.volume,
-                                ),
-                            );
+                            let player =
+                                AlarmPlayer::new(alarm.into(), stream_handle.mixer(), &holidays);
+                            alarms.insert(player.alarm.id, player);
+                        }
+                        Alert::AlarmCreated(alarm) => {
+                            let player = AlarmPlayer::new(alarm, stream_handle.mixer(), &holidays);
+                            alarms.insert(player.alarm.id, player);
                         }
                         Alert::AlarmRemoved(id) => {
                             if let Some(a) = alarms.remove(&id) {
-                                a.2.stop();
+                                a.player.stop();
+                            }
+                        }
+                        Alert::Batch(changes) => {
+                            for change in changes {
+                                match change {
+                                    Change::Created(alarm) => {
+                                        let player = AlarmPlayer::new(
+                                            alarm,
+                                            stream_handle.mixer(),
+                                            &holidays,
+                                        );
+                                        alarms.insert(player.alarm.id, player);
+                                    }
+                                    Change::Set(id, alarm_edit) => {
+                                        if let Some(a) = alarms.get_mut(&id) {
+                                            a.edit(alarm_edit, &sounds, &holidays);
+                                        }
+                                    }
+                                    Change::Removed(id) => {
+                                        if let Some(a) = alarms.remove(&id) {
+                                            a.player.stop();
+                                        }
+                                    }
+                                }
                             }
                         }
                         Alert::SoundAdded(new_sounds) => {
@@ -211,14 +839,46 @@ fn main() -> std::io::Result<()> {
                                     .map(|sound| (sound.name.clone(), sound)),
                             );
                         }
-                        Alert::SoundRemoved(id) => {
+                        Alert::SoundRemoved(id, _) => {
                             sounds.remove(&id);
                         }
+                        Alert::ExceptionsSet(exceptions) => {
+                            holidays = exceptions;
+                            for alarm in alarms.values_mut() {
+                                alarm.schedule(&holidays);
+                            }
+                        }
                         Alert::AlarmRinging(_) => {}
                         Alert::AlarmStopped(id) => {
                             if let Some(a) = alarms.get_mut(&id) {
-                                a.2.stop();
+                                log::info!("alarm {id} stopped");
+                                a.player.clear();
+                            }
+                        }
+                        Alert::Reloaded {
+                            alarms: new_alarms,
+                            sounds: new_sounds,
+                            exceptions,
+                        } => {
+                            for alarm in alarms.values() {
+                                alarm.player.clear();
+                            }
+                            holidays = exceptions;
+                            alarms = new_alarms
+                                .into_iter()
+                                .map(|(id, alarm)| {
+                                    let player =
+                                        AlarmPlayer::new(alarm, stream_handle.mixer(), &holidays);
+                                    (id, player)
+                                })
+                                .collect();
+                            sounds = new_sounds;
+                        }
+                        Alert::ShuttingDown => {
+                            for alarm in alarms.values() {
+                                alarm.player.clear();
                             }
+                            return;
                         }
                     }
                 }
@@ -231,102 +891,359 @@ fn main() -> std::io::Result<()> {
                 let minutes = Duration::minutes(1);
                 let before = now;
                 let after = now + minutes;
-                if alarms.iter_mut().any(|(id, alarm)| {
-                    if alarm.1 && alarm.0 > before && alarm.0 < after && alarm.2.is_paused() {
-                        s.broadcast_blocking(Alert::AlarmRinging(*id));
-                        cpvc::set_system_volume((alarm.3 / 100.) as u8);
-                        alarm.2.play();
+                let mut any_ringing = false;
+                for (id, alarm) in &mut alarms {
+                    // once its time passed it rings again on the next day it rings on
+                    if alarm.time.is_some_and(|time| time <= before) {
+                        alarm.schedule(&holidays);
+                    }
+                    if let Some(time) = alarm.time
+                        && alarm.alarm.enabled
+                        && time > before
+                        && time < after
+                        && !alarm.is_ringing()
+                        && alarm.rang != Some(time)
+                    {
+                        log::info!(
+                            "alarm {id} is ringing (scheduled for {time}, volume {})",
+                            alarm.alarm.volume
+                        );
+                        s.send(Alert::AlarmRinging(*id));
+                        cpvc::set_system_volume((alarm.alarm.volume / 100.) as u8);
+                        alarm.rang = Some(time);
+                        // load the sounds now, so that shuffled and daily sounds are picked when
+                        // the alarm rings
+                        alarm.reload(&sounds);
+                        log::debug!(
+                            "alarm {id} plays {:?}",
+                            alarm.alarm.sounds_for(time.date_naive())
+                        );
+                        alarm.player.play();
                         cpvc::set_mute(false);
                     }
-                    !alarm.2.is_paused()
-                }) {
+                    if alarm.is_ringing() {
+                        // start the playlist over once its done
+                        if alarm.player.empty() {
+                            alarm.reload(&sounds);
+                        }
+                        any_ringing = true;
+                    }
+                }
+                if any_ringing {
                     cpvc::set_mute(false);
                 }
             }
-        });
-    }
+        })
+    };
 
-    {
-        let mut r = r.new_receiver();
+    let config_thread = {
+        let r = r_config;
+        let s = s.clone();
         thread::spawn(move || {
             let mut ringing_alarms = HashSet::new();
+            let mut journal = Journal::open(Config::history_path())
+                .inspect_err(|e| log::error!("couldn't open the history, not keeping one: {e}"))
+                .ok();
+            let mut expiry_checked = time::Instant::now();
             loop {
-                if let Ok(m) = r.try_recv() {
-                    match m {
-                        Alert::AlarmSet(id, alarm_edit) => {
-                            if let Some(alarm) = config.alarms.data.get_mut(&id) {
-                                match alarm_edit {
-                                    AlarmEdit::Time(new_time) => {
-                                        ringing_alarms.remove(&id);
-                                        alarm.time = new_time;
-                                    }
-                                    AlarmEdit::Name(new_name) => alarm.name = new_name,
-                                    AlarmEdit::Sound(new_sound) => alarm.sound = new_sound,
-                                    AlarmEdit::Volume(new_volume) => alarm.volume = new_volume,
-                                    AlarmEdit::Enable(new_enabled) => {
-                                        if !new_enabled {
-                                            ringing_alarms.remove(&id);
-                                        }
-                                        alarm.enabled = new_enabled;
-                                    }
-                                }
-                            }
-                            config.save(Config::config_path());
-                        }
-                        Alert::AlaramAdded(alarm) => {
-                            let alarm = config::Alarm {
-                                name: alarm.name,
-                                time: alarm.time,
-                                volume: alarm.volume,
-                                sound: alarm.sound,
-                                enabled: true,
-                                id: alarm.id,
-                            };
-                            config.alarms.insert(alarm);
-                            config.save(Config::config_path());
-                        }
-                        Alert::AlarmRemoved(id) => {
-                            config.alarms.data.remove(&id).unwrap();
-                            config.save(Config::config_path());
-                            ringing_alarms.remove(&id);
+                if expiry_checked.elapsed() >= EXPIRY_CHECK {
+                    expiry_checked = time::Instant::now();
+                    let changes = config.expire(&ringing_alarms, Local::now().naive_local());
+                    if !changes.is_empty() {
+                        log::info!("{} alarms expired ({:?})", changes.len(), config.expired);
+                        apply_changes(&mut config, &mut ringing_alarms, &changes);
+                        config.save(Config::config_path());
+                        s.send(Alert::Batch(changes));
+                    }
+                }
+                if reload.swap(false, Ordering::SeqCst) {
+                    match Config::try_load(Config::config_path()) {
+                        Ok(new_config) => {
+                            log::info!("reloaded config");
+                            config = new_config;
+                            ringing_alarms.clear();
+                            s.send(Alert::Reloaded {
+                                alarms: config.alarms.data.clone(),
+                                sounds: config.sounds.sounds.clone(),
+                                exceptions: config.exceptions.clone(),
+                            });
                         }
-                        Alert::SoundAdded(sounds) => {
-                            config.sounds.sounds.extend(
-                                sounds.into_iter().map(|sound| (sound.name.clone(), sound)),
-                            );
-                            config.save(Config::config_path());
+                        Err(e) => {
+                            log::error!("couldn't reload config, keeping the old one: {e}");
                         }
-                        Alert::SoundRemoved(sound) => {
-                            config.sounds.sounds.remove(&sound).unwrap();
-                            config.save(Config::config_path());
+                    }
+                }
+                if let Ok(m) = r.try_recv() {
+                    if let Some(journal) = &mut journal {
+                        for (id, event) in m.history() {
+                            if let Err(e) = journal.record(id, event) {
+                                log::error!("couldn't add to the history: {e}");
+                            }
                         }
+                    }
+                    match m {
+                        // the config thread made these changes itself (and saved them) before
+                        // telling everyone
+                        Alert::AlarmSet(..)
+                        | Alert::AlaramAdded(_)
+                        | Alert::AlarmCreated(_)
+                        | Alert::AlarmRemoved(_)
+                        | Alert::Batch(_)
+                        | Alert::SoundAdded(_)
+                        | Alert::SoundRemoved(..)
+                        | Alert::ExceptionsSet(_)
+                        | Alert::Reloaded { .. } => {}
                         Alert::AlarmRinging(id) => {
                             ringing_alarms.insert(id);
                         }
                         Alert::AlarmStopped(id) => {
                             ringing_alarms.remove(&id);
                         }
+                        Alert::ShuttingDown => {
+                            // everything before this has been handled, so the config is up to date
+                            config.save(Config::config_path());
+                            return;
+                        }
                     }
                 }
-                if let Ok(ServerCommand { kind, reciever }) = r_server.try_recv() {
+                if let Ok(ServerCommand {
+                    kind,
+                    reciever,
+                    request,
+                }) = r_server.try_recv()
+                {
+                    // the client may have left before its answer is ready, that's fine
                     match kind {
+                        ServerCommandKind::SetAlarm(id, alarm_edit) => {
+                            // checked like an edit in a batch, everyone is told if it worked
+                            // but only the client that sent it if it didn't
+                            let checked = config.alarms.data.get(&id).map_or(
+                                Err(AlarmError::NoSuchAlarm(id)),
+                                |alarm| {
+                                    let mut alarm = alarm.clone();
+                                    alarm.apply(alarm_edit.clone());
+                                    AlarmSpec::from(alarm).validate(&config.sounds.sounds)
+                                },
+                            );
+                            match checked {
+                                Ok(()) => {
+                                    edit_alarm(
+                                        &mut config,
+                                        &mut ringing_alarms,
+                                        id,
+                                        alarm_edit.clone(),
+                                    );
+                                    config.save(Config::config_path());
+                                    s.send(Alert::AlarmSet(id, alarm_edit));
+                                }
+                                Err(error) => {
+                                    log::info!("not editing alarm {id}: {error}");
+                                    let _ = reciever
+                                        .send((request, ServerResponce::BatchRejected(0, error)));
+                                }
+                            }
+                        }
+                        ServerCommandKind::AddAlarm(alarm) => {
+                            // checked like a created alarm
+                            let spec = AlarmSpec::from(alarm.clone());
+                            match spec.validate(&config.sounds.sounds) {
+                                Ok(()) => {
+                                    config.alarms.insert(spec.with_id(alarm.id));
+                                    config.save(Config::config_path());
+                                    s.send(Alert::AlaramAdded(alarm));
+                                }
+                                Err(error) => {
+                                    log::info!("not adding alarm {}: {error}", alarm.id);
+                                    let _ = reciever
+                                        .send((request, ServerResponce::AlarmNotCreated(error)));
+                                }
+                            }
+                        }
+                        ServerCommandKind::RemoveAlarm(id) => {
+                            ringing_alarms.remove(&id);
+                            // clients can ask to remove alarms that don't exist, they're still
+                            // told it's gone
+                            if config.alarms.data.remove(&id).is_some() {
+                                config.save(Config::config_path());
+                            }
+                            s.send(Alert::AlarmRemoved(id));
+                        }
+                        ServerCommandKind::SetExceptions(exceptions) => {
+                            config.exceptions.clone_from(&exceptions);
+                            config.save(Config::config_path());
+                            s.send(Alert::ExceptionsSet(exceptions));
+                        }
+                        ServerCommandKind::AddSounds(sounds) => {
+                            config.sounds.sounds.extend(
+                                sounds
+                                    .iter()
+                                    .map(|sound| (sound.name.clone(), sound.clone())),
+                            );
+                            config.save(Config::config_path());
+                            s.send(Alert::SoundAdded(sounds));
+                        }
                         ServerCommandKind::NewUID => {
-                            reciever.send(ServerResponce::NewUID(get_uid())).unwrap();
+                            let _ = reciever.send((request, ServerResponce::NewUID(get_uid())));
                         }
                         ServerCommandKind::Init => {
-                            reciever
-                                .send(ServerResponce::Init {
+                            let _ = reciever.send((
+                                request,
+                                ServerResponce::Init {
                                     alarms: config.alarms.data.clone(),
                                     sounds: config.sounds.sounds.clone(),
                                     ringing_alarms: ringing_alarms.clone(),
-                                })
-                                .unwrap();
+                                },
+                            ));
+                        }
+                        ServerCommandKind::CreateAlarm(spec) => {
+                            let responce = match spec.validate(&config.sounds.sounds) {
+                                Ok(()) => {
+                                    let alarm = spec.with_id(get_uid());
+                                    config.alarms.insert(alarm.clone());
+                                    config.save(Config::config_path());
+                                    s.send(Alert::AlarmCreated(alarm.clone()));
+                                    ServerResponce::AlarmCreated(alarm)
+                                }
+                                Err(error) => {
+                                    log::info!("not creating alarm: {error}");
+                                    ServerResponce::AlarmNotCreated(error)
+                                }
+                            };
+                            let _ = reciever.send((request, responce));
+                        }
+                        ServerCommandKind::Batch(ops) => {
+                            let responce = match Op::plan(
+                                ops,
+                                &config.alarms.data,
+                                &config.sounds.sounds,
+                                get_uid,
+                            ) {
+                                Ok(changes) => {
+                                    apply_changes(&mut config, &mut ringing_alarms, &changes);
+                                    config.save(Config::config_path());
+                                    s.send(Alert::Batch(changes.clone()));
+                                    ServerResponce::BatchApplied(changes)
+                                }
+                                Err((op, error)) => {
+                                    log::info!("not applying batch, op {op} failed: {error}");
+                                    ServerResponce::BatchRejected(op, error)
+                                }
+                            };
+                            let _ = reciever.send((request, responce));
+                        }
+                        ServerCommandKind::SkipNext(id) => {
+                            // the day it already skips doesn't count, so skipping twice is the
+                            // same as once
+                            let next = config.alarms.data.get(&id).map(|alarm| {
+                                config::Alarm {
+                                    skip: None,
+                                    ..alarm.clone()
+                                }
+                                .next_ring(Local::now().naive_local(), &config.exceptions)
+                            });
+                            let responce = match next {
+                                None => {
+                                    ServerResponce::BatchRejected(0, AlarmError::NoSuchAlarm(id))
+                                }
+                                Some(None) => {
+                                    ServerResponce::BatchRejected(0, AlarmError::WontRing(id))
+                                }
+                                Some(Some(next)) => {
+                                    let changes =
+                                        vec![Change::Set(id, AlarmEdit::Skip(Some(next.date())))];
+                                    apply_changes(&mut config, &mut ringing_alarms, &changes);
+                                    config.save(Config::config_path());
+                                    s.send(Alert::Batch(changes.clone()));
+                                    ServerResponce::BatchApplied(changes)
+                                }
+                            };
+                            let _ = reciever.send((request, responce));
+                        }
+                        ServerCommandKind::GetExceptions => {
+                            let _ = reciever.send((
+                                request,
+                                ServerResponce::Exceptions(config.exceptions.clone()),
+                            ));
+                        }
+                        ServerCommandKind::ExportCalendar => {
+                            let calendar = ics::export(config.alarms.data.values(), Local::now());
+                            let _ = reciever.send((request, ServerResponce::Calendar(calendar)));
+                        }
+                        ServerCommandKind::ImportCalendar(calendar) => {
+                            let responce = match ics::alarms(&calendar, &config.sounds) {
+                                Ok((alarms, warnings)) => {
+                                    // the alarms were already checked
+                                    let changes =
+                                        ics::changes(alarms, &config.alarms.data, get_uid);
+                                    log::info!(
+                                        "imported a calendar with {} changes and {} warnings",
+                                        changes.len(),
+                                        warnings.len()
+                                    );
+                                    if !changes.is_empty() {
+                                        apply_changes(&mut config, &mut ringing_alarms, &changes);
+                                        config.save(Config::config_path());
+                                        s.send(Alert::Batch(changes.clone()));
+                                    }
+                                    ServerResponce::CalendarImported(changes, warnings)
+                                }
+                                Err(error) => {
+                                    log::info!("not importing calendar: {error}");
+                                    ServerResponce::CalendarNotImported(error)
+                                }
+                            };
+                            let _ = reciever.send((request, responce));
+                        }
+                        ServerCommandKind::QueryHistory {
+                            since,
+                            until,
+                            alarm,
+                        } => {
+                            let entries = journal.as_ref().map_or_else(Vec::new, |journal| {
+                                journal
+                                    .query(since, until, alarm)
+                                    .inspect_err(|e| log::error!("couldn't read the history: {e}"))
+                                    .unwrap_or_default()
+                            });
+                            let _ = reciever.send((request, ServerResponce::History(entries)));
+                        }
+                        ServerCommandKind::RemoveSound(sound, removal) => {
+                            match config.sound_removal(&sound, removal) {
+                                Err(error) => {
+                                    let _ = reciever.send((
+                                        request,
+                                        ServerResponce::SoundNotRemoved(sound, error),
+                                    ));
+                                }
+                                Ok(edits) => {
+                                    // the alarms get moved off the sound before its removed so
+                                    // that the alarm thread never sees an alarm with a missing
+                                    // sound
+                                    for (id, alarm_edit) in &edits {
+                                        edit_alarm(
+                                            &mut config,
+                                            &mut ringing_alarms,
+                                            *id,
+                                            alarm_edit.clone(),
+                                        );
+                                    }
+                                    config.sounds.sounds.remove(&sound);
+                                    config.save(Config::config_path());
+                                    let mut affected: Vec<_> =
+                                        edits.iter().map(|(id, _)| *id).collect();
+                                    affected.dedup();
+                                    for (id, alarm_edit) in edits {
+                                        s.send(Alert::AlarmSet(id, alarm_edit));
+                                    }
+                                    s.send(Alert::SoundRemoved(sound, affected));
+                                }
+                            }
                         }
                     }
                 }
             }
-        });
-    }
+        })
+    };
     // problem with the crossbeam channel is that a message can only be read once (I think), so we
     // need an alert reciever for each client, and the main server thread will send to all these
     // recievers the alert, instead of the alert coming from the client thread that it got the
@@ -335,110 +1252,192 @@ fn main() -> std::io::Result<()> {
     // and also servercommands for any alert sent from the client
     // also from alarm thread will need connection to server thread to tell when alarm ringing
     // main problem is that crossbeam is not a broadcaster channel(and bus is to limited)
-    for conn in listener.incoming().filter_map(handle_error) {
+    let http = match args.http {
+        Some(http) => {
+            let listener = roosty_clockd::http::bind(http).inspect_err(|e| {
+                log::error!("couldn't start the HTTP API on {http}: {e}");
+            })?;
+            log::info!("serving the HTTP API on {http}");
+            Some(roosty_clockd::http::serve(
+                listener,
+                address,
+                shutdown.clone(),
+            ))
+        }
+        None => None,
+    };
+
+    let mut notifier = systemd::Notifier::from_env();
+    if let Some(notifier) = &notifier {
+        notifier.ready();
+    }
+    let mut client_threads = Vec::new();
+    let mut clients = 0_u64;
+    while !shutdown.load(Ordering::SeqCst) {
+        if let Some(notifier) = &mut notifier {
+            notifier.watchdog();
+        }
+        let Some(conn) = accept(&listener, &remote_listeners) else {
+            thread::sleep(time::Duration::from_millis(50));
+            continue;
+        };
+        clients += 1;
+        let client = clients;
+        log::info!("client {client} connected");
+        client_threads.retain(|thread: &thread::JoinHandle<()>| !thread.is_finished());
         // TODO: handle alerts from other threads, has to have access to writer
-        let (s, mut r) = (s.clone(), r.new_receiver());
+        let s = s.clone();
+        let (queue, events) = mpsc::sync_channel(Subscriber::CAPACITY);
+        let filter = Arc::new(Mutex::new(Filter::default()));
+        let behind = Arc::new(AtomicBool::new(false));
+        subscribers.lock().unwrap().push(Subscriber {
+            queue,
+            filter: filter.clone(),
+            behind: behind.clone(),
+        });
         let s_server = s_server.clone();
-        // let mut conn = BufReader::new(conn);
-        let (reader, mut writer) = conn.split();
+        let shutdown = shutdown.clone();
+        let token = token.clone();
+        let backoff = backoff.clone();
 
         let (s_client, r_client) = mpsc::channel();
-        thread::spawn(move || {
-            let mut reader = reader;
-            let mut buffer = Vec::new();
-            // Wrap the connection into a buffered receiver right away
-            // so that we could receive a single line from it.
-
-            // Since our client example sends first, the server should receive a line and only then
-            // send a response. Otherwise, because receiving from and sending to a connection cannot
-            // be simultaneous without threads or async, we can deadlock the two processes by having
-            // both sides wait for the send buffer to be emptied by the other.
-            loop {
-                if read(&mut reader, &mut buffer).is_ok()
-                    && let Ok(message) = { bitcode::deserialize(&buffer).map_err(|_| ()) }
-                {
-                    match message {
-                        ClientMessage::GetNewUID => {
-                            s_server
-                                .send(ServerCommand {
-                                    kind: ServerCommandKind::NewUID,
-                                    reciever: s_client.clone(),
-                                })
-                                .unwrap();
-                        }
-                        ClientMessage::Init => {
-                            s_server
-                                .send(ServerCommand {
-                                    kind: ServerCommandKind::Init,
-                                    reciever: s_client.clone(),
-                                })
-                                .unwrap();
-                        }
-                        ClientMessage::SetAlarm(alarm, alarm_edit) => {
-                            s.broadcast_blocking(Alert::AlarmSet(alarm, alarm_edit))
-                                .unwrap();
-                        }
-                        ClientMessage::AddAlarm(alarm) => {
-                            s.try_broadcast(Alert::AlaramAdded(alarm)).unwrap();
+        let thread = thread::Builder::new()
+            .name(format!("client {client}"))
+            .stack_size(roosty_clockd::CLIENT_STACK_SIZE)
+            .spawn(move || {
+                let encoding = match conn.handshake(token.as_deref(), &backoff) {
+                    Ok(encoding) => encoding,
+                    Err(e) => {
+                        log::warn!("client {client} sent an invalid handshake: {e}");
+                        return;
+                    }
+                };
+                log::debug!("client {client} uses {encoding}");
+                // only clients on this machine can stop the daemon
+                let local = matches!(conn, Incoming::Local(_));
+                let Ok((reader, mut writer)) = conn.split() else {
+                    return;
+                };
+                let mut reader = BufReader::new(reader);
+                let mut buffer = Vec::new();
+                // Wrap the connection into a buffered receiver right away
+                // so that we could receive a single line from it.
+
+                // Since our client example sends first, the server should receive a line and only then
+                // send a response. Otherwise, because receiving from and sending to a connection cannot
+                // be simultaneous without threads or async, we can deadlock the two processes by having
+                // both sides wait for the send buffer to be emptied by the other.
+                loop {
+                    let received = encoding.recieve(&mut reader, &mut buffer);
+                    // the client went away
+                    if matches!(&received, Err(e) if e.kind() == io::ErrorKind::UnexpectedEof) {
+                        log::info!("client {client} disconnected");
+                        return;
+                    }
+                    if let Err(e) = &received
+                        && e.kind() == io::ErrorKind::InvalidData
+                    {
+                        log::warn!("client {client} sent an invalid message: {e}");
+                        // with bitcode there is no telling where the next message starts
+                        if encoding == Encoding::Bitcode {
+                            log::info!("disconnecting client {client}");
+                            return;
                         }
-                        ClientMessage::RemoveAlarm(id) => {
-                            s.broadcast_blocking(Alert::AlarmRemoved(id)).unwrap();
+                    }
+                    if let Ok(message) = received {
+                        log::debug!("client {client} sent {message:?}");
+                        let (request, message) = match message {
+                            ClientMessage::Request(id, message) => (Some(id), *message),
+                            message => (None, message),
+                        };
+                        match message {
+                            ClientMessage::StopAlarm(i) => {
+                                s.send(Alert::AlarmStopped(i));
+                            }
+                            ClientMessage::Shutdown if local => {
+                                shutdown.store(true, Ordering::SeqCst);
+                            }
+                            ClientMessage::Shutdown => {
+                                log::warn!("remote client {client} tried to stop the daemon");
+                            }
+                            ClientMessage::Ping => {
+                                let _ = encoding.send(
+                                    &mut writer,
+                                    &ServerMessage::Pong(std::process::id()).respond_to(request),
+                                );
+                            }
+                            ClientMessage::Subscribe(new) => {
+                                log::debug!("client {client} subscribed to {new:?}");
+                                *filter.lock().unwrap() = new.clone();
+                                let _ = encoding.send(
+                                    &mut writer,
+                                    &ServerMessage::Subscribed(new).respond_to(request),
+                                );
+                            }
+                            ClientMessage::Request(..) => {
+                                log::warn!("client {client} sent a request inside a request");
+                            }
+                            message => {
+                                if let Some(kind) = command(message) {
+                                    // the config thread only stops when the daemon does
+                                    let _ = s_server.send(ServerCommand {
+                                        kind,
+                                        reciever: s_client.clone(),
+                                        request,
+                                    });
+                                }
+                            }
                         }
+                    }
+                    if let Ok((request, message)) = r_client.try_recv() {
+                        let message = ServerMessage::from(message);
+                        // if the client is gone, reading its next message notices
+                        let _ = encoding.send(&mut writer, &message.respond_to(request));
+                    }
 
-                        ClientMessage::AddedSounds(sounds) => {
-                            s.broadcast_blocking(Alert::SoundAdded(sounds)).unwrap();
+                    match events.try_recv() {
+                        Ok(ServerMessage::ShuttingDown) => {
+                            let _ = encoding.send(&mut writer, &ServerMessage::ShuttingDown);
+                            return;
                         }
-
-                        ClientMessage::RemoveSound(sound) => {
-                            s.broadcast_blocking(Alert::SoundRemoved(sound)).unwrap();
+                        Ok(message) => {
+                            let _ = encoding.send(&mut writer, &message);
                         }
-                        ClientMessage::StopAlarm(i) => {
-                            s.broadcast_blocking(Alert::AlarmStopped(i)).unwrap();
+                        // caught up, so now it can start over
+                        Err(mpsc::TryRecvError::Empty) if behind.swap(false, Ordering::SeqCst) => {
+                            log::warn!("client {client} fell behind, it has to resync");
+                            let _ = encoding.send(&mut writer, &ServerMessage::ResyncRequired);
                         }
+                        Err(_) => {}
                     }
-                }
-                if let Ok(message) = r_client.try_recv() {
-                    let message = match message {
-                        ServerResponce::NewUID(id) => ServerMessage::UID(id),
-                        ServerResponce::Init {
-                            alarms,
-                            sounds,
-                            ringing_alarms,
-                        } => ServerMessage::Init {
-                            alarms,
-                            sounds,
-                            ringing_alarms,
-                        },
-                    };
-                    let message = bitcode::serialize(&message).unwrap();
-                    roosty_clockd::write(&mut writer, &message);
-                }
+                    // Now that the receive has come through and the client is waiting on the server's send, do
+                    // it. (`.get_mut()` is to get the sender, `BufReader` doesn't implement a pass-through
+                    // `Write`.)
 
-                if let Ok(message) = r.try_recv() {
-                    let message = match message {
-                        Alert::AlarmSet(id, alarm_edit) => ServerMessage::AlarmSet(id, alarm_edit),
-                        Alert::AlaramAdded(alarm) => ServerMessage::AlaramAdded(alarm),
-                        Alert::AlarmRemoved(id) => ServerMessage::AlarmRemoved(id),
-                        Alert::SoundAdded(sound) => ServerMessage::SoundsAdded(sound),
-                        Alert::SoundRemoved(sound) => ServerMessage::SoundRemoved(sound),
-                        Alert::AlarmRinging(id) => ServerMessage::AlarmRinging(id),
-                        Alert::AlarmStopped(id) => ServerMessage::AlarmStopped(id),
-                    };
-                    let message = bitcode::serialize(&message).unwrap();
-                    roosty_clockd::write(&mut writer, &message);
+                    // Print out the result, getting the newline for free!
                 }
-                // Now that the receive has come through and the client is waiting on the server's send, do
-                // it. (`.get_mut()` is to get the sender, `BufReader` doesn't implement a pass-through
-                // `Write`.)
-
-                // Print out the result, getting the newline for free!
+            });
+        match thread {
+            Ok(thread) => client_threads.push(thread),
+            Err(e) => log::error!("couldn't start a thread for client {client}: {e}"),
+        }
+    }
 
-                // Clear the buffer so that the next iteration will display new data instead of messages
-                // stacking on top of one another.
-                buffer.clear();
-            }
-        });
+    log::info!("shutting down");
+    if let Some(notifier) = &notifier {
+        notifier.stopping();
+    }
+    // stop ringing, tell the clients, and save the config
+    s.send(Alert::ShuttingDown);
+    for thread in client_threads {
+        let _ = thread.join();
+    }
+    if let Some(http) = http {
+        let _ = http.join();
     }
+    let _ = dispatcher.join();
+    let _ = config_thread.join();
+    let _ = audio_thread.join();
 
     Ok(())
 }
@@ -0,0 +1,302 @@
+//! controlling the daemon from another machine over TCP
+//!
+//! It's off unless the daemon is given addresses to listen on (`--listen` or `remote.listen` in
+//! the config) and a token (`remote.token` in the config or [`TOKEN_ENV`]). A remote client
+//! starts with a line with the token (`token <token>\n`), and then it's the same as over the
+//! local socket (see [`crate::encoding`]), except that it can't stop the daemon. If the token is
+//! wrong the connection is closed, and every wrong token makes the daemon wait longer before
+//! checking the next one from the same address (see [`Backoff`]).
+//!
+//! TLS is out of scope: the daemon doesn't do it and won't, the token and everything after it is
+//! sent in the clear. Outside a trusted network tunnel it (over ssh, or with stunnel and a local
+//! certificate), the daemon warns about addresses that aren't loopback.
+use std::{
+    collections::HashMap,
+    env, fmt,
+    io::{self, Read, Write},
+    net::{IpAddr, SocketAddr, TcpStream, ToSocketAddrs},
+    sync::{Mutex, MutexGuard, PoisonError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// environment variable with the token, takes precedence over the config
+pub const TOKEN_ENV: &str = "ROOSTY_CLOCKD_TOKEN";
+
+pub const DEFAULT_PORT: u16 = 7436;
+
+/// how long connecting to a remote daemon can take
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// the longest token line that is read, so a client can't make the daemon read forever
+const MAX_LINE: usize = 256;
+
+/// how long checking the token waits after the first wrong one, it doubles with every one after
+const BACKOFF: Duration = Duration::from_millis(100);
+
+/// the longest wait after a wrong token
+const MAX_BACKOFF: Duration = Duration::from_secs(10);
+
+/// the `[remote]` section of the daemons config
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct Config {
+    /// the addresses to listen on, for example `["0.0.0.0:7436"]`
+    pub listen: Vec<SocketAddr>,
+    /// what clients have to send to be let in, overridden by [`TOKEN_ENV`]
+    pub token: Option<String>,
+}
+
+impl Config {
+    /// the token from [`TOKEN_ENV`] or the config, if there is one that isn't empty
+    #[must_use]
+    pub fn token(&self) -> Option<String> {
+        env::var(TOKEN_ENV)
+            .ok()
+            .or_else(|| self.token.clone())
+            .filter(|token| !token.is_empty())
+    }
+}
+
+/// A daemon on another machine.
+#[derive(Clone, PartialEq, Eq)]
+pub struct Remote {
+    /// `host:port` (or just `host` for the [`DEFAULT_PORT`])
+    pub host: String,
+    token: String,
+}
+
+impl Remote {
+    #[must_use]
+    pub fn new(host: &str, token: String) -> Self {
+        // a bare ipv6 address has colons in it too, but no brackets
+        let has_port = host.rsplit_once(':').is_some_and(|(host, port)| {
+            port.parse::<u16>().is_ok() && (!host.contains(':') || host.ends_with(']'))
+        });
+        let host = if has_port {
+            host.to_string()
+        } else if host.contains(':') {
+            format!("[{host}]:{DEFAULT_PORT}")
+        } else {
+            format!("{host}:{DEFAULT_PORT}")
+        };
+        Self { host, token }
+    }
+
+    /// Connects to the daemon and sends the token.
+    ///
+    /// # Errors
+    /// If the host can't be found or nothing is listening on it.
+    pub fn connect(&self) -> io::Result<TcpStream> {
+        let mut last = io::Error::new(io::ErrorKind::NotFound, "the host has no addresses");
+        for address in self.host.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&address, CONNECT_TIMEOUT) {
+                Ok(mut stream) => {
+                    stream.set_nodelay(true)?;
+                    send_token(&mut stream, &self.token)?;
+                    return Ok(stream);
+                }
+                Err(e) => last = e,
+            }
+        }
+        Err(last)
+    }
+}
+
+// so the token doesn't end up in logs
+impl fmt::Debug for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Remote")
+            .field("host", &self.host)
+            .finish_non_exhaustive()
+    }
+}
+
+impl fmt::Display for Remote {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.host)
+    }
+}
+
+/// Sends the line with the token, before the [`Encoding`](crate::encoding::Encoding) handshake.
+///
+/// # Errors
+/// If the token couldn't be written.
+pub fn send_token(w: &mut impl Write, token: &str) -> io::Result<()> {
+    w.write_all(format!("token {token}\n").as_bytes())
+}
+
+/// Slows down guessing the token, shared by every remote client.
+///
+/// After a wrong token from an address, tokens from it are checked one at a time, each waiting
+/// longer and longer (up to [`MAX_BACKOFF`]) until a right one comes in. Other addresses don't
+/// have to wait.
+#[derive(Debug, Default)]
+pub struct Backoff {
+    /// how many wrong tokens each address sent since its last right one, and when its next one
+    /// can be checked
+    peers: Mutex<HashMap<IpAddr, (u32, Instant)>>,
+}
+
+impl Backoff {
+    fn check(&self, sent: &[u8], token: &str, peer: IpAddr) -> io::Result<()> {
+        // the turn is taken before waiting, so guesses over many connections wait one after
+        // another, and the lock isn't held while waiting so other connections can go on
+        let turn = self.peers().get_mut(&peer).map(|(failures, next)| {
+            let turn = (*next).max(Instant::now());
+            *next = turn + wait(*failures);
+            turn
+        });
+        if let Some(turn) = turn {
+            thread::sleep(turn.saturating_duration_since(Instant::now()));
+        }
+        let mut peers = self.peers();
+        if same(sent, token.as_bytes()) {
+            peers.remove(&peer);
+            return Ok(());
+        }
+        let (failures, next) = peers.entry(peer).or_insert((0, Instant::now()));
+        *next = (*next).max(Instant::now() + wait(*failures));
+        *failures = failures.saturating_add(1);
+        Err(io::Error::new(
+            io::ErrorKind::PermissionDenied,
+            "wrong token",
+        ))
+    }
+
+    fn peers(&self) -> MutexGuard<'_, HashMap<IpAddr, (u32, Instant)>> {
+        self.peers.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+}
+
+/// how long to wait before checking a token after `failures` wrong ones
+fn wait(failures: u32) -> Duration {
+    BACKOFF
+        .saturating_mul(1 << failures.min(16))
+        .min(MAX_BACKOFF)
+}
+
+/// Reads the line with the token from `peer` and checks it, waiting for a while first if `peer`
+/// sent wrong ones before.
+///
+/// # Errors
+/// If the token is wrong ([`io::ErrorKind::PermissionDenied`]) or the line isn't one.
+pub fn accept_token(
+    r: &mut impl Read,
+    token: &str,
+    peer: IpAddr,
+    backoff: &Backoff,
+) -> io::Result<()> {
+    // byte by byte so that nothing after the line is read
+    let mut line = Vec::new();
+    let mut byte = [0];
+    while line.len() < MAX_LINE {
+        r.read_exact(&mut byte)?;
+        if byte[0] == b'\n' {
+            let sent = line
+                .strip_prefix(b"token ")
+                .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "expected a token"))?;
+            return backoff.check(sent, token, peer);
+        }
+        line.push(byte[0]);
+    }
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        "the token line is too long",
+    ))
+}
+
+/// compares all of both so how long it takes doesn't say how much of the token was right
+fn same(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |diff, (a, b)| diff | (a ^ b)) == 0
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    const PEER: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 168, 1, 2));
+
+    #[test]
+    fn adds_the_default_port() {
+        assert_eq!(Remote::new("clock", String::new()).host, "clock:7436");
+        assert_eq!(Remote::new("clock:80", String::new()).host, "clock:80");
+        assert_eq!(Remote::new("::1", String::new()).host, "[::1]:7436");
+        assert_eq!(Remote::new("[::1]:80", String::new()).host, "[::1]:80");
+    }
+
+    #[test]
+    fn checks_the_token() {
+        let mut sent = Vec::new();
+        send_token(&mut sent, "secret").unwrap();
+        sent.extend_from_slice(b"bitcode\n");
+        let backoff = Backoff::default();
+        let mut r = &sent[..];
+        accept_token(&mut r, "secret", PEER, &backoff).unwrap();
+        // the handshake is still there
+        assert_eq!(r, b"bitcode\n");
+        let error = accept_token(&mut &sent[..], "secrets", PEER, &backoff).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::PermissionDenied);
+        let error = accept_token(&mut &b"bitcode\n"[..], "secret", PEER, &backoff).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+
+    fn guess(backoff: &Backoff, token: &str, peer: IpAddr) -> (bool, Duration) {
+        let start = Instant::now();
+        let result = accept_token(
+            &mut format!("token {token}\n").as_bytes(),
+            "secret",
+            peer,
+            backoff,
+        );
+        (result.is_ok(), start.elapsed())
+    }
+
+    #[test]
+    fn wrong_tokens_slow_down() {
+        let backoff = Backoff::default();
+        // the waits start when the last token was checked, so they look a little shorter
+        let (ok, first) = guess(&backoff, "guess", PEER);
+        assert!(!ok);
+        assert!(first < BACKOFF);
+        let (ok, second) = guess(&backoff, "guess", PEER);
+        assert!(!ok);
+        assert!(second > BACKOFF / 2);
+        let (ok, third) = guess(&backoff, "guess", PEER);
+        assert!(!ok);
+        assert!(third > BACKOFF * 3 / 2);
+        // the right token has to wait its turn too, but resets the wait
+        let (ok, right) = guess(&backoff, "secret", PEER);
+        assert!(ok);
+        assert!(right > BACKOFF * 3);
+        assert!(backoff.peers().is_empty());
+        let (ok, right) = guess(&backoff, "secret", PEER);
+        assert!(ok);
+        assert!(right < BACKOFF);
+    }
+
+    #[test]
+    fn other_addresses_dont_wait() {
+        let backoff = Backoff::default();
+        for _ in 0..3 {
+            guess(&backoff, "guess", PEER);
+        }
+        let other = IpAddr::from([192, 168, 1, 3]);
+        thread::scope(|scope| {
+            // waiting for its turn doesn't hold up anyone else
+            let waiting = scope.spawn(|| guess(&backoff, "guess", PEER));
+            thread::sleep(BACKOFF / 2);
+            let (ok, wrong) = guess(&backoff, "guess", other);
+            assert!(!ok);
+            assert!(wrong < BACKOFF);
+            let (ok, right) = guess(&backoff, "secret", other);
+            assert!(ok);
+            assert!(right > BACKOFF / 2);
+            assert!(waiting.join().unwrap().1 > BACKOFF * 3);
+        });
+    }
+}
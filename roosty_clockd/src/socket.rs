@@ -0,0 +1,173 @@
+//! where the daemon listens, this is shared by the daemon and its clients so they always agree
+use std::{env, fmt, io, path::PathBuf};
+
+use interprocess::local_socket::{
+    GenericFilePath, GenericNamespaced, Listener, ListenerOptions, Name, Stream, prelude::*,
+};
+
+/// environment variable to override the socket, takes precedence over the default but not over
+/// a command line flag
+pub const SOCKET_ENV: &str = "ROOSTY_CLOCKD_SOCKET";
+
+pub const DEFAULT_NAME: &str = "roosty-clockd.sock";
+
+/// The address of the daemons socket.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Address {
+    /// an abstract/namespaced socket (or named pipe on windows)
+    Namespaced(String),
+    /// a socket file
+    Path(PathBuf),
+}
+
+impl Address {
+    /// Figures out where the socket is.
+    ///
+    /// `name` (from a command line flag) is used if given, then [`SOCKET_ENV`], then
+    /// [`DEFAULT_NAME`].
+    /// Names with a path separator in them are always socket files, other names are namespaced if
+    /// the platform supports it, otherwise they are put in `$XDG_RUNTIME_DIR` (or the temp dir).
+    #[must_use]
+    pub fn resolve(name: Option<&str>) -> Self {
+        Self::resolve_with(name, env::var(SOCKET_ENV).ok())
+    }
+
+    // split out so the tests don't have to set the environment variable
+    fn resolve_with(name: Option<&str>, env: Option<String>) -> Self {
+        let name = name
+            .map(ToString::to_string)
+            .or(env)
+            .unwrap_or_else(|| DEFAULT_NAME.to_string());
+        if name.contains(std::path::is_separator) {
+            Self::Path(PathBuf::from(name))
+        } else if GenericNamespaced::is_supported() {
+            Self::Namespaced(name)
+        } else {
+            Self::Path(runtime_dir().join(name))
+        }
+    }
+
+    /// # Errors
+    /// If the name is not valid for the platform.
+    pub fn name(&self) -> io::Result<Name<'_>> {
+        match self {
+            Self::Namespaced(name) => name.as_str().to_ns_name::<GenericNamespaced>(),
+            Self::Path(path) => path.as_path().to_fs_name::<GenericFilePath>(),
+        }
+    }
+
+    /// Creates a listener on the address, socket files are only accessible by the current user.
+    ///
+    /// # Errors
+    /// If the socket is in use or could not be created.
+    pub fn listen(&self) -> io::Result<Listener> {
+        let options = ListenerOptions::new().name(self.name()?);
+        #[cfg(unix)]
+        let options = if let Self::Path(path) = self {
+            use interprocess::os::unix::local_socket::ListenerOptionsExt;
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            options.mode(0o600)
+        } else {
+            options
+        };
+        options.create_sync()
+    }
+
+    /// Where the daemon listening on this address keeps its lock file (with its PID in it).
+    #[must_use]
+    pub fn lock_path(&self) -> PathBuf {
+        match self {
+            Self::Namespaced(name) => runtime_dir().join(format!("{name}.pid")),
+            Self::Path(path) => {
+                let mut path = path.clone().into_os_string();
+                path.push(".pid");
+                PathBuf::from(path)
+            }
+        }
+    }
+
+    /// Connects to the daemon at this address.
+    ///
+    /// # Errors
+    /// If nothing is listening on the address.
+    pub fn connect(&self) -> io::Result<Stream> {
+        Stream::connect(self.name()?)
+    }
+}
+
+impl Default for Address {
+    fn default() -> Self {
+        Self::resolve(None)
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Namespaced(name) => write!(f, "{name}"),
+            Self::Path(path) => write!(f, "{}", path.display()),
+        }
+    }
+}
+
+fn runtime_dir() -> PathBuf {
+    env::var_os("XDG_RUNTIME_DIR").map_or_else(env::temp_dir, PathBuf::from)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// where a name without a path separator ends up on this platform
+    fn plain(name: &str) -> Address {
+        if GenericNamespaced::is_supported() {
+            Address::Namespaced(name.to_string())
+        } else {
+            Address::Path(runtime_dir().join(name))
+        }
+    }
+
+    #[test]
+    fn the_default_name_is_used_without_overrides() {
+        assert_eq!(Address::resolve_with(None, None), plain(DEFAULT_NAME));
+    }
+
+    #[test]
+    fn flags_take_precedence_over_the_environment() {
+        let env = Some("from-env.sock".to_string());
+        assert_eq!(
+            Address::resolve_with(None, env.clone()),
+            plain("from-env.sock")
+        );
+        assert_eq!(
+            Address::resolve_with(Some("from-flag.sock"), env),
+            plain("from-flag.sock")
+        );
+        assert_eq!(
+            Address::resolve_with(Some("from-flag.sock"), None),
+            plain("from-flag.sock")
+        );
+    }
+
+    #[test]
+    fn names_with_separators_are_socket_files() {
+        let path = env::temp_dir().join("roosty").join("clockd.sock");
+        let address = Address::resolve_with(path.to_str(), Some("from-env.sock".to_string()));
+        assert_eq!(address, Address::Path(path.clone()));
+        assert_eq!(
+            address.lock_path(),
+            env::temp_dir().join("roosty").join("clockd.sock.pid")
+        );
+        let address = Address::resolve_with(None, path.to_str().map(ToString::to_string));
+        assert_eq!(address, Address::Path(path));
+    }
+
+    #[test]
+    fn namespaced_sockets_keep_their_lock_in_the_runtime_dir() {
+        let address = Address::Namespaced("clockd.sock".to_string());
+        assert_eq!(address.lock_path(), runtime_dir().join("clockd.sock.pid"));
+        assert_eq!(address.to_string(), "clockd.sock");
+    }
+}
@@ -0,0 +1,327 @@
+//! turning [`config::Sound`]s into something rodio can play
+use std::{
+    collections::HashMap, f32::consts::TAU, fs::File, io::BufReader, num::NonZero, time::Duration,
+};
+
+use chrono::NaiveDate;
+use rodio::{ChannelCount, Decoder, Sample, SampleRate, Source};
+
+use crate::config::{self, NoiseColor, SoundKind, Waveform};
+
+/// How long a synthesized sound plays for in a playlist (they would go on forever otherwise).
+pub const PLAYLIST_SYNTH_LENGTH: Duration = Duration::from_secs(30);
+
+/// Opens a sound for playback, repeating it forever.
+///
+/// If a file sound cannot be opened or decoded a tone is used instead, so an alarm always makes
+/// some noise.
+#[must_use]
+pub fn open(sound: &config::Sound) -> Box<dyn Source + Send> {
+    open_with(sound, true)
+}
+
+/// Opens everything `alarm` should play on `date` (see [`config::Alarm::sounds_for`]), in order.
+///
+/// A single sound repeats forever, while each sound of a playlist is only played once.
+#[must_use]
+pub fn open_alarm(
+    alarm: &config::Alarm,
+    sounds: &HashMap<String, config::Sound>,
+    date: NaiveDate,
+) -> Vec<Box<dyn Source + Send>> {
+    let names = alarm.sounds_for(date);
+    let repeat = names.len() == 1;
+    names
+        .into_iter()
+        .map(|name| match sounds.get(name) {
+            Some(sound) => open_with(sound, repeat),
+            None => {
+                log::warn!("alarm {} uses missing sound {name}", alarm.id);
+                fallback(repeat)
+            }
+        })
+        .collect()
+}
+
+fn open_with(sound: &config::Sound, repeat: bool) -> Box<dyn Source + Send> {
+    if sound.kind != SoundKind::File {
+        return synth(
+            Synth::new(sound.kind, sound.synth.unwrap_or_default()),
+            repeat,
+        );
+    }
+    match File::open(&sound.path)
+        .map_err(|e| e.to_string())
+        .and_then(|file| Decoder::new(BufReader::new(file)).map_err(|e| e.to_string()))
+    {
+        Ok(decoder) if repeat => Box::new(decoder.repeat_infinite()),
+        Ok(decoder) => Box::new(decoder),
+        Err(e) => {
+            log::error!(
+                "couldn't play {} ({}), falling back to a tone: {e}",
+                sound.name,
+                sound.path.display()
+            );
+            fallback(repeat)
+        }
+    }
+}
+
+fn synth(synth: Synth, repeat: bool) -> Box<dyn Source + Send> {
+    if repeat {
+        Box::new(synth)
+    } else {
+        Box::new(synth.take_duration(PLAYLIST_SYNTH_LENGTH))
+    }
+}
+
+fn fallback(repeat: bool) -> Box<dyn Source + Send> {
+    synth(Synth::fallback(), repeat)
+}
+
+/// An infinite procedurally generated sound.
+#[derive(Debug, Clone)]
+pub struct Synth {
+    kind: SoundKind,
+    settings: config::Synth,
+    // how many samples have been generated so far
+    sample: u64,
+    // where in its cycle the wave is (0 to 1), kept small so it doesn't lose precision over time
+    phase: f32,
+    // state of the noise generator (xorshift) and filters
+    rng: u64,
+    pink: [f32; 3],
+    brown: f32,
+}
+
+impl Synth {
+    const SAMPLE_RATE: u32 = 44_100;
+
+    #[must_use]
+    pub const fn new(kind: SoundKind, settings: config::Synth) -> Self {
+        Self {
+            kind,
+            settings,
+            sample: 0,
+            phase: 0.,
+            rng: 0x2545_f491_4f6c_dd1d,
+            pink: [0.; 3],
+            brown: 0.,
+        }
+    }
+
+    /// the sound used when a file sound fails
+    #[must_use]
+    pub fn fallback() -> Self {
+        Self::new(
+            SoundKind::Tone,
+            config::Synth {
+                waveform: Waveform::Square,
+                ..config::Synth::default()
+            },
+        )
+    }
+
+    fn white(&mut self) -> f32 {
+        self.rng ^= self.rng << 13;
+        self.rng ^= self.rng >> 7;
+        self.rng ^= self.rng << 17;
+        // top 24 bits are exactly representable as a f32
+        #[allow(clippy::cast_precision_loss)]
+        let unit = (self.rng >> 40) as f32 / (1 << 24) as f32;
+        unit.mul_add(2., -1.)
+    }
+
+    /// the next sample of a wave at `pitch`
+    fn wave(&mut self, waveform: Waveform, pitch: f32) -> f32 {
+        let phase = self.phase;
+        self.phase = (phase + pitch / Self::SAMPLE_RATE as f32).fract();
+        match waveform {
+            Waveform::Sine => (phase * TAU).sin(),
+            // square waves are a lot louder than sine waves
+            Waveform::Square => {
+                if phase < 0.5 {
+                    0.6
+                } else {
+                    -0.6
+                }
+            }
+        }
+    }
+}
+
+impl Iterator for Synth {
+    type Item = Sample;
+
+    fn next(&mut self) -> Option<Sample> {
+        let config::Synth {
+            waveform,
+            pitch,
+            second_pitch,
+            on_ms,
+            off_ms,
+            noise,
+        } = self.settings;
+        let rate = u64::from(Self::SAMPLE_RATE);
+        // position in the current beep/pause cycle
+        let cycle = (u64::from(on_ms) + u64::from(off_ms)).max(1) * rate / 1000;
+        let position = self.sample % cycle;
+        let on = position < u64::from(on_ms) * rate / 1000;
+        self.sample += 1;
+
+        let value = match self.kind {
+            _ if !on => 0.,
+            SoundKind::File | SoundKind::Tone => self.wave(waveform, pitch),
+            SoundKind::Chime => {
+                // each beep is split into a high and low part, that both fade out
+                let half = (u64::from(on_ms) * rate / 2000).max(1);
+                let (pitch, start) = if position < half {
+                    (pitch, 0)
+                } else {
+                    (second_pitch, half)
+                };
+                #[allow(clippy::cast_precision_loss)]
+                let fade = 1. - (position - start) as f32 / half as f32;
+                self.wave(waveform, pitch) * fade.max(0.)
+            }
+            SoundKind::Noise => {
+                let white = self.white();
+                let noise = match noise {
+                    NoiseColor::White => white * 0.5,
+                    // https://www.firstpr.com.au/dsp/pink-noise/ (economy version)
+                    NoiseColor::Pink => {
+                        self.pink[0] = 0.99765f32.mul_add(self.pink[0], white * 0.099_046);
+                        self.pink[1] = 0.963f32.mul_add(self.pink[1], white * 0.296_516_4);
+                        self.pink[2] = 0.57f32.mul_add(self.pink[2], white * 1.052_691_3);
+                        white.mul_add(0.1848, self.pink.iter().sum::<f32>()) * 0.12
+                    }
+                    NoiseColor::Brown => {
+                        self.brown = 0.02f32.mul_add(white, self.brown) / 1.02;
+                        self.brown * 3.5
+                    }
+                };
+                // the filters aren't bounded, so keep the rare peaks from clipping
+                noise.clamp(-1., 1.)
+            }
+        };
+        Some(value)
+    }
+}
+
+impl Source for Synth {
+    fn current_span_len(&self) -> Option<usize> {
+        None
+    }
+
+    fn channels(&self) -> ChannelCount {
+        NonZero::<u16>::MIN
+    }
+
+    fn sample_rate(&self) -> SampleRate {
+        NonZero::new(Self::SAMPLE_RATE).unwrap_or(NonZero::<u32>::MIN)
+    }
+
+    fn total_duration(&self) -> Option<Duration> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn samples(kind: SoundKind, settings: config::Synth, seconds: f32) -> Vec<f32> {
+        #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+        let count = (seconds * Synth::SAMPLE_RATE as f32) as usize;
+        Synth::new(kind, settings).take(count).collect()
+    }
+
+    fn crossings(samples: &[f32]) -> usize {
+        samples
+            .windows(2)
+            .filter(|pair| (pair[0] < 0.) != (pair[1] < 0.))
+            .count()
+    }
+
+    #[test]
+    fn tones_beep_then_pause() {
+        let settings = config::Synth {
+            waveform: Waveform::Square,
+            ..config::Synth::default()
+        };
+        let samples = samples(SoundKind::Tone, settings, 1.);
+        // 250ms on, 250ms off
+        let beep = 44_100 / 4;
+        for cycle in samples.chunks(beep * 2) {
+            let (on, off) = cycle.split_at(beep);
+            assert!(on.iter().all(|sample| sample.abs() == 0.6));
+            assert!(off.iter().all(|&sample| sample == 0.));
+        }
+        // 880hz for a quarter of a second
+        let changes = crossings(&samples[..beep]);
+        assert!((438..=442).contains(&changes), "{changes}");
+    }
+
+    #[test]
+    fn tones_stay_in_tune_after_hours() {
+        let mut synth = Synth::new(SoundKind::Tone, config::Synth::default());
+        synth.sample = 10 * 3600 * 44_100;
+        let samples: Vec<_> = synth.take(44_100 / 4).collect();
+        let changes = crossings(&samples);
+        assert!((438..=442).contains(&changes), "{changes}");
+    }
+
+    #[test]
+    fn tones_without_pauses_are_continuous() {
+        let settings = config::Synth {
+            off_ms: 0,
+            ..config::Synth::default()
+        };
+        let samples = samples(SoundKind::Tone, settings, 1.);
+        assert!(samples.iter().all(|sample| (-1.0..=1.).contains(sample)));
+        let changes = crossings(&samples);
+        assert!((1758..=1762).contains(&changes), "{changes}");
+    }
+
+    #[test]
+    fn chimes_go_down_and_fade() {
+        let samples = samples(SoundKind::Chime, config::Synth::default(), 0.25);
+        let (high, low) = samples.split_at(samples.len() / 2);
+        // 880hz then 660hz for an eighth of a second each
+        let changes = crossings(high);
+        assert!((218..=222).contains(&changes), "{changes}");
+        let changes = crossings(low);
+        assert!((163..=167).contains(&changes), "{changes}");
+        for part in [high, low] {
+            let loudest = |samples: &[f32]| samples.iter().map(|s| s.abs()).fold(0., f32::max);
+            let (start, end) = part.split_at(part.len() / 2);
+            assert!(loudest(start) > 0.9);
+            assert!(loudest(end) <= 0.5);
+        }
+    }
+
+    #[test]
+    fn noise_stays_in_range() {
+        let noise = |noise| {
+            let settings = config::Synth {
+                off_ms: 0,
+                noise,
+                ..config::Synth::default()
+            };
+            samples(SoundKind::Noise, settings, 1.)
+        };
+        let white = noise(NoiseColor::White);
+        assert!(white.iter().all(|sample| (-0.5..=0.5).contains(sample)));
+        #[allow(clippy::cast_precision_loss)]
+        let mean = white.iter().sum::<f32>() / white.len() as f32;
+        assert!(mean.abs() < 0.01, "{mean}");
+        for color in [NoiseColor::Pink, NoiseColor::Brown] {
+            let samples = noise(color);
+            assert!(samples.iter().all(|sample| (-1.0..=1.).contains(sample)));
+            assert!(samples.iter().any(|&sample| sample != 0.));
+            // darker noise has less high frequencies, so changes sign less often
+            assert!(crossings(&samples) < crossings(&white), "{color:?}");
+        }
+        assert!(crossings(&noise(NoiseColor::Brown)) * 5 < crossings(&white));
+    }
+}
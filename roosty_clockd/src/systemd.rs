@@ -0,0 +1,369 @@
+//! running as a systemd user service, with socket activation and `sd_notify`
+//!
+//! none of this does anything when not started by systemd (or on platforms without it)
+use std::{
+    env, fmt, io,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
+
+use interprocess::local_socket::Listener;
+
+use crate::socket::Address;
+
+pub const SERVICE_NAME: &str = "roosty_clockd.service";
+pub const SOCKET_NAME: &str = "roosty_clockd.socket";
+
+/// the first fd passed by systemd, `SD_LISTEN_FDS_START`
+#[cfg(unix)]
+const LISTEN_FDS_START: std::os::fd::RawFd = 3;
+
+/// The listening socket systemd passed us with `LISTEN_FDS`, if any.
+#[must_use]
+pub fn listener() -> Option<Listener> {
+    let fd = inherited_fd(
+        env::var("LISTEN_PID").ok().as_deref(),
+        env::var("LISTEN_FDS").ok().as_deref(),
+        std::process::id(),
+    )?;
+    #[cfg(unix)]
+    {
+        use std::os::fd::{FromRawFd, OwnedFd};
+        // SAFETY: systemd hands this fd to us (it checked out with `LISTEN_PID`) and nothing
+        // else takes ownership of it
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+        Some(interprocess::os::unix::uds_local_socket::Listener::from(fd).into())
+    }
+    #[cfg(not(unix))]
+    {
+        let _ = fd;
+        None
+    }
+}
+
+/// Which fd to listen on, given `LISTEN_PID` and `LISTEN_FDS`.
+///
+/// The variables are only meant for `pid`, otherwise they were inherited from somewhere else.
+/// If systemd passed more than one socket only the first is used.
+#[cfg(unix)]
+fn inherited_fd(
+    listen_pid: Option<&str>,
+    listen_fds: Option<&str>,
+    pid: u32,
+) -> Option<std::os::fd::RawFd> {
+    if listen_pid?.parse::<u32>().ok()? != pid {
+        return None;
+    }
+    (listen_fds?.parse::<u32>().ok()? >= 1).then_some(LISTEN_FDS_START)
+}
+
+#[cfg(not(unix))]
+const fn inherited_fd(_: Option<&str>, _: Option<&str>, _: u32) -> Option<()> {
+    None
+}
+
+/// Sends status updates to systemd over `NOTIFY_SOCKET`.
+#[derive(Debug)]
+pub struct Notifier {
+    #[cfg(unix)]
+    socket: std::os::unix::net::UnixDatagram,
+    #[cfg(unix)]
+    address: std::os::unix::net::SocketAddr,
+    /// how often to ping the watchdog (half of `WATCHDOG_USEC`)
+    watchdog: Option<Duration>,
+    last_watchdog: Instant,
+}
+
+impl Notifier {
+    /// A notifier for the socket systemd gave us, if we were started by systemd.
+    #[must_use]
+    pub fn from_env() -> Option<Self> {
+        let path = env::var("NOTIFY_SOCKET").ok()?;
+        let watchdog = watchdog_interval(
+            env::var("WATCHDOG_USEC").ok().as_deref(),
+            env::var("WATCHDOG_PID").ok().as_deref(),
+            std::process::id(),
+        );
+        match Self::new(&path, watchdog) {
+            Ok(notifier) => Some(notifier),
+            Err(e) => {
+                log::warn!("couldn't connect to NOTIFY_SOCKET {path}: {e}");
+                None
+            }
+        }
+    }
+
+    /// A notifier for the socket at `path`, which is abstract if it starts with `@`.
+    ///
+    /// # Errors
+    /// If the path is not a valid socket address.
+    #[cfg(unix)]
+    pub fn new(path: &str, watchdog: Option<Duration>) -> io::Result<Self> {
+        use std::os::unix::net::{SocketAddr, UnixDatagram};
+        let address = match path.strip_prefix('@') {
+            #[cfg(any(target_os = "linux", target_os = "android"))]
+            Some(name) => {
+                #[cfg(target_os = "android")]
+                use std::os::android::net::SocketAddrExt;
+                #[cfg(target_os = "linux")]
+                use std::os::linux::net::SocketAddrExt;
+                SocketAddr::from_abstract_name(name)?
+            }
+            _ => SocketAddr::from_pathname(path)?,
+        };
+        Ok(Self {
+            socket: UnixDatagram::unbound()?,
+            address,
+            watchdog,
+            last_watchdog: Instant::now(),
+        })
+    }
+
+    /// # Errors
+    /// Always, systemd only exists on unix.
+    #[cfg(not(unix))]
+    pub fn new(_: &str, _: Option<Duration>) -> io::Result<Self> {
+        Err(io::ErrorKind::Unsupported.into())
+    }
+
+    /// # Errors
+    /// If the message couldn't be sent.
+    pub fn notify(&self, state: &str) -> io::Result<()> {
+        #[cfg(unix)]
+        {
+            self.socket
+                .send_to_addr(state.as_bytes(), &self.address)
+                .map(|_| ())
+        }
+        #[cfg(not(unix))]
+        {
+            let _ = state;
+            Ok(())
+        }
+    }
+
+    /// Tells systemd we are done starting up.
+    pub fn ready(&self) {
+        if let Err(e) = self.notify("READY=1") {
+            log::warn!("couldn't notify systemd that we are ready: {e}");
+        }
+    }
+
+    pub fn stopping(&self) {
+        if let Err(e) = self.notify("STOPPING=1") {
+            log::warn!("couldn't notify systemd that we are stopping: {e}");
+        }
+    }
+
+    /// Pings the watchdog, if its enabled and it's time to.
+    pub fn watchdog(&mut self) {
+        let Some(interval) = self.watchdog else {
+            return;
+        };
+        if self.last_watchdog.elapsed() >= interval {
+            self.last_watchdog = Instant::now();
+            if let Err(e) = self.notify("WATCHDOG=1") {
+                log::warn!("couldn't ping the systemd watchdog: {e}");
+            }
+        }
+    }
+}
+
+/// How often to ping the watchdog given `WATCHDOG_USEC` and `WATCHDOG_PID`, systemd recommends
+/// twice per timeout.
+fn watchdog_interval(usec: Option<&str>, watchdog_pid: Option<&str>, pid: u32) -> Option<Duration> {
+    if let Some(watchdog_pid) = watchdog_pid
+        && watchdog_pid.parse::<u32>().ok()? != pid
+    {
+        return None;
+    }
+    let usec = usec?.parse::<u64>().ok().filter(|usec| *usec > 0)?;
+    Some(Duration::from_micros(usec) / 2)
+}
+
+/// The user service and socket units for running `exe` on `address`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Units {
+    pub service: String,
+    pub socket: String,
+}
+
+impl Units {
+    #[must_use]
+    pub fn new(exe: &Path, address: &Address) -> Self {
+        let listen = match address {
+            Address::Namespaced(name) => format!("@{name}"),
+            Address::Path(path) => path.display().to_string(),
+        };
+        let service = format!(
+            "[Unit]
+Description=roosty clock alarm daemon
+Requires={SOCKET_NAME}
+After={SOCKET_NAME}
+
+[Service]
+Type=notify
+ExecStart=\"{}\" --socket \"{address}\"
+ExecReload=kill -HUP $MAINPID
+WatchdogSec=30
+Restart=on-failure
+
+[Install]
+WantedBy=default.target
+",
+            exe.display()
+        );
+        let socket = format!(
+            "[Unit]
+Description=roosty clock alarm daemon socket
+
+[Socket]
+ListenStream={listen}
+SocketMode=0600
+
+[Install]
+WantedBy=sockets.target
+"
+        );
+        Self { service, socket }
+    }
+
+    /// Writes the units to `dir` and returns where they ended up.
+    ///
+    /// # Errors
+    /// If they couldn't be written.
+    pub fn install(&self, dir: &Path) -> io::Result<(PathBuf, PathBuf)> {
+        std::fs::create_dir_all(dir)?;
+        let service = dir.join(SERVICE_NAME);
+        let socket = dir.join(SOCKET_NAME);
+        std::fs::write(&service, &self.service)?;
+        std::fs::write(&socket, &self.socket)?;
+        Ok((service, socket))
+    }
+
+    /// where systemd looks for user units
+    #[must_use]
+    pub fn user_dir() -> Option<PathBuf> {
+        directories::BaseDirs::new().map(|dirs| dirs.config_dir().join("systemd").join("user"))
+    }
+}
+
+impl fmt::Display for Units {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "# {SERVICE_NAME}\n{}\n# {SOCKET_NAME}\n{}",
+            self.service, self.socket
+        )
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::net::UnixDatagram;
+
+    use super::*;
+
+    #[test]
+    fn inherited_fd_only_for_us() {
+        assert_eq!(inherited_fd(Some("42"), Some("1"), 42), Some(3));
+        assert_eq!(inherited_fd(Some("42"), Some("2"), 42), Some(3));
+        assert_eq!(inherited_fd(Some("41"), Some("1"), 42), None);
+        assert_eq!(inherited_fd(Some("42"), Some("0"), 42), None);
+        assert_eq!(inherited_fd(None, Some("1"), 42), None);
+        assert_eq!(inherited_fd(Some("42"), None, 42), None);
+    }
+
+    #[test]
+    fn watchdog_interval_is_half_the_timeout() {
+        assert_eq!(
+            watchdog_interval(Some("30000000"), None, 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(
+            watchdog_interval(Some("30000000"), Some("42"), 42),
+            Some(Duration::from_secs(15))
+        );
+        assert_eq!(watchdog_interval(Some("30000000"), Some("41"), 42), None);
+        assert_eq!(watchdog_interval(Some("0"), None, 42), None);
+        assert_eq!(watchdog_interval(None, None, 42), None);
+    }
+
+    fn fake_notify_socket(name: &str) -> (UnixDatagram, PathBuf) {
+        let path = env::temp_dir().join(format!("roosty-clockd-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let socket = UnixDatagram::bind(&path).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        (socket, path)
+    }
+
+    fn recv(socket: &UnixDatagram) -> String {
+        let mut buffer = [0; 64];
+        let len = socket.recv(&mut buffer).unwrap();
+        String::from_utf8_lossy(&buffer[..len]).into_owned()
+    }
+
+    #[test]
+    fn notifies_ready_and_stopping() {
+        let (socket, path) = fake_notify_socket("notify");
+        let notifier = Notifier::new(path.to_str().unwrap(), None).unwrap();
+        notifier.ready();
+        assert_eq!(recv(&socket), "READY=1");
+        notifier.stopping();
+        assert_eq!(recv(&socket), "STOPPING=1");
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn pings_watchdog_when_due() {
+        let (socket, path) = fake_notify_socket("watchdog");
+        let mut notifier = Notifier::new(path.to_str().unwrap(), Some(Duration::ZERO)).unwrap();
+        notifier.watchdog();
+        assert_eq!(recv(&socket), "WATCHDOG=1");
+
+        let mut notifier =
+            Notifier::new(path.to_str().unwrap(), Some(Duration::from_secs(60))).unwrap();
+        notifier.watchdog();
+        socket.set_nonblocking(true).unwrap();
+        assert!(socket.recv(&mut [0; 64]).is_err());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn notifies_abstract_socket() {
+        use std::os::linux::net::SocketAddrExt;
+        use std::os::unix::net::SocketAddr;
+        let name = format!("roosty-clockd-test-{}", std::process::id());
+        let socket =
+            UnixDatagram::bind_addr(&SocketAddr::from_abstract_name(&name).unwrap()).unwrap();
+        socket
+            .set_read_timeout(Some(Duration::from_secs(1)))
+            .unwrap();
+        Notifier::new(&format!("@{name}"), None).unwrap().ready();
+        assert_eq!(recv(&socket), "READY=1");
+    }
+
+    #[test]
+    fn socket_unit_listens_on_address() {
+        let units = Units::new(
+            Path::new("/usr/bin/roosty_clockd"),
+            &Address::Path(PathBuf::from("/run/user/1000/roosty-clockd.sock")),
+        );
+        assert!(
+            units
+                .socket
+                .contains("ListenStream=/run/user/1000/roosty-clockd.sock")
+        );
+        assert!(units.service.contains(
+            "ExecStart=\"/usr/bin/roosty_clockd\" --socket \"/run/user/1000/roosty-clockd.sock\""
+        ));
+        let units = Units::new(
+            Path::new("/usr/bin/roosty_clockd"),
+            &Address::Namespaced("roosty-clockd.sock".to_string()),
+        );
+        assert!(units.socket.contains("ListenStream=@roosty-clockd.sock"));
+    }
+}